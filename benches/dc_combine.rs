@@ -0,0 +1,30 @@
+//! Combination of the main DC-net with 50 peers and 1 KiB slots
+//!
+//! Compare `cargo bench` with `cargo bench --features rayon`.
+
+#![feature(test)]
+
+extern crate test;
+extern crate rand;
+extern crate dicemix;
+
+use test::Bencher;
+use rand::{SeedableRng, ChaChaRng};
+
+use dicemix::dc::Randomize;
+use dicemix::dc::xor::{XorVec, combine_slots};
+
+const NUM_PEERS: usize = 50;
+const SLOT_BYTES: usize = 1024;
+
+#[bench]
+fn combine_main(b: &mut Bencher) {
+    let mut rng = ChaChaRng::from_seed([0; 32]);
+    let contributions: Vec<_> = (0..NUM_PEERS).map(|_| {
+        let mut dc_xor = XorVec::from(vec![XorVec::from(vec![0u8; SLOT_BYTES]); NUM_PEERS]);
+        dc_xor.randomize(&mut rng);
+        dc_xor
+    }).collect();
+
+    b.iter(|| combine_slots(&contributions).unwrap());
+}
@@ -1,8 +1,190 @@
 extern crate gcc;
 
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Headers of FLINT included by the solver
+const FLINT_HEADERS: &[&str] = &["flint/flint.h", "flint/fmpz.h", "flint/fmpz_mod_polyxx.h"];
+
+/// Libraries linked by the solver
+const LIBS: &[&str] = &["flint", "gmp"];
+
+/// Returns the directories in a colon-separated search path variable such as `CPATH`.
+fn env_paths(var: &str) -> Vec<PathBuf> {
+    match env::var_os(var) {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => vec![],
+    }
+}
+
+/// The directories in which FLINT has been found, in addition to the default search paths
+#[derive(Default)]
+struct FlintDirs {
+    include: Vec<PathBuf>,
+    lib: Vec<PathBuf>,
+}
+
+/// Returns the directory in an environment variable, which must satisfy `valid`.
+///
+/// The variable is optional, but if it is set, a directory that does not contain what we need
+/// is an error, because the user has most probably made a mistake.
+fn explicit_dir<F: Fn(&Path) -> bool>(var: &str, expected: &str, valid: F) -> Option<PathBuf> {
+    println!("cargo:rerun-if-env-changed={}", var);
+    let dir = PathBuf::from(env::var_os(var)?);
+    if !valid(&dir) {
+        let msg = format!("{} is set to {}, which does not contain {}.", var, dir.display(), expected);
+        println!("cargo:warning={}", msg);
+        panic!("{}", msg);
+    }
+    Some(dir)
+}
+
+/// Asks pkg-config for the directories of FLINT.
+///
+/// Returns `None` if pkg-config is not installed or does not know FLINT.
+fn pkg_config() -> Option<FlintDirs> {
+    let query = |arg: &str, prefix: &str| -> Option<Vec<PathBuf>> {
+        let output = Command::new("pkg-config").args([arg, "flint"]).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let output = String::from_utf8(output.stdout).ok()?;
+        Some(output.split_whitespace()
+            .filter(|flag| flag.starts_with(prefix))
+            .map(|flag| PathBuf::from(&flag[prefix.len()..]))
+            .collect())
+    };
+
+    let mut include = query("--cflags-only-I", "-I")?;
+    // Some versions of FLINT put `<prefix>/include/flint` in the cflags, but we include the
+    // headers as <flint/...>.
+    let parents: Vec<_> = include.iter()
+        .filter(|dir| dir.ends_with("flint"))
+        .filter_map(|dir| dir.parent().map(Path::to_path_buf))
+        .collect();
+    include.extend(parents);
+
+    Some(FlintDirs {
+        include,
+        lib: query("--libs-only-L", "-L")?,
+    })
+}
+
+/// Locates FLINT, trying `FLINT_INCLUDE_DIR` and `FLINT_LIB_DIR` first, then pkg-config.
+///
+/// If neither yields a directory, only the default search paths are used.
+fn flint_dirs() -> FlintDirs {
+    let include = explicit_dir("FLINT_INCLUDE_DIR", "flint/flint.h", |dir| dir.join(FLINT_HEADERS[0]).exists());
+    let lib = explicit_dir("FLINT_LIB_DIR", "the flint library", |dir| has_lib(&[dir.to_path_buf()], "flint"));
+
+    let pkg = match (&include, &lib) {
+        (&Some(_), &Some(_)) => FlintDirs::default(),
+        _ => pkg_config().unwrap_or_default(),
+    };
+    FlintDirs {
+        include: include.map(|dir| vec![dir]).unwrap_or(pkg.include),
+        lib: lib.map(|dir| vec![dir]).unwrap_or(pkg.lib),
+    }
+}
+
+/// Returns the directories in which the compiler looks for headers.
+fn include_dirs(flint: &FlintDirs) -> Vec<PathBuf> {
+    let mut dirs = flint.include.clone();
+    dirs.extend(env_paths("CPLUS_INCLUDE_PATH"));
+    dirs.extend(env_paths("CPATH"));
+    dirs.push(PathBuf::from("/usr/local/include"));
+    dirs.push(PathBuf::from("/usr/include"));
+    dirs
+}
+
+/// Returns the directories in which the linker is likely to look for libraries.
+///
+/// This is a heuristic, because the linker has further sources of search paths, e.g.,
+/// `/etc/ld.so.conf`.
+fn lib_dirs(flint: &FlintDirs) -> Vec<PathBuf> {
+    let mut dirs = flint.lib.clone();
+    dirs.extend(env_paths("LIBRARY_PATH"));
+    // Search paths given to rustc via `-L`
+    if let Ok(flags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+        let mut flags = flags.split('\x1f');
+        while let Some(flag) = flags.next() {
+            let path = if flag == "-L" {
+                flags.next()
+            } else {
+                flag.strip_prefix("-L")
+            };
+            if let Some(path) = path {
+                // Strip a kind such as `native=`.
+                dirs.push(PathBuf::from(path.splitn(2, '=').last().unwrap()));
+            }
+        }
+    }
+    for prefix in &["/usr/local/lib", "/usr/lib", "/usr/lib64"] {
+        dirs.push(PathBuf::from(prefix));
+        if let Ok(target) = env::var("TARGET") {
+            // Debian-style multiarch directories, e.g., /usr/lib/x86_64-linux-gnu
+            let multiarch = target.replace("-unknown-", "-");
+            dirs.push(Path::new(prefix).join(multiarch));
+        }
+    }
+    dirs
+}
+
+fn has_lib(dirs: &[PathBuf], lib: &str) -> bool {
+    dirs.iter().any(|dir| {
+        ["a", "so", "dylib"].iter().any(|ext| dir.join(format!("lib{}.{}", lib, ext)).exists())
+    })
+}
+
+/// Checks that FLINT and GMP are installed before compiling anything against them.
+///
+/// Missing headers are fatal, because the compiler would fail with a less readable error anyway.
+/// Libraries that cannot be found only cause a warning, because the linker may know more
+/// search paths than we do.
+fn probe(flint: &FlintDirs) {
+    let include_dirs = include_dirs(flint);
+    let missing: Vec<_> = FLINT_HEADERS.iter()
+        .filter(|header| !include_dirs.iter().any(|dir| dir.join(header).exists()))
+        .collect();
+    if !missing.is_empty() {
+        let msg = format!("FLINT headers not found: {:?}. The solver of dicemix needs FLINT \
+                           (built with its C++ interface) and GMP. Install them, e.g., with \
+                           `apt install libflint-dev libgmp-dev`, or set FLINT_INCLUDE_DIR to the \
+                           directory that contains flint/flint.h. There is no other solver, but \
+                           the tests do not need it: they can be run without the `flint` feature, \
+                           i.e., with `--no-default-features --features std`.", missing);
+        println!("cargo:warning={}", msg);
+        panic!("{}", msg);
+    }
+
+    let lib_dirs = lib_dirs(flint);
+    for lib in LIBS.iter().filter(|lib| !has_lib(&lib_dirs, lib)) {
+        println!("cargo:warning=lib{} not found in the usual directories, linking may fail. \
+                  Set FLINT_LIB_DIR or add its directory to LIBRARY_PATH if necessary.", lib);
+    }
+}
+
 fn main() {
+    // The solver is compiled only with the `flint` feature, which requires the standard
+    // library. Without it, there is nothing to compile or link.
+    if env::var_os("CARGO_FEATURE_FLINT").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/solver/solver_flint");
+    for var in &["CPLUS_INCLUDE_PATH", "CPATH", "LIBRARY_PATH"] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+    let flint = flint_dirs();
+    probe(&flint);
+
     // Compile the external code
     let mut conf = gcc::Build::new();
+    for dir in flint.include.iter() {
+        conf.include(dir);
+    }
 
     if cfg!(debug_assertions) {
         conf.define("DEBUG", None);
@@ -13,6 +195,10 @@ fn main() {
         .compile("libsolver_flint.a");
 
     // Tell rustc to link against flint and gmp
-    println!("cargo:rustc-link-lib=flint");
-    println!("cargo:rustc-link-lib=gmp");
+    for dir in flint.lib.iter() {
+        println!("cargo:rustc-link-search=native={}", dir.display());
+    }
+    for lib in LIBS {
+        println!("cargo:rustc-link-lib={}", lib);
+    }
 }
@@ -0,0 +1,7 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate dicemix;
+
+fuzz_target!(|data: &[u8]| {
+    dicemix::fuzz::read_authenticated_payloads(data);
+});
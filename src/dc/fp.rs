@@ -1,10 +1,11 @@
-use std::ops::{Neg, Add, AddAssign, Sub, SubAssign, Mul, MulAssign};
-use std::cmp::Ordering;
+use core::ops::{Neg, Add, AddAssign, Sub, SubAssign, Mul, MulAssign};
+use core::cmp::Ordering;
+use alloc::vec::Vec;
 use rand::Rng;
 use rand::distributions::{Standard, Distribution};
 use serde::{Serialize, Deserialize};
 
-use super::Randomize;
+use super::{Randomize, DcZero};
 
 // The field size.
 const P: u128 = (1 << 127) - 1;
@@ -16,13 +17,19 @@ const P: u128 = (1 << 127) - 1;
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Fp(u128);
 
+// Field elements are serialized as 16 little-endian bytes, because not all serde formats
+// support u128 natively.
+//
+// The encoding is canonical: zero is always serialized as 0, never as P, and deserialization
+// rejects every value >= P. Otherwise a malicious peer could send the same element in two
+// encodings, e.g., to make honest peers disagree about a signed or hashed message.
 impl Serialize for Fp {
     #[inline]
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: ::serde::Serializer
     {
         let u = u128::from(*self);
-        serializer.serialize_u128(u)
+        u.to_le_bytes().serialize(serializer)
     }
 }
 
@@ -33,27 +40,13 @@ impl<'de> Deserialize<'de> for Fp {
     {
         use serde::de;
 
-        struct Visitor;
-        impl<'de> ::serde::de::Visitor<'de> for Visitor {
-            type Value = Fp;
-
-            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-                formatter.write_str("a u128 x such that 0 <= x < p where p = 2**127 - 1")
-            }
-
-            fn visit_u128<E>(self, x: u128) -> Result<Fp, E>
-                where E: de::Error
-            {
-                if x >= P {
-                    let unexp_str = format!("the u128 with value {:x} >= 2**127 - 1", x);
-                    let unexp = de::Unexpected::Other(&unexp_str);
-                    return Err(de::Error::invalid_value(unexp, &self))
-                }
-                Ok(Fp::from_u127(x))
-            }
+        let x = u128::from_le_bytes(<[u8; 16]>::deserialize(deserializer)?);
+        if x >= P {
+            let unexp_str = format!("the u128 with value {:x} >= 2**127 - 1", x);
+            let unexp = de::Unexpected::Other(&unexp_str);
+            return Err(de::Error::invalid_value(unexp, &"a u128 x such that 0 <= x < p where p = 2**127 - 1"));
         }
-
-        deserializer.deserialize_u128(Visitor { })
+        Ok(Fp::from_u127(x))
     }
 }
 
@@ -91,6 +84,9 @@ impl Reduce for (u128, u128) {
 }
 
 impl Fp {
+    /// The order of the field, i.e., the prime 2**127 - 1
+    pub const MODULUS: u128 = P;
+
     #[inline]
     pub fn from_u127(x: u128) -> Self {
         // x == P is explicitly allowed.
@@ -100,6 +96,14 @@ impl Fp {
         Fp(x)
     }
 
+    /// Creates a field element from a small integer, which is always in range.
+    ///
+    /// Unlike `From<u64>`, this can be used in constants.
+    #[inline]
+    pub const fn from_u64(x: u64) -> Self {
+        Fp(x as u128)
+    }
+
     #[inline]
     pub fn from_u128_discard_msb(x: u128) -> Self {
         Self::from_u127(x & P)
@@ -107,7 +111,70 @@ impl Fp {
 
     #[inline]
     pub fn prime() -> u128 {
-        P
+        Self::MODULUS
+    }
+
+    /// Raises the element to the power of `exp` by square-and-multiply.
+    pub fn pow(self, mut exp: u64) -> Self {
+        let mut base = self;
+        let mut result = Fp(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            exp >>= 1;
+        }
+        result
+    }
+
+    /// The number of bits available to `pack()`
+    pub const PACK_BITS: u32 = 127;
+
+    /// Packs several fields, given as `(value, width)` pairs, into a single field element.
+    ///
+    /// The first field occupies the least significant bits, and every further field the bits
+    /// above the previous one. Returns `None` if a value does not fit in its width, if the widths
+    /// exceed `PACK_BITS` in total, or if all 127 bits are set, because that integer is the
+    /// modulus and thus not a canonical element.
+    pub fn pack(parts: &[(u128, u32)]) -> Option<Self> {
+        let mut x = 0u128;
+        let mut offset = 0u32;
+        for &(value, width) in parts {
+            let end = offset.checked_add(width)?;
+            // Checking the end first ensures that the shifts do not overflow.
+            if end > Self::PACK_BITS || value >> width != 0 {
+                return None;
+            }
+            x |= value << offset;
+            offset = end;
+        }
+        if x == P {
+            return None;
+        }
+        Some(Fp(x))
+    }
+
+    /// Unpacks the fields of the given widths, which must be laid out as by `pack()`.
+    ///
+    /// Returns `None` if the widths exceed `PACK_BITS` in total or if bits above the last field
+    /// are set, in which case the element has not been packed with these widths.
+    pub fn unpack(self, widths: &[u32]) -> Option<Vec<u128>> {
+        let mut x = u128::from(self);
+        let mut total = 0u32;
+        let mut parts = Vec::with_capacity(widths.len());
+        for &width in widths {
+            total = total.checked_add(width)?;
+            if total > Self::PACK_BITS {
+                return None;
+            }
+            parts.push(x & ((1 << width) - 1));
+            x >>= width;
+        }
+        if x != 0 {
+            return None;
+        }
+        Some(parts)
     }
 }
 
@@ -118,10 +185,26 @@ impl From<Fp> for u128 {
     }
 }
 
+impl From<u64> for Fp {
+    #[inline]
+    fn from(x: u64) -> Fp {
+        Fp::from_u64(x)
+    }
+}
+
+impl From<u32> for Fp {
+    #[inline]
+    fn from(x: u32) -> Fp {
+        Fp::from_u64(x as u64)
+    }
+}
+
 impl Distribution<Fp> for Standard {
     #[inline]
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Fp {
-        Fp::from_u128_discard_msb(rng.gen::<u128>())
+        let x = Fp::from_u128_discard_msb(rng.gen::<u128>());
+        debug_assert!(x.0 <= P);
+        x
     }
 
 }
@@ -134,6 +217,13 @@ impl Randomize for Fp {
 
 }
 
+impl DcZero for Fp {
+    #[inline]
+    fn dc_zero(_: &Self) -> Self {
+        Fp(0)
+    }
+}
+
 impl Neg for Fp {
     type Output = Self;
     #[inline]
@@ -157,6 +247,13 @@ impl AddAssign for Fp {
     }
 }
 
+impl<'a> AddAssign<&'a Fp> for Fp {
+    #[inline]
+    fn add_assign(&mut self, other: &'a Fp) {
+        *self = *self + *other
+    }
+}
+
 impl Sub for Fp {
     type Output = Self;
     #[inline]
@@ -172,6 +269,13 @@ impl SubAssign for Fp {
     }
 }
 
+impl<'a> SubAssign<&'a Fp> for Fp {
+    #[inline]
+    fn sub_assign(&mut self, other: &'a Fp) {
+        *self = *self - *other
+    }
+}
+
 impl Mul for Fp {
     type Output = Self;
     #[inline]
@@ -249,6 +353,59 @@ mod tests {
         assert_eq!(Fp(4) - Fp(8), Fp(P - 4));
     }
 
+    #[test]
+    fn assign_by_ref() {
+        let xs = [Fp(7), Fp(P - 2), Fp(75661398932549814984099328258351945610)];
+        for a in xs.iter() {
+            for b in xs.iter() {
+                let (mut x, mut y) = (*a, *a);
+                x += b;
+                y += *b;
+                assert_eq!(x, y);
+                x -= b;
+                y -= *b;
+                assert_eq!(x, y);
+                assert_eq!(x, *a);
+            }
+        }
+    }
+
+    #[test]
+    fn random_in_range() {
+        use rand::{SeedableRng, ChaChaRng};
+
+        assert_eq!(Fp::prime(), Fp::MODULUS);
+        let mut rng = ChaChaRng::from_seed([0x54; 32]);
+        for _ in 0..10000 {
+            let x: Fp = rng.gen();
+            assert!(u128::from(x) < Fp::MODULUS);
+        }
+        // The non-canonical representation of zero is mapped to zero.
+        assert_eq!(u128::from(Fp::from_u128_discard_msb(u128::max_value())), 0);
+    }
+
+    #[test]
+    fn group_laws() {
+        use rand::{SeedableRng, ChaChaRng};
+        use dc::tests::assert_group_laws;
+
+        let mut rng = ChaChaRng::from_seed([0x55; 32]);
+        for _ in 0..16 {
+            let (a, b): (Fp, Fp) = (rng.gen(), rng.gen());
+            assert_group_laws(&a, &b);
+        }
+        // Both representations of zero
+        assert_group_laws(&Fp(P), &Fp(0));
+        assert_group_laws(&Fp(5), &Fp(P));
+    }
+
+    #[test]
+    fn zero() {
+        let x = Fp(75661398932549814984099328258351945610);
+        assert_eq!(Fp::dc_zero(&x) + x, x);
+        assert_eq!(Fp::dc_zero(&x), Fp::from_u127(0));
+    }
+
     #[test]
     fn mul() {
         assert_eq!(Fp(4) * Fp(3), Fp(12));
@@ -264,6 +421,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn from_small() {
+        const FIVE: Fp = Fp::from_u64(5);
+        assert_eq!(Fp::from(12u64), Fp::from_u127(12));
+        assert_eq!(Fp::from(12u32), Fp::from(12u64));
+        assert_eq!(Fp::from(u64::max_value()), Fp::from_u127(u64::max_value() as u128));
+        assert_eq!(Fp::from(7u32) + FIVE, Fp::from(12u64));
+        assert_eq!(Fp::from(4u32) - FIVE, -Fp::from(1u32));
+        assert_eq!(FIVE * Fp::from(3u64), Fp::from(15u32));
+    }
+
+    #[test]
+    fn pack() {
+        // A 96-bit hash of an output address and a 31-bit index fill the whole element.
+        let hash = 0x0123_4567_89ab_cdef_fedc_ba98u128;
+        let index = (1 << 31) - 2;
+        let x = Fp::pack(&[(hash, 96), (index, 31)]).unwrap();
+        assert_eq!(u128::from(x), hash | index << 96);
+        assert_eq!(x.unpack(&[96, 31]), Some(vec![hash, index]));
+
+        let x = Fp::pack(&[(5, 3), (0, 0), (1, 1)]).unwrap();
+        assert_eq!(x, Fp(13));
+        assert_eq!(x.unpack(&[3, 0, 1]), Some(vec![5, 0, 1]));
+        assert_eq!(Fp::pack(&[]), Some(Fp(0)));
+        assert_eq!(Fp(0).unpack(&[]), Some(vec![]));
+        assert_eq!(Fp::pack(&[(0, 127), (0, 0)]), Some(Fp(0)));
+    }
+
+    #[test]
+    fn pack_overflow() {
+        assert_eq!(Fp::pack(&[(0, 96), (0, 32)]), None);
+        assert_eq!(Fp::pack(&[(0, 128)]), None);
+        assert_eq!(Fp::pack(&[(0, u32::max_value()), (0, 1)]), None);
+        assert_eq!(Fp::pack(&[(8, 3)]), None);
+        assert_eq!(Fp::pack(&[(1 << 127, 127)]), None);
+        // All bits set is the modulus, i.e., zero.
+        assert_eq!(Fp::pack(&[(P >> 31, 96), ((1 << 31) - 1, 31)]), None);
+
+        assert_eq!(Fp(0).unpack(&[96, 32]), None);
+        assert_eq!(Fp(0).unpack(&[u32::max_value(), 1]), None);
+        // Bits above the last field are set.
+        assert_eq!(Fp(8).unpack(&[3]), None);
+        assert_eq!(Fp(8).unpack(&[4]), Some(vec![8]));
+    }
+
+    #[test]
+    fn pow() {
+        assert_eq!(Fp(3).pow(0), Fp(1));
+        assert_eq!(Fp(3).pow(5), Fp(243));
+        assert_eq!(Fp(0).pow(0), Fp(1));
+        assert_eq!(Fp(P).pow(7), Fp(0));
+        let a = Fp(0x1234_5678_9abc_def0_0fed_cba9_8765_4321);
+        let k = 0x8000_0000_0000_0001;
+        assert_eq!(a.pow(k) * a, a.pow(k + 1));
+        assert_eq!(a.pow(k / 2) * a.pow(k / 2) * a, a.pow(k));
+    }
+
     #[test]
     fn eq() {
         assert_eq!(Fp(0), Fp(P));
@@ -293,4 +507,108 @@ mod tests {
         a *= Fp(2);
         assert_eq!(a, Fp(30));
     }
+
+    #[test]
+    fn serde() {
+        use bincode;
+
+        let x = Fp(P - 1);
+        let ser = bincode::serialize(&x, bincode::Infinite).unwrap();
+        assert_eq!(ser.len(), 16);
+        assert_eq!(bincode::deserialize::<Fp>(&ser).unwrap(), x);
+
+        let ser = bincode::serialize(&Fp::prime().to_le_bytes(), bincode::Infinite).unwrap();
+        assert!(bincode::deserialize::<Fp>(&ser).is_err());
+    }
+
+    #[test]
+    fn serde_canonical() {
+        use bincode;
+
+        // Both internal representations of zero have the same encoding, which round-trips.
+        let zero = bincode::serialize(&Fp(0), bincode::Infinite).unwrap();
+        assert_eq!(zero, [0u8; 16]);
+        assert_eq!(bincode::serialize(&Fp(P), bincode::Infinite).unwrap(), zero);
+        assert_eq!(bincode::deserialize::<Fp>(&zero).unwrap().0, 0);
+
+        // Encoding zero as P and all values above P are rejected.
+        for &x in [P, P + 1, 1 << 127 | 5, u128::max_value()].iter() {
+            let ser = bincode::serialize(&x.to_le_bytes(), bincode::Infinite).unwrap();
+            assert!(bincode::deserialize::<Fp>(&ser).is_err(), "accepted {:x}", x);
+        }
+    }
+
+    mod properties {
+        use quickcheck::{Arbitrary, Gen};
+
+        use super::*;
+
+        impl Arbitrary for Fp {
+            fn arbitrary<G: Gen>(g: &mut G) -> Self {
+                // Reductions happen for values near P (including P itself, which represents
+                // zero), so we generate such values more often than uniform sampling would.
+                match g.gen_range(0, 4) {
+                    0 => Fp(P - g.gen_range(0, 1 << 16)),
+                    1 => Fp(g.gen_range(0, 1 << 16)),
+                    _ => g.gen(),
+                }
+            }
+        }
+
+        // Reference multiplication by double-and-add, which never overflows because all
+        // intermediate values are smaller than 2 * P.
+        fn mul_ref(a: u128, b: u128) -> u128 {
+            let add = |x: u128, y: u128| (x + y) % P;
+            (0..127).rev().fold(0, |acc, i| {
+                let acc = add(acc, acc);
+                if (b >> i) & 1 == 1 { add(acc, a) } else { acc }
+            })
+        }
+
+        fn consistent(x: Fp) -> bool {
+            x.0 <= P
+        }
+
+        quickcheck! {
+            fn add_associative(a: Fp, b: Fp, c: Fp) -> bool {
+                (a + b) + c == a + (b + c)
+            }
+
+            fn add_commutative(a: Fp, b: Fp) -> bool {
+                a + b == b + a && consistent(a + b)
+            }
+
+            fn mul_associative(a: Fp, b: Fp, c: Fp) -> bool {
+                (a * b) * c == a * (b * c)
+            }
+
+            fn mul_commutative(a: Fp, b: Fp) -> bool {
+                a * b == b * a && consistent(a * b)
+            }
+
+            fn distributive(a: Fp, b: Fp, c: Fp) -> bool {
+                a * (b + c) == a * b + a * c
+            }
+
+            fn identities(a: Fp) -> bool {
+                a + Fp(0) == a && a + Fp(P) == a && a * Fp(1) == a && a * Fp(P) == Fp(0)
+            }
+
+            fn additive_inverse(a: Fp, b: Fp) -> bool {
+                a + -a == Fp(0) && a - b == a + -b && consistent(-a) && consistent(a - b)
+            }
+
+            fn mul_matches_reference(a: Fp, b: Fp) -> bool {
+                u128::from(a * b) == mul_ref(u128::from(a), u128::from(b))
+            }
+
+            fn discard_msb_idempotent(a: Fp, msb: bool) -> bool {
+                // The internal representation covers all values up to P inclusive.
+                let x = a.0 | (msb as u128) << 127;
+                let y = u128::from(Fp::from_u128_discard_msb(x));
+                let canonical = x & P != P;
+                u128::from(Fp::from_u128_discard_msb(y)) == y && (!canonical || y == x & P)
+            }
+        }
+    }
 }
@@ -1,7 +1,9 @@
+use core::ops::AddAssign;
 use rand::Rng;
 
 pub mod xor;
 pub mod fp;
+pub mod scalar;
 
 // TODO https://github.com/rust-lang/rust/issues/41517
 // trait DcGroup = Add + AddAssign + Sub + SubAssign + Neg + Randomize;
@@ -15,3 +17,102 @@ pub mod fp;
 pub trait Randomize {
     fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R);
 }
+
+/// Trait for types whose additive identity depends on the structure of a value.
+///
+/// Like `Randomize`, this preserves the structure of the template, so for example the identity of
+/// a vector is an all-zero vector of the same length as the template.
+pub trait DcZero {
+    fn dc_zero(template: &Self) -> Self;
+}
+
+/// Checks that a set of pads cancels out, i.e., that the pads sum to zero.
+///
+/// This is meant for tests and debug assertions, e.g., with the pads that all peers of a DC-net
+/// apply with the signs given by `pad_sign()`. Pads of different structure, e.g., vectors of
+/// different lengths, never cancel out. An empty set cancels out trivially.
+pub fn dc_pads_cancel<T>(pads: &[T]) -> bool
+    where T: Clone + PartialEq + DcZero + AddAssign
+{
+    let zero = match pads.first() {
+        Some(first) => T::dc_zero(first),
+        None => return true,
+    };
+    // Equal identities imply equal structure, so the sum below is well-defined.
+    if pads.iter().any(|pad| T::dc_zero(pad) != zero) {
+        return false;
+    }
+
+    let mut sum = zero.clone();
+    for pad in pads {
+        sum += pad.clone();
+    }
+    sum == zero
+}
+
+#[cfg(test)]
+pub mod tests {
+    use core::fmt::Debug;
+    use core::ops::{Add, Sub, Neg};
+    use rand::{SeedableRng, ChaChaRng};
+
+    use super::*;
+    use super::fp::Fp;
+    use super::scalar::Scalar;
+    use super::xor::XorVec;
+
+    /// Checks the group laws that the DC-nets rely on for the pads to cancel out.
+    ///
+    /// Every type used in a DC-net should call this with a few random elements of equal
+    /// structure, so that the laws are verified uniformly for all of them.
+    pub fn assert_group_laws<T>(a: &T, b: &T)
+        where T: Clone + PartialEq + Debug + DcZero + Add<Output = T> + Sub<Output = T> + Neg<Output = T>
+    {
+        let zero = T::dc_zero(a);
+        assert_eq!(a.clone() + zero.clone(), *a);
+        assert_eq!(a.clone() - a.clone(), zero);
+        assert_eq!(a.clone() + (-a.clone()), zero);
+        assert_eq!((a.clone() + b.clone()) - b.clone(), *a);
+        assert_eq!(a.clone() - b.clone(), a.clone() + (-b.clone()));
+        assert_eq!(a.clone() + b.clone(), b.clone() + a.clone());
+    }
+
+    // Returns a pad set in which every pad is added by one peer and subtracted by another one.
+    fn signed_pads<T>(pads: &[T]) -> Vec<T>
+        where T: Clone + Neg<Output = T>
+    {
+        pads.iter().cloned().chain(pads.iter().map(|pad| -pad.clone())).collect()
+    }
+
+    #[test]
+    fn pads_cancel() {
+        let mut rng = ChaChaRng::from_seed([0x92; 32]);
+        let fps: Vec<Fp> = (0..3).map(|_| rng.gen()).collect();
+        let scalars: Vec<Scalar> = (0..3).map(|_| rng.gen()).collect();
+        let xors: Vec<XorVec<u8>> = (0..3).map(|_| {
+            let mut pad = XorVec::from(vec![0u8; 16]);
+            pad.randomize(&mut rng);
+            pad
+        }).collect();
+
+        assert!(dc_pads_cancel(&signed_pads(&fps)));
+        assert!(dc_pads_cancel(&signed_pads(&scalars)));
+        assert!(dc_pads_cancel(&signed_pads(&xors)));
+        assert!(dc_pads_cancel::<Fp>(&[]));
+
+        // A tampered pad does not cancel out.
+        let mut tampered = signed_pads(&fps);
+        tampered[1] += Fp::from_u64(1);
+        assert!(!dc_pads_cancel(&tampered));
+        let mut tampered = signed_pads(&scalars);
+        tampered.pop();
+        assert!(!dc_pads_cancel(&tampered));
+        let mut tampered = signed_pads(&xors);
+        tampered[4] = XorVec::from(vec![0u8; 16]);
+        assert!(!dc_pads_cancel(&tampered));
+
+        // Pads of different lengths never cancel out, even if they are zero.
+        let zeros = [XorVec::from(vec![0u8; 16]), XorVec::from(vec![0u8; 15])];
+        assert!(!dc_pads_cancel(&zeros));
+    }
+}
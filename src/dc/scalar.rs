@@ -0,0 +1,316 @@
+use core::ops::{Neg, Add, AddAssign, Sub, SubAssign};
+use core::cmp::Ordering;
+use alloc::vec::Vec;
+use rand::Rng;
+use rand::distributions::{Standard, Distribution};
+use serde::{Serialize, Deserialize};
+
+use super::{Randomize, DcZero};
+
+// The order of the secp256k1 group as little-endian 64-bit limbs.
+const N: [u64; 4] = [
+    0xBFD25E8CD0364141, 0xBAAEDCE6AF48A03B, 0xFFFFFFFFFFFFFFFE, 0xFFFFFFFFFFFFFFFF,
+];
+
+// A scalar of the secp256k1 group, i.e., an integer modulo the group order.
+//
+// It is consistent iff self.0 < N (as little-endian 64-bit limbs).
+// In contrast to secp256k1::key::SecretKey, zero is a valid scalar.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Scalar([u64; 4]);
+
+#[inline]
+fn cmp_limbs(a: &[u64; 4], b: &[u64; 4]) -> Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+// Computes a - b, returning the borrow.
+#[inline]
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut r = [0u64; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d1, b1) = a[i].overflowing_sub(b[i]);
+        let (d2, b2) = d1.overflowing_sub(borrow as u64);
+        r[i] = d2;
+        borrow = b1 || b2;
+    }
+    (r, borrow)
+}
+
+impl Scalar {
+    /// Creates a scalar from its 32-byte big-endian encoding.
+    ///
+    /// Returns `None` if the encoded integer is not smaller than the group order.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            for &b in &bytes[(3 - i) * 8..(4 - i) * 8] {
+                *limb = (*limb << 8) | b as u64;
+            }
+        }
+        if cmp_limbs(&limbs, &N) == Ordering::Less {
+            Some(Scalar(limbs))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the 32-byte big-endian encoding of the scalar.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            for j in 0..8 {
+                bytes[(3 - i) * 8 + j] = (limb >> (56 - 8 * j)) as u8;
+            }
+        }
+        bytes
+    }
+}
+
+impl Serialize for Scalar {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ::serde::Serializer
+    {
+        self.to_bytes().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scalar {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Scalar, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        use serde::de;
+
+        let bytes = <[u8; 32]>::deserialize(deserializer)?;
+        Scalar::from_bytes(&bytes).ok_or_else(|| {
+            let unexp = de::Unexpected::Other("an integer not smaller than the group order");
+            de::Error::invalid_value(unexp, &"a secp256k1 scalar")
+        })
+    }
+}
+
+impl Distribution<Scalar> for Standard {
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Scalar {
+        // Rejection sampling, the probability of rejection is about 2**-128.
+        loop {
+            let mut bytes = [0u8; 32];
+            rng.fill_bytes(&mut bytes);
+            if let Some(x) = Scalar::from_bytes(&bytes) {
+                return x;
+            }
+        }
+    }
+}
+
+impl Randomize for Scalar {
+    #[inline]
+    fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        *self = rng.gen::<Scalar>();
+    }
+}
+
+impl DcZero for Scalar {
+    #[inline]
+    fn dc_zero(_: &Self) -> Self {
+        Scalar::default()
+    }
+}
+
+impl Neg for Scalar {
+    type Output = Self;
+    #[inline]
+    fn neg(self) -> Self {
+        if self.0 == [0; 4] {
+            self
+        } else {
+            Scalar(sub_limbs(&N, &self.0).0)
+        }
+    }
+}
+
+impl Add for Scalar {
+    type Output = Self;
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        let mut r = [0u64; 4];
+        let mut carry = false;
+        for i in 0..4 {
+            let (s1, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (s2, c2) = s1.overflowing_add(carry as u64);
+            r[i] = s2;
+            carry = c1 || c2;
+        }
+
+        // Both summands are smaller than N, so subtracting N once is sufficient.
+        if carry || cmp_limbs(&r, &N) != Ordering::Less {
+            r = sub_limbs(&r, &N).0;
+        }
+        Scalar(r)
+    }
+}
+
+impl AddAssign for Scalar {
+    #[inline]
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other
+    }
+}
+
+impl Sub for Scalar {
+    type Output = Self;
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        self + (-other)
+    }
+}
+
+impl SubAssign for Scalar {
+    #[inline]
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other
+    }
+}
+
+/// Combines the DC-net contributions of several peers elementwise.
+///
+/// Returns `None` if the contributions differ in length.
+pub fn combine(contributions: &[Vec<Scalar>]) -> Option<Vec<Scalar>> {
+    let len = contributions.first().map_or(0, Vec::len);
+    if contributions.iter().any(|c| c.len() != len) {
+        return None;
+    }
+
+    let mut sum = vec![Scalar::default(); len];
+    for contribution in contributions {
+        for (acc, &x) in sum.iter_mut().zip(contribution.iter()) {
+            *acc += x;
+        }
+    }
+    Some(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, ChaChaRng};
+
+    use dc::tests::assert_group_laws;
+    use super::*;
+
+    fn scalar(x: u64) -> Scalar {
+        Scalar([x, 0, 0, 0])
+    }
+
+    #[test]
+    fn add_wraps() {
+        let minus_one = -scalar(1);
+        assert_eq!(minus_one.0, [N[0] - 1, N[1], N[2], N[3]]);
+        assert_eq!(minus_one + scalar(1), scalar(0));
+        assert_eq!(minus_one + scalar(3), scalar(2));
+        assert_eq!(-scalar(0), scalar(0));
+    }
+
+    fn from_hex(hex: &str) -> Scalar {
+        let hex = format!("{:0>64}", hex);
+        let mut bytes = [0u8; 32];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        Scalar::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn reference_arithmetic() {
+        // (a, b, a + b, a - b, -a) modulo the group order, computed with Python integers
+        let vectors = [
+            ("a6d35aa084cb655e172d765172f44c5bd44c00c07e7a9169f093b1ead28ec954",
+             "5c09cb900df913dfc705ea2b15eeb44afd7925ac49d5a1161d21623070d5378e",
+             "2dd263092c4793dde33607c88e300a817164986190792444de2b58e732dbfa1",
+             "4ac98f1076d2517e50278c265d059810d6d2db1434a4f053d3724fba61b991c6",
+             "592ca55f7b349aa1e8d289ae8d0bb3a2e662dc2630ce0ed1cf3eaca1fda777ed"),
+            ("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140",
+             "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140",
+             "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd036413f",
+             "0",
+             "1"),
+            ("5",
+             "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd036413e",
+             "2",
+             "8",
+             "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd036413c"),
+            ("0",
+             "7",
+             "7",
+             "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd036413a",
+             "0"),
+        ];
+        for &(a, b, sum, difference, negation) in vectors.iter() {
+            let (a, b) = (from_hex(a), from_hex(b));
+            assert_eq!(a + b, from_hex(sum));
+            assert_eq!(a - b, from_hex(difference));
+            assert_eq!(-a, from_hex(negation));
+
+            let mut x = a;
+            x += b;
+            x -= b;
+            assert_eq!(x, a);
+            x -= a;
+            assert_eq!(x, Scalar::default());
+        }
+    }
+
+    #[test]
+    fn group_laws() {
+        let mut rng = ChaChaRng::from_seed([8; 32]);
+        for _ in 0..16 {
+            let (a, b): (Scalar, Scalar) = (rng.gen(), rng.gen());
+            assert_group_laws(&a, &b);
+        }
+        assert_group_laws(&scalar(0), &-scalar(1));
+    }
+
+    #[test]
+    fn zero() {
+        for &x in [scalar(0), scalar(7), -scalar(1)].iter() {
+            assert_eq!(Scalar::dc_zero(&x) + x, x);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let mut bytes = [0u8; 32];
+        bytes[31] = 5;
+        bytes[0] = 0x80;
+        let x = Scalar::from_bytes(&bytes).unwrap();
+        assert_eq!(x.0, [5, 0, 0, 0x8000000000000000]);
+        assert_eq!(x.to_bytes(), bytes);
+        assert!(Scalar::from_bytes(&[0xff; 32]).is_none());
+        assert!(Scalar::from_bytes(&(-scalar(1)).to_bytes()).is_some());
+    }
+
+    #[test]
+    fn pads_cancel() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let num_peers = 3;
+        let len = 4;
+        let msgs: Vec<Vec<Scalar>> = (0..num_peers).map(|i| vec![scalar(i as u64 + 1); len]).collect();
+
+        let mut contributions = msgs.clone();
+        for i in 0..num_peers {
+            for j in (i + 1)..num_peers {
+                let pads: Vec<Scalar> = (0..len).map(|_| rng.gen()).collect();
+                for k in 0..len {
+                    contributions[i][k] += pads[k];
+                    contributions[j][k] -= pads[k];
+                }
+            }
+        }
+
+        assert_eq!(combine(&contributions), combine(&msgs));
+        assert_eq!(combine(&msgs).unwrap(), vec![scalar(6); len]);
+        assert_eq!(combine(&[vec![scalar(1)], vec![]]), None);
+    }
+}
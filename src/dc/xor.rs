@@ -1,13 +1,127 @@
-use std::ops::{BitXor, BitXorAssign, Add, AddAssign, Sub, SubAssign, Neg};
-use std::iter::FromIterator;
+use core::ops::{BitXor, BitXorAssign, Add, AddAssign, Sub, SubAssign, Neg, Deref};
+use core::iter::FromIterator;
+use core::fmt;
+use alloc::vec::Vec;
 use rand::Rng;
 use rand::distributions::Standard;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
 
-use super::Randomize;
+use super::{Randomize, DcZero};
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct XorVec<T>(Vec<T>);
 
+/// Trait for element types of an `XorVec`, which determine how the vector is serialized.
+///
+/// Vectors of bytes are serialized as a single byte blob, which is faster than serializing the
+/// bytes one by one. With bincode, this does not make the encoding any smaller: a byte blob is
+/// encoded like a sequence of bytes. Vectors of other types are serialized as the derived
+/// implementation of serde would serialize them.
+pub trait XorElement: Sized {
+    fn serialize_vec<S: Serializer>(vec: &Vec<Self>, serializer: S) -> Result<S::Ok, S::Error>;
+    fn deserialize_vec<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Self>, D::Error>;
+}
+
+impl XorElement for u8 {
+    fn serialize_vec<S: Serializer>(vec: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(vec)
+    }
+
+    fn deserialize_vec<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a byte array")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+
+            // Some formats cannot distinguish byte arrays from sequences.
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut vec = Vec::new();
+                while let Some(b) = seq.next_element()? {
+                    vec.push(b);
+                }
+                Ok(vec)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(Visitor)
+    }
+}
+
+impl<T: XorElement> XorElement for XorVec<T> {
+    fn serialize_vec<S: Serializer>(vec: &Vec<Self>, serializer: S) -> Result<S::Ok, S::Error> {
+        vec.serialize(serializer)
+    }
+
+    fn deserialize_vec<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Self>, D::Error> {
+        Vec::deserialize(deserializer)
+    }
+}
+
+impl<T: XorElement> Serialize for XorVec<T> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct("XorVec", &SerializeVec(&self.0))
+    }
+}
+
+impl<'de, T: XorElement> Deserialize<'de> for XorVec<T> {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor<T>(::core::marker::PhantomData<T>);
+        impl<'de, T: XorElement> de::Visitor<'de> for Visitor<T> {
+            type Value = XorVec<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("tuple struct XorVec")
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<XorVec<T>, D::Error> {
+                T::deserialize_vec(deserializer).map(XorVec)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct("XorVec", Visitor(::core::marker::PhantomData))
+    }
+}
+
+// Adapter to serialize a vector of elements by `XorElement::serialize_vec()`
+struct SerializeVec<'a, T: 'a>(&'a Vec<T>);
+
+impl<'a, T: XorElement> Serialize for SerializeVec<'a, T> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        T::serialize_vec(self.0, serializer)
+    }
+}
+
+impl<T> From<Vec<T>> for XorVec<T> {
+    #[inline]
+    fn from(vec: Vec<T>) -> Self {
+        XorVec(vec)
+    }
+}
+
+impl<T> Deref for XorVec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
 impl<T> BitXor for XorVec<T>
 where
     T: BitXor,
@@ -45,6 +159,19 @@ where
     }
 }
 
+impl<'a, T> BitXorAssign<&'a XorVec<T>> for XorVec<T>
+where
+    T: BitXorAssign<&'a T>,
+{
+    #[inline]
+    fn bitxor_assign(&mut self, rhs: &'a XorVec<T>) {
+        debug_assert_eq!(self.0.len(), rhs.0.len());
+        for (a, b) in self.0.iter_mut().zip(rhs.0.iter()) {
+            T::bitxor_assign(a, b);
+        }
+    }
+}
+
 impl<T> Add for XorVec<T>
 where
     T: BitXor,
@@ -68,6 +195,16 @@ where
     }
 }
 
+impl<'a, T> AddAssign<&'a XorVec<T>> for XorVec<T>
+where
+    T: BitXorAssign<&'a T>,
+{
+    #[inline]
+    fn add_assign(&mut self, rhs: &'a XorVec<T>) {
+        Self::bitxor_assign(self, rhs)
+    }
+}
+
 impl<T> Sub for XorVec<T>
 where
     T: BitXor,
@@ -91,6 +228,17 @@ where
     }
 }
 
+impl<'a, T> SubAssign<&'a XorVec<T>> for XorVec<T>
+where
+    T: BitXorAssign<&'a T>,
+{
+    #[inline]
+    fn sub_assign(&mut self, rhs: &'a XorVec<T>) {
+        Self::bitxor_assign(self, rhs)
+    }
+}
+
+// Every element of an XOR group is its own inverse, so this is not a mistake.
 impl<T> Neg for XorVec<T> {
     type Output = Self;
 
@@ -134,8 +282,146 @@ impl<T> Randomize for Vec<T> where T: Randomize {
 //     }
 // }
 
+impl DcZero for u8 {
+    fn dc_zero(_: &Self) -> Self {
+        0
+    }
+}
+
+impl<T> DcZero for Vec<T> where T: DcZero {
+    fn dc_zero(template: &Self) -> Self {
+        template.iter().map(T::dc_zero).collect()
+    }
+}
+
+impl<T> DcZero for XorVec<T> where T: DcZero {
+    fn dc_zero(template: &Self) -> Self {
+        XorVec(Vec::dc_zero(&template.0))
+    }
+}
+
 impl<T> Randomize for XorVec<T> where T: Randomize {
     fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         self.0.randomize(rng);
     }
 }
+
+/// Combines the main DC-net contributions of several peers slot by slot.
+///
+/// Returns `None` if the contributions differ in the number of slots or in the size of a slot.
+/// With the `rayon` feature, the slots are combined in parallel.
+pub fn combine_slots(contributions: &[XorVec<XorVec<u8>>]) -> Option<XorVec<XorVec<u8>>> {
+    let sizes: Vec<usize> = contributions.first().map_or(vec![], |c| c.iter().map(|slot| slot.len()).collect());
+    let same_shape = |c: &XorVec<XorVec<u8>>| {
+        c.len() == sizes.len() && c.iter().zip(sizes.iter()).all(|(slot, &size)| slot.len() == size)
+    };
+    if !contributions.iter().all(same_shape) {
+        return None;
+    }
+
+    let combine_slot = |i: usize| {
+        let mut sum = XorVec::dc_zero(&contributions[0][i]);
+        for contribution in contributions {
+            sum += &contribution[i];
+        }
+        sum
+    };
+
+    #[cfg(feature = "rayon")]
+    let slots: Vec<_> = {
+        use rayon::prelude::*;
+        (0..sizes.len()).into_par_iter().map(combine_slot).collect()
+    };
+    #[cfg(not(feature = "rayon"))]
+    let slots: Vec<_> = (0..sizes.len()).map(combine_slot).collect();
+
+    Some(XorVec::from(slots))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, ChaChaRng};
+
+    use dc::tests::assert_group_laws;
+    use super::*;
+
+    #[test]
+    fn group_laws() {
+        let mut rng = ChaChaRng::from_seed([6; 32]);
+        let mut random = || {
+            let mut x = XorVec::from(vec![0u8; 33]);
+            x.randomize(&mut rng);
+            x
+        };
+        let (a, b) = (random(), random());
+        assert_group_laws(&a, &b);
+
+        // Every element is its own inverse, which is what makes `Sub` and `Neg` trivial.
+        assert_eq!(-a.clone(), a);
+        assert_eq!(a.clone() - a.clone(), XorVec::from(vec![0u8; 33]));
+        assert_eq!(a.clone() - b.clone(), a.clone() + b.clone());
+
+        let slots = XorVec::from(vec![a.clone(), b.clone()]);
+        let other_slots = XorVec::from(vec![b, a]);
+        assert_group_laws(&slots, &other_slots);
+        assert_eq!(-slots.clone(), slots);
+    }
+
+    #[test]
+    fn assign_by_ref() {
+        let mut rng = ChaChaRng::from_seed([3; 32]);
+        let mut random = |len| {
+            let mut vec = XorVec::from(vec![XorVec::from(vec![0u8; len]); 4]);
+            vec.randomize(&mut rng);
+            vec
+        };
+        let (a, b) = (random(1 << 16), random(1 << 16));
+
+        let mut x = a.clone();
+        let mut y = a.clone();
+        x += &b;
+        y += b.clone();
+        assert_eq!(x, y);
+        x -= &b;
+        y -= b.clone();
+        assert_eq!(x, y);
+        assert_eq!(x, a);
+    }
+
+    #[test]
+    fn zero() {
+        let mut rng = ChaChaRng::from_seed([5; 32]);
+        let mut x = XorVec::from(vec![XorVec::from(vec![0u8; 7]), XorVec::from(vec![0u8; 300])]);
+        x.randomize(&mut rng);
+        let zero = XorVec::dc_zero(&x);
+        assert_eq!(zero.iter().map(|slot| slot.len()).collect::<Vec<_>>(), vec![7, 300]);
+        assert!(zero.iter().all(|slot| slot.iter().all(|&b| b == 0)));
+        assert_eq!(zero + x.clone(), x);
+    }
+
+    #[test]
+    fn combine() {
+        let mut rng = ChaChaRng::from_seed([4; 32]);
+        let sizes = [16, 1, 0, 1024];
+        let contributions: Vec<_> = (0..5).map(|_| {
+            let mut c: XorVec<XorVec<u8>> = sizes.iter().map(|&size| XorVec::from(vec![0u8; size])).collect::<Vec<_>>().into();
+            c.randomize(&mut rng);
+            c
+        }).collect();
+
+        // Combine byte by byte.
+        let expected: Vec<Vec<u8>> = sizes.iter().enumerate().map(|(i, &size)| {
+            (0..size).map(|j| contributions.iter().fold(0, |acc, c| acc ^ c[i][j])).collect()
+        }).collect();
+        let combined = combine_slots(&contributions).unwrap();
+        assert_eq!(combined.iter().map(|slot| slot.to_vec()).collect::<Vec<_>>(), expected);
+        assert_eq!(combine_slots(&[]).unwrap().len(), 0);
+
+        let mut wrong_size = contributions.clone();
+        wrong_size[2] = sizes.iter().map(|&size| XorVec::from(vec![0u8; size + 1])).collect::<Vec<_>>().into();
+        assert_eq!(combine_slots(&wrong_size), None);
+        let mut wrong_count = contributions.clone();
+        wrong_count[4] = XorVec::from(vec![]);
+        assert_eq!(combine_slots(&wrong_count), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Entry points for fuzzing the handling of untrusted input
+//!
+//! This module is only compiled with the `fuzz` feature, which is enabled by the fuzz targets
+//! in `fuzz/`.
+
+use futures::{stream, Stream};
+use bytes::Bytes;
+
+use io::{ReadAuthenticatedPayloads, IncomingPayload, RoundCounter};
+use messages::{PublicKey, SecretKey};
+use ::{PeerIndex, SessionId};
+
+lazy_static! {
+    static ref LTVKS: Vec<PublicKey> = (1..4).map(|i| {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[i; 32]).unwrap();
+        PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap()
+    }).collect();
+}
+
+/// Feeds a single frame into `ReadAuthenticatedPayloads` for a session with three peers.
+///
+/// The first byte of `data` is the peer index reported by the underlying stream, which may be
+/// out of range, and the remaining bytes are the frame.
+pub fn read_authenticated_payloads(data: &[u8]) {
+    let (peer_index, frame) = match data.split_first() {
+        Some((&peer_index, frame)) => (PeerIndex::from(peer_index), frame),
+        None => return,
+    };
+
+    let inner = stream::iter_ok::<_, ::std::io::Error>(vec![(peer_index, Bytes::from(frame))]);
+    let reader = ReadAuthenticatedPayloads::new(inner, SessionId([0x5e; 32]), &LTVKS, RoundCounter::new())
+        .expect("the keys are valid");
+    let items: Vec<_> = reader.wait().collect();
+
+    assert_eq!(items.len(), 1);
+    match items[0] {
+        Ok((index, IncomingPayload::Valid(_))) | Ok((index, IncomingPayload::Invalid(_))) => {
+            assert_eq!(index, peer_index);
+        },
+        Err(_) => panic!("the underlying stream does not fail"),
+    }
+}
@@ -22,32 +22,321 @@
 ///! header, if the header is added by the by the sending peer).
 
 use std::io;
-use futures::{Stream, Poll, Async};
-use bytes::Bytes;
-use bincode;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::cell::Cell;
+use futures::{Stream, Sink, Poll, Async, AsyncSink, StartSend};
+use bytes::{Bytes, BytesMut, BufMut};
 use secp256k1;
 use blake2::{Blake2s, Digest};
+use bit_set::BitSet;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
 
-use messages::{Message, Payload, PublicKey};
-use ::{SessionId, PeerIndex, SequenceNum};
+use messages::{Message, Header, Payload, PublicKey, SecretKey, ValidationError, PROTOCOL_VERSION, MAX_MESSAGE_SIZE};
+use messages::{encode, decode, Secret};
+use secp256k1::constants::COMPACT_SIGNATURE_SIZE;
+use ::{SessionId, PeerIndex, SequenceNum, peer_index_to_usize, usize_to_peer_index};
 
-const MAGIC_MESSAGE_PREFIX : &[u8; 32] = b"DICEMIX_SIGNED_MESSAGE__________";
+/// Size of the personalization field of the BLAKE2s parameter block in bytes, see RFC 7693
+const PERSONALIZATION_SIZE : usize = 8;
+
+/// BLAKE2s personalization of the hash of a signed message, for domain separation
+///
+/// The personalization ensures that a signature on a message can never be mistaken for a
+/// signature on some other data hashed with BLAKE2s under the same key. It is part of the
+/// parameter block, so it costs no extra compression and is independent of the block size.
+///
+/// Until protocol version 1, the hash was domain-separated by prepending the 32-byte string
+/// `DICEMIX_SIGNED_MESSAGE__________` twice instead, which filled exactly one block.
+/// Peers of version 1 reject all frames of later versions with `RejectReason::WrongVersion`,
+/// so the digests are never mixed.
+const MESSAGE_PERSONALIZATION : &[u8; PERSONALIZATION_SIZE] = b"DMSigMsg";
+
+/// Size of the hash of a signed message in bytes, which is the size of a secp256k1 message
+const DIGEST_SIZE : usize = 32;
+
+// Fails to compile if secp256k1 expects messages of a different size.
+const _DIGEST_SIZE_CHECK : [(); DIGEST_SIZE] = [(); secp256k1::constants::MESSAGE_SIZE];
+
+// Fails to compile if the personalization does not fill exactly the two words of the parameter
+// block that `new_message_hasher()` writes it to.
+const _PERSONALIZATION_SIZE_CHECK : [(); 2 * 4] = [(); MESSAGE_PERSONALIZATION.len()];
 
 pub enum IncomingPayload {
     Valid(Payload),
-    Invalid,
+    Invalid(RejectReason),
+}
+
+/// The reason why an incoming message has been rejected
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum RejectReason {
+    /// The message is too short to contain a signature.
+    TooShort,
+    /// The message is larger than the maximum message size or the frame limit of the round.
+    TooLong,
+    /// The message cannot be deserialized.
+    MalformedMessage,
+    /// The signature cannot be deserialized.
+    MalformedSignature,
+    /// The message has been created for a different protocol version.
+    WrongVersion,
+    /// The message belongs to a different session.
+    WrongSession,
+    /// The message belongs to a different round.
+    WrongSequence,
+    /// The peer index in the header does not match the sender.
+    WrongPeerIndex,
+    /// The underlying stream has reported a sender that is not a peer of the session.
+    UnknownPeer,
+    /// The payload exceeds the size bounds given by the number of peers.
+    InvalidPayload,
+    /// A public key in the payload is not a valid point.
+    InvalidKey,
+    /// The signature is invalid.
+    BadSignature,
+    /// The frame is an exact copy of a frame accepted earlier in this round.
+    Replay,
+}
+
+/// An error in the stream of incoming payloads
+///
+/// Invalid messages are not errors of the stream, see `IncomingPayload::Invalid`.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The underlying transport has failed.
+    Transport(io::Error),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        ReadError::Transport(err)
+    }
+}
+
+/// Maximum number of rounds in a session
+///
+/// Every round has its own sequence number, and sequence numbers are never reused within a
+/// session, because this would allow replaying frames of earlier rounds.
+pub const MAX_ROUNDS: u64 = SequenceNum::max_value() as u64 + 1;
+
+/// The session has used up all `MAX_ROUNDS` rounds and must be terminated.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RoundsExhausted;
+
+/// A frame on the wire, i.e., a serialized message followed by its compact signature
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SignedFrame {
+    pub message: Bytes,
+    pub signature: [u8; COMPACT_SIGNATURE_SIZE],
+}
+
+/// The frame is too short to contain a signature.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TooShort;
+
+impl SignedFrame {
+    /// Splits a frame into the serialized message and the signature.
+    pub fn split(bytes: &Bytes) -> Result<SignedFrame, TooShort> {
+        let split_pos = bytes.len().checked_sub(COMPACT_SIGNATURE_SIZE).ok_or(TooShort)?;
+        let mut signature = [0u8; COMPACT_SIGNATURE_SIZE];
+        signature.copy_from_slice(&bytes[split_pos..]);
+        Ok(SignedFrame {
+            message: bytes.slice_to(split_pos),
+            signature,
+        })
+    }
+
+    /// Checks that the signature of the frame is a valid signature of the message by `ltvk`.
+    ///
+    /// This does not parse the message, so a valid signature proves that the owner of `ltvk`
+    /// has sent exactly these bytes, whatever they contain.
+    pub fn verify(&self, ltvk: &PublicKey) -> bool {
+        let mut hasher = new_message_hasher();
+        hasher.input(&self.message);
+        match secp256k1::Signature::from_compact(&::SECP256K1, &self.signature) {
+            Ok(sig) => ::SECP256K1.verify(&finalize_message_digest(hasher), &sig, ltvk).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the frame, i.e., the inverse of `split()`.
+    pub fn into_bytes(self) -> Bytes {
+        let mut bytes = BytesMut::with_capacity(self.message.len() + COMPACT_SIGNATURE_SIZE);
+        bytes.put_slice(&self.message);
+        bytes.put_slice(&self.signature);
+        bytes.freeze()
+    }
+}
+
+// A retained frame is serialized as the frame on the wire, e.g., as part of a checkpoint.
+impl Serialize for SignedFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.clone().into_bytes()[..].serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        SignedFrame::split(&Bytes::from(bytes)).map_err(|TooShort| {
+            de::Error::invalid_length(0, &"a frame with a compact signature")
+        })
+    }
+}
+
+/// An error in the setup of a `ReadAuthenticatedPayloads`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SetupError {
+    /// There are no peers, so no message could ever be authenticated.
+    NoPeers,
+    /// The long-term verification key of the peer with this index is invalid.
+    InvalidLtvk(PeerIndex),
+    /// There are more peers than a `PeerIndex` can address.
+    TooManyPeers,
+}
+
+/// The sequence number of the current round, shared between reading and writing
+///
+/// Clones refer to the same counter, so advancing the round affects all of them at once.
+#[derive(Clone, Debug, Default)]
+pub struct RoundCounter(Rc<Cell<SequenceNum>>);
+
+impl RoundCounter {
+    /// Creates a counter starting at the first round.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a counter starting at the round with the given sequence number, e.g., to resume
+    /// a session.
+    pub fn starting_at(sequence_num: SequenceNum) -> Self {
+        RoundCounter(Rc::new(Cell::new(sequence_num)))
+    }
+
+    /// Returns the sequence number of the current round.
+    pub fn get(&self) -> SequenceNum {
+        self.0.get()
+    }
+
+    /// Moves on to the next round.
+    ///
+    /// If the current round is the last one, the counter stays in the last round.
+    pub fn advance(&self) -> Result<(), RoundsExhausted> {
+        let next = self.0.get().checked_add(1).ok_or(RoundsExhausted)?;
+        self.0.set(next);
+        Ok(())
+    }
+}
+
+/// Decides whether a frame is worth processing, e.g., to stop a peer that floods us with frames
+///
+/// The limiter is consulted for every frame from a peer that has not been excluded before the
+/// frame is authenticated, so rejecting a frame saves the verification of its signature.
+pub trait RateLimiter {
+    /// Returns whether a frame of `len` bytes from `peer_index` in the round with
+    /// `sequence_num` is processed. Frames are dropped otherwise.
+    fn allow(&mut self, peer_index: PeerIndex, sequence_num: SequenceNum, len: usize) -> bool;
+}
+
+/// A `RateLimiter` that processes at most a fixed number of frames per peer and round
+///
+/// An honest peer sends a single frame per round, but the broadcast mechanism may relay
+/// duplicates, so the budget should be somewhat larger than one.
+pub struct FrameBudget {
+    frames_per_round: usize,
+    sequence_num: SequenceNum,
+    spent: HashMap<PeerIndex, usize>,
+}
+
+impl FrameBudget {
+    pub fn new(frames_per_round: usize) -> Self {
+        Self {
+            frames_per_round,
+            sequence_num: 0,
+            spent: HashMap::new(),
+        }
+    }
+}
+
+impl RateLimiter for FrameBudget {
+    fn allow(&mut self, peer_index: PeerIndex, sequence_num: SequenceNum, _: usize) -> bool {
+        if sequence_num != self.sequence_num {
+            self.sequence_num = sequence_num;
+            self.spent.clear();
+        }
+        let spent = self.spent.entry(peer_index).or_insert(0);
+        if *spent >= self.frames_per_round {
+            return false;
+        }
+        *spent += 1;
+        true
+    }
 }
 
 /// Wrapper for FramedRead that parses and authenticates messages.
 ///
-/// Errors in the stream indicate always I/O errors.
+/// Errors in the stream are `ReadError`s, which indicate failures of the underlying transport.
 /// Invalid messages are indicated by a stream item with `IncomingPayload::Invalid`
-/// as second component.
+/// as second component, which carries the reason for the rejection.
 pub struct ReadAuthenticatedPayloads<'a, T: Stream<Item = (PeerIndex, Bytes)>> {
     inner: T,
     session_id: SessionId,
-    ltvks: &'a Vec<PublicKey>,
-    sequence_num: SequenceNum,
+    ltvks: &'a [PublicKey],
+    round: RoundCounter,
+    max_frame_length: usize,
+    excluded: BitSet,
+    replay_cache: Option<ReplayCache>,
+    reject_counts: HashMap<RejectReason, u64>,
+    rate_limiter: Option<Box<dyn RateLimiter>>,
+}
+
+/// Digests of the valid frames accepted in the current round, see
+/// `ReadAuthenticatedPayloads::enable_replay_cache()`
+struct ReplayCache {
+    capacity: usize,
+    digests: HashSet<[u8; 32]>,
+    // Insertion order, for evicting the oldest digest once the cache is full
+    order: VecDeque<[u8; 32]>,
+}
+
+impl ReplayCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            digests: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.digests.contains(digest)
+    }
+
+    fn insert(&mut self, digest: [u8; 32]) {
+        if self.capacity == 0 || !self.digests.insert(digest) {
+            return;
+        }
+        if self.order.len() == self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.digests.remove(&oldest);
+        }
+        self.order.push_back(digest);
+    }
+
+    fn clear(&mut self) {
+        self.digests.clear();
+        self.order.clear();
+    }
+}
+
+/// Returns the BLAKE2s digest of a whole frame, including the signature.
+fn frame_digest(bytes: &Bytes) -> [u8; 32] {
+    let mut hasher = Blake2s::default();
+    hasher.input(bytes);
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.result());
+    digest
 }
 
 impl<'a, T> ReadAuthenticatedPayloads<'a, T>
@@ -56,120 +345,1057 @@ impl<'a, T> ReadAuthenticatedPayloads<'a, T>
     /// Creates a new `ReadAuthenticatedPayloads`.
     ///
     /// The underlying stream is responsible for handling messages
-    ///   * from excluded peers and
-    ///   * from peers that have sent a message already in this round,
+    /// from peers that have sent a message already in this round,
     /// e.g., by returning an error or just ignoring the message.
+    /// Messages from excluded peers are dropped, see `exclude()`.
+    /// The expected sequence number is read from `round`, which should be shared with the
+    /// corresponding `WriteSignedPayloads`. A resumed session starts with a counter from
+    /// `RoundCounter::starting_at()`.
+    ///
+    /// Returns an error if `ltvks` is empty or contains an invalid key.
     // TODO This means we need to forward the call to advance_round() to the underlying stream.
-    // Also there should be an exclude() function, and we need to delegate calls to this function
-    // to the underlying stream, too.
-    fn new(inner: T, session_id: SessionId, ltvks: &'a Vec<PublicKey>) -> Self {
-        Self {
-            inner: inner,
-            session_id: session_id,
-            ltvks: ltvks,
-            sequence_num: 0,
+    pub fn new(inner: T, session_id: SessionId, ltvks: &'a [PublicKey], round: RoundCounter)
+        -> Result<Self, SetupError>
+    {
+        if ltvks.is_empty() {
+            return Err(SetupError::NoPeers);
+        }
+        if usize_to_peer_index(ltvks.len()).is_none() {
+            return Err(SetupError::TooManyPeers);
+        }
+        if let Some(index) = ltvks.iter().position(|ltvk| !ltvk.is_valid()) {
+            return Err(SetupError::InvalidLtvk(usize_to_peer_index(index).expect("checked above")));
+        }
+        Ok(Self {
+            inner,
+            session_id,
+            ltvks,
+            round,
+            max_frame_length: MAX_MESSAGE_SIZE + COMPACT_SIGNATURE_SIZE,
+            excluded: BitSet::with_capacity(ltvks.len()),
+            replay_cache: None,
+            reject_counts: HashMap::new(),
+            rate_limiter: None,
+        })
+    }
+
+    /// Returns the number of rejected messages per reason, over all rounds of the session.
+    ///
+    /// Messages from excluded peers are dropped before they are authenticated and not counted.
+    pub fn reject_counts(&self) -> &HashMap<RejectReason, u64> {
+        &self.reject_counts
+    }
+
+    /// Rejects exact copies of frames accepted earlier in the same round as
+    /// `RejectReason::Replay`, e.g., if a relay sends the same frame twice.
+    ///
+    /// The digests of at most `capacity` frames are kept per round, which should be at least the
+    /// number of peers. The cache is cleared whenever the round advances.
+    pub fn enable_replay_cache(&mut self, capacity: usize) {
+        self.replay_cache = Some(ReplayCache::new(capacity));
+    }
+
+    /// Consults `limiter` for every incoming frame, dropping the frames it does not allow.
+    ///
+    /// By default, no frames are dropped. Dropped frames are not counted as rejections.
+    pub fn set_rate_limiter(&mut self, limiter: Box<dyn RateLimiter>) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// Excludes a peer, i.e., all further messages from this peer are dropped silently.
+    ///
+    /// Excluding an index that does not belong to a peer of the session has no effect, because
+    /// messages with such an index are rejected as `RejectReason::UnknownPeer` anyway.
+    // TODO We should delegate calls to this function to the underlying stream, which may be able
+    // to drop the messages earlier.
+    pub fn exclude(&mut self, peer_index: PeerIndex) {
+        match peer_index_to_usize(peer_index) {
+            Some(index) if index < self.ltvks.len() => {
+                self.excluded.insert(index);
+            },
+            _ => {},
         }
     }
 
+    /// Moves on to the next round, in which frames longer than `max_frame_length` are rejected.
+    ///
+    /// The frame limit for a round can be obtained from a `RoundSchedule`. After the last round,
+    /// the round and the frame limit stay unchanged and the session must be terminated.
     // TODO We should export access to set_max_frame_length() of the underlying
     // length_delimited::FramedRead (and actually assume that it is of this type).
     // First, we need an adapter Stream<PeerIndex, T>, which relays a constant PeerIndex
     // and delegates every call to an inner Stream<T>.
-    fn advance_round(&mut self, /* max_frame_length: usize */) {
-        self.sequence_num += 1;
+    pub fn advance_round(&mut self, max_frame_length: usize) -> Result<(), RoundsExhausted> {
+        self.round.advance()?;
+        self.max_frame_length = max_frame_length;
+        if let Some(ref mut cache) = self.replay_cache {
+            cache.clear();
+        }
         // self.inner.set_max_frame_length(max_frame_length);
+        Ok(())
+    }
+
+    fn is_excluded(&self, peer_index: PeerIndex) -> bool {
+        peer_index_to_usize(peer_index).map_or(false, |index| self.excluded.contains(index))
+    }
+
+    fn is_rate_limited(&mut self, peer_index: PeerIndex, bytes: &Bytes) -> bool {
+        let sequence_num = self.round.get();
+        match self.rate_limiter {
+            Some(ref mut limiter) => !limiter.allow(peer_index, sequence_num, bytes.len()),
+            None => false,
+        }
+    }
+
+    /// Parses and authenticates a message received from a peer, counting rejections.
+    fn authenticate(&mut self, peer_index: PeerIndex, bytes: &Bytes) -> IncomingPayload {
+        let incoming_payload = self.authenticate_uncounted(peer_index, bytes);
+        if let IncomingPayload::Invalid(reason) = incoming_payload {
+            *self.reject_counts.entry(reason).or_insert(0) += 1;
+        }
+        incoming_payload
+    }
+
+    fn authenticate_uncounted(&mut self, peer_index: PeerIndex, bytes: &Bytes) -> IncomingPayload {
+        if bytes.len() > self.max_frame_length {
+            // TODO log: format!("frame too long for this round, {} bytes", bytes.len()))
+            return IncomingPayload::Invalid(RejectReason::TooLong);
+        }
+
+        let digest = match self.replay_cache {
+            Some(ref cache) => {
+                let digest = frame_digest(bytes);
+                if cache.contains(&digest) {
+                    // TODO log: format!("replayed frame from peer {}", peer_index)
+                    return IncomingPayload::Invalid(RejectReason::Replay);
+                }
+                Some(digest)
+            },
+            None => None,
+        };
+
+        let incoming_payload = authenticate(self.session_id, self.ltvks, self.round.get(), peer_index, bytes);
+        if let (&IncomingPayload::Valid(_), Some(digest)) = (&incoming_payload, digest) {
+            self.replay_cache.as_mut().unwrap().insert(digest);
+        }
+        incoming_payload
     }
 }
+
+impl<'a, T> ReadAuthenticatedPayloads<'a, T>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+{
+    /// Polls the next frame that is not from an excluded peer and authenticates it.
+    ///
+    /// The frame is returned along with the payload.
+    fn poll_frame(&mut self) -> Poll<Option<(PeerIndex, IncomingPayload, Bytes)>, ReadError> {
+        loop {
+            // This maps errors of the underlying stream to `ReadError::Transport`.
+            match try_ready!(self.inner.poll()) {
+                None => return Ok(Async::Ready(None)),
+                Some((peer_index, _)) if self.is_excluded(peer_index) => {
+                    // Drop messages from excluded peers.
+                    continue;
+                },
+                Some((peer_index, ref bytes)) if self.is_rate_limited(peer_index, bytes) => {
+                    // TODO log: format!("rate limit of peer {} exceeded", peer_index)
+                    continue;
+                },
+                Some((peer_index, bytes)) => {
+                    let incoming_payload = self.authenticate(peer_index, &bytes);
+                    return Ok(Async::Ready(Some((peer_index, incoming_payload, bytes))));
+                },
+            }
+        }
+    }
+}
+
 impl<'a, T> Stream for ReadAuthenticatedPayloads<'a, T>
     where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
 {
     type Item = (PeerIndex, IncomingPayload);
-    type Error = io::Error;
+    type Error = ReadError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match try_ready!(self.inner.poll()) {
-            None => Ok(Async::Ready(None)),
-            Some((peer_index, bytes)) => {
-                // Return value indicating an invalid message
-                let invalid = Ok(Async::Ready(Some((peer_index, IncomingPayload::Invalid))));
-
-                // Check size
-                if bytes.len() < secp256k1::constants::COMPACT_SIGNATURE_SIZE {
-                    // TODO log: format!("message too short to extract header and signature, only {} bytes", bytes.len()))
-                    return invalid;
-                }
+        let item = try_ready!(self.poll_frame());
+        Ok(Async::Ready(item.map(|(peer_index, incoming_payload, _)| (peer_index, incoming_payload))))
+    }
+}
+
+/// Wrapper for `ReadAuthenticatedPayloads` that keeps the signed frames of valid messages.
+///
+/// The frame of a valid message is cryptographic evidence that the peer has sent the message,
+/// e.g., to prove to others that a peer has signed a disruptive message. Every item carries
+/// the frame if and only if the payload is `IncomingPayload::Valid`.
+pub struct ReadAuthenticatedPayloadsWithProof<'a, T: Stream<Item = (PeerIndex, Bytes)>> {
+    reader: ReadAuthenticatedPayloads<'a, T>,
+}
+
+impl<'a, T> ReadAuthenticatedPayloadsWithProof<'a, T>
+    where T: Stream<Item = (PeerIndex, Bytes)>
+{
+    /// Creates a new `ReadAuthenticatedPayloadsWithProof` from a reader.
+    pub fn new(reader: ReadAuthenticatedPayloads<'a, T>) -> Self {
+        Self {
+            reader,
+        }
+    }
+
+    /// Returns the wrapped reader, e.g., to exclude peers.
+    pub fn get_mut(&mut self) -> &mut ReadAuthenticatedPayloads<'a, T> {
+        &mut self.reader
+    }
+
+    /// Consumes the wrapper, returning the wrapped reader.
+    pub fn into_inner(self) -> ReadAuthenticatedPayloads<'a, T> {
+        self.reader
+    }
+}
 
-                // Split bytes
-                let split_pos = bytes.len() - secp256k1::constants::COMPACT_SIGNATURE_SIZE;
-                let (msg_bytes, sig_bytes) = bytes.split_at(split_pos);
-
-                // Try to deserialize
-                let sig_result = secp256k1::Signature::from_compact(&::SECP256K1, &sig_bytes);
-                let msg_result : bincode::Result<Message> = bincode::deserialize(&msg_bytes);
-
-                // Create message digest
-                let mut hasher = new_prefixed_hasher();
-                hasher.input(&bytes);
-
-                match (msg_result, sig_result) {
-                    (Err(err), _) => {
-                        // TODO log: cannot parse message
-                        invalid
-                    },
-                    (_, Err(err)) => {
-                        // TODO log: cannot deserialize signature
-                        invalid
-                    },
-                    (Ok(Message { header: hdr, payload: pay }), Ok(sig)) => {
-                        // Check session ID
-                        if hdr.session_id != self.session_id {
-                            // TODO log: format!("unexpected session ID {})", hdr.session_id)
-                            return invalid;
-                        }
-
-                        // Check sequence number
-                        if hdr.sequence_num != self.sequence_num {
-                            // TODO log: format!("wrong sequence number (got {}, expected {})", hdr.sequence_num, expected);
-                            return invalid;
-                        }
-
-                        // Check peer index
-                        if hdr.peer_index != peer_index {
-                            // TODO log: format!("unexpected peer index {})", hdr.peer_index)
-                            return invalid;
-                        }
-
-                        // Verify signature
-                        let digest = secp256k1::Message::from_slice(&hasher.result()).unwrap();
-                        // TODO These "as" casts
-                        //   * assume that usize is at least u32 and
-                        //   * are ugly because they will be everywhere
-                        //     (but being explicit may be a good idea)
-                        // The underlying stream could cast safely to usize
-                        // as soon as it receives a message.
-                        // See https://github.com/rust-lang/rust/pull/29220 .
-                        match ::SECP256K1.verify(&digest, &sig, &self.ltvks[peer_index as usize]) {
-                            Err(err) => {
-                                // TODO log
-                                invalid
-                            },
-                            Ok(()) => {
-                                Ok(Async::Ready(Some((peer_index, IncomingPayload::Valid(pay)))))
-                            },
-                        }
-                    }
+impl<'a, T> Stream for ReadAuthenticatedPayloadsWithProof<'a, T>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+{
+    type Item = (PeerIndex, IncomingPayload, Option<SignedFrame>);
+    type Error = ReadError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let item = try_ready!(self.reader.poll_frame());
+        Ok(Async::Ready(item.map(|(peer_index, incoming_payload, bytes)| {
+            let proof = match incoming_payload {
+                // A valid frame always contains a signature.
+                IncomingPayload::Valid(_) => SignedFrame::split(&bytes).ok(),
+                IncomingPayload::Invalid(_) => None,
+            };
+            (peer_index, incoming_payload, proof)
+        })))
+    }
+}
+
+/// Parses and authenticates a message received from a peer in the given session and round.
+///
+/// This never panics, whatever the underlying stream delivers.
+fn authenticate(session_id: SessionId,
+                ltvks: &[PublicKey],
+                sequence_num: SequenceNum,
+                peer_index: PeerIndex,
+                bytes: &Bytes) -> IncomingPayload {
+    // Check the sender, which is provided by the underlying stream.
+    // Every accepted peer index is smaller than the number of peers, so later code can cast it
+    // to usize without truncation.
+    let ltvk = match peer_index_to_usize(peer_index).and_then(|index| ltvks.get(index)) {
+        Some(ltvk) => ltvk,
+        None => {
+            // TODO log: format!("unknown peer index {}", peer_index)
+            return IncomingPayload::Invalid(RejectReason::UnknownPeer);
+        }
+    };
+
+    // Split bytes
+    let frame = match SignedFrame::split(bytes) {
+        Ok(frame) => frame,
+        Err(TooShort) => {
+            // TODO log: format!("message too short to extract header and signature, only {} bytes", bytes.len()))
+            return IncomingPayload::Invalid(RejectReason::TooShort);
+        },
+    };
+    let msg_bytes = &frame.message;
+
+    if msg_bytes.len() > MAX_MESSAGE_SIZE {
+        // TODO log: format!("message too long, {} bytes", msg_bytes.len()))
+        return IncomingPayload::Invalid(RejectReason::TooLong);
+    }
+
+    // Try to deserialize
+    let sig_result = secp256k1::Signature::from_compact(&::SECP256K1, &frame.signature);
+    let msg_result = decode::<Message>(msg_bytes);
+
+    // Create message digest
+    let mut hasher = new_message_hasher();
+    hasher.input(msg_bytes);
+
+    let reject = |reason| IncomingPayload::Invalid(reason);
+    match (msg_result, sig_result) {
+        (Err(_), _) => {
+            // TODO log: cannot parse message
+            reject(RejectReason::MalformedMessage)
+        },
+        (_, Err(_)) => {
+            // TODO log: cannot deserialize signature
+            reject(RejectReason::MalformedSignature)
+        },
+        (Ok(Message { header: hdr, payload: pay }), Ok(sig)) => {
+            // Check version
+            if hdr.version != PROTOCOL_VERSION {
+                // TODO log: format!("unsupported protocol version {}", hdr.version)
+                return reject(RejectReason::WrongVersion);
+            }
+
+            // Check session ID
+            if hdr.session_id != session_id {
+                // TODO log: format!("unexpected session ID {})", hdr.session_id)
+                return reject(RejectReason::WrongSession);
+            }
+
+            // Check sequence number
+            if hdr.sequence_num != sequence_num {
+                // TODO log: format!("wrong sequence number (got {}, expected {})", hdr.sequence_num, expected);
+                return reject(RejectReason::WrongSequence);
+            }
+
+            // Check peer index
+            if hdr.peer_index != peer_index {
+                // TODO log: format!("unexpected peer index {})", hdr.peer_index)
+                return reject(RejectReason::WrongPeerIndex);
+            }
+
+            // Check sizes
+            match pay.validate(ltvks.len()) {
+                Ok(()) => {},
+                Err(ValidationError::InvalidKey) => {
+                    // TODO log: invalid public key
+                    return reject(RejectReason::InvalidKey);
+                },
+                Err(_) => {
+                    // TODO log: format!("invalid payload: {:?}", err)
+                    return reject(RejectReason::InvalidPayload);
+                },
+            }
+
+            // Verify signature
+            let digest = finalize_message_digest(hasher);
+            match ::SECP256K1.verify(&digest, &sig, ltvk) {
+                Err(_) => {
+                    // TODO log
+                    reject(RejectReason::BadSignature)
+                },
+                Ok(()) => {
+                    IncomingPayload::Valid(pay)
+                },
+            }
+        }
+    }
+}
+
+/// Wrapper for FramedRead that routes messages of several sessions by their session ID and
+/// parses and authenticates them like `ReadAuthenticatedPayloads`.
+///
+/// Every session has its own long-term verification keys and its own round counter.
+/// Messages for sessions that have not been added, and messages whose header cannot be
+/// parsed, cannot be attributed to any session and are dropped.
+pub struct MultiSessionReader<'a, T: Stream<Item = (PeerIndex, Bytes)>> {
+    inner: T,
+    sessions: HashMap<SessionId, (&'a [PublicKey], RoundCounter)>,
+}
+
+impl<'a, T> MultiSessionReader<'a, T>
+    where T: Stream<Item = (PeerIndex, Bytes)>
+{
+    /// Creates a new `MultiSessionReader` without any sessions.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Adds a session, replacing an existing session with the same ID.
+    ///
+    /// The expected sequence number is read from `round`, which should be shared with the
+    /// `WriteSignedPayloads` of the session.
+    pub fn add_session(&mut self, session_id: SessionId, ltvks: &'a [PublicKey], round: RoundCounter) {
+        self.sessions.insert(session_id, (ltvks, round));
+    }
+
+    /// Removes a session, i.e., all further messages for this session are dropped silently.
+    ///
+    /// Returns `false` if there was no such session.
+    pub fn remove_session(&mut self, session_id: &SessionId) -> bool {
+        self.sessions.remove(session_id).is_some()
+    }
+}
+
+impl<'a, T> Stream for MultiSessionReader<'a, T>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+{
+    type Item = (SessionId, PeerIndex, IncomingPayload);
+    type Error = ReadError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let (peer_index, bytes) = match try_ready!(self.inner.poll()) {
+                None => return Ok(Async::Ready(None)),
+                Some(frame) => frame,
+            };
+
+            // The header is at the beginning of the message, so we can read it without
+            // deserializing the payload.
+            let header = decode::<Header>(&bytes);
+            let session_id = match header {
+                Ok(hdr) => hdr.session_id,
+                Err(_) => {
+                    // TODO log: cannot parse header
+                    continue;
                 }
-            },
+            };
+
+            match self.sessions.get(&session_id) {
+                None => {
+                    // TODO log: format!("unknown session ID {:?}", session_id)
+                    continue;
+                },
+                Some(&(ltvks, ref round)) => {
+                    let incoming_payload = authenticate(session_id, ltvks, round.get(), peer_index, &bytes);
+                    return Ok(Async::Ready(Some((session_id, peer_index, incoming_payload))));
+                },
+            }
         }
     }
 }
 
-fn new_prefixed_hasher() -> Blake2s {
-    let mut hasher = Blake2s::default();
-    // We get exactly one block if we input the prefix twice (2 * 32 bytes).
-    for _ in 0..1 {
-        hasher.input(MAGIC_MESSAGE_PREFIX);
+/// Wrapper for a sink of frames that stamps, serializes and signs outgoing payloads.
+///
+/// The sequence number in the header is read from `round`, which should be shared with the
+/// corresponding `ReadAuthenticatedPayloads`.
+pub struct WriteSignedPayloads<T: Sink<SinkItem = Bytes>> {
+    inner: T,
+    session_id: SessionId,
+    peer_index: PeerIndex,
+    ltsk: Secret<SecretKey>,
+    round: RoundCounter,
+}
+
+impl<T> WriteSignedPayloads<T>
+    where T: Sink<SinkItem = Bytes>
+{
+    /// Creates a new `WriteSignedPayloads` for our peer index and long-term signing key.
+    pub fn new(inner: T, session_id: SessionId, peer_index: PeerIndex, ltsk: SecretKey, round: RoundCounter) -> Self {
+        Self {
+            inner,
+            session_id,
+            peer_index,
+            ltsk: Secret::new(ltsk),
+            round,
+        }
+    }
+
+    /// Serializes a payload for the current round and appends the signature.
+    fn encode_signed(&self, payload: &Payload) -> Bytes {
+        let header = Header::new(self.session_id, self.peer_index, self.round.get());
+        encode_signed(payload, &header, &self.ltsk)
     }
-    hasher
 }
 
+impl<T> Sink for WriteSignedPayloads<T>
+    where T: Sink<SinkItem = Bytes>
+{
+    type SinkItem = Payload;
+    type SinkError = T::SinkError;
+
+    fn start_send(&mut self, payload: Payload) -> StartSend<Payload, T::SinkError> {
+        // We keep the payload around in case the inner sink is not ready. Signing again
+        // is deterministic, so this costs time but produces the same frame.
+        match self.inner.start_send(self.encode_signed(&payload))? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(payload)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), T::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+
+/// Creates a frame, i.e., the serialized message with the signature appended.
+///
+/// The signature is a compact ECDSA signature by `sk` of the personalized BLAKE2s hash of the
+/// bincode encoding of the message. This is the inverse of the authentication performed by
+/// `ReadAuthenticatedPayloads` and useful for generating test vectors.
+pub fn encode_signed(payload: &Payload, header: &Header, sk: &SecretKey) -> Bytes {
+    // A struct is encoded like the tuple of its fields, so this is the encoding of a `Message`
+    // without copying the payload, which may contain a secret.
+    let bytes = encode(&(header, payload));
+
+    let mut hasher = new_message_hasher();
+    hasher.input(&bytes);
+    let digest = finalize_message_digest(hasher);
+    let sig = ::SECP256K1.sign(&digest, sk).expect("the signing key is valid");
+    SignedFrame {
+        message: Bytes::from(bytes),
+        signature: sig.serialize_compact(&::SECP256K1),
+    }.into_bytes()
+}
+
+/// Creates the hasher for signed messages, which reader and writer must agree on.
+fn new_message_hasher() -> Blake2s {
+    // The parameter block as little-endian words, see RFC 7693, Section 2.5: a digest length
+    // of 32 bytes, no key, fanout 1, depth 1, and the personalization in the last two words.
+    const PERSONALIZATION_WORDS : usize = PERSONALIZATION_SIZE / 4;
+    let mut p = [0x01010000 | DIGEST_SIZE as u32, 0, 0, 0, 0, 0, 0, 0];
+    for (i, b) in MESSAGE_PERSONALIZATION.iter().enumerate() {
+        p[p.len() - PERSONALIZATION_WORDS + i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    Blake2s::with_parameter_block(&p)
+}
+
+/// Finalizes a hasher created by `new_message_hasher()` into the digest to be signed.
+///
+/// This cannot fail, because the digest length in the parameter block of the hasher and the
+/// size of a secp256k1 message are both `DIGEST_SIZE`, which is checked at compile time.
+fn finalize_message_digest(hasher: Blake2s) -> secp256k1::Message {
+    let mut digest = [0u8; DIGEST_SIZE];
+    // The output of Blake2s has 32 bytes by type, see the size check above.
+    digest.copy_from_slice(&hasher.result());
+    secp256k1::Message::from(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream, Future};
+    use secp256k1::key::SecretKey;
+
+    use messages::{Header, Confirm, DcExponential, DcMain, Extension};
+    use ::Commitment;
+    use dc::xor::XorVec;
+    use testutil::broadcast_bus;
+    use super::*;
+
+    const SESSION_ID: SessionId = SessionId([0x5e; 32]);
+
+    fn sign_message(message: &Message, sk: &SecretKey) -> Bytes {
+        sign_bytes(encode(message), sk)
+    }
+
+    fn sign_bytes(mut bytes: Vec<u8>, sk: &SecretKey) -> Bytes {
+        let mut hasher = new_message_hasher();
+        hasher.input(&bytes);
+        let sig = ::SECP256K1.sign(&finalize_message_digest(hasher), sk).unwrap();
+        bytes.extend_from_slice(&sig.serialize_compact(&::SECP256K1));
+        Bytes::from(bytes)
+    }
+
+    fn new_key() -> (SecretKey, PublicKey) {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x11; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        (sk, pk)
+    }
+
+    fn confirm_message(header: Header) -> Message {
+        Message {
+            header,
+            payload: Payload::Confirm(Confirm { data: vec![1, 2, 3] }),
+        }
+    }
+
+    fn read_all(frames: Vec<(PeerIndex, Bytes)>, ltvks: &[PublicKey]) -> Vec<(PeerIndex, IncomingPayload)> {
+        let inner = stream::iter_ok::<_, io::Error>(frames);
+        ReadAuthenticatedPayloads::new(inner, SESSION_ID, ltvks, RoundCounter::new()).unwrap()
+            .wait().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn setup() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let message = confirm_message(Header::new(SESSION_ID, 0, 5));
+        let inner = stream::iter_ok::<_, io::Error>(vec![(0, sign_message(&message, &sk))]);
+        let reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::starting_at(5)).unwrap();
+        // A resumed session expects the sequence number it has been resumed at.
+        match reader.wait().next() {
+            Some(Ok((0, IncomingPayload::Valid(ref pay)))) => assert_eq!(*pay, message.payload),
+            _ => panic!("message of resumed session rejected"),
+        }
+
+        let empty = stream::empty::<(PeerIndex, Bytes), io::Error>;
+        let no_peers = vec![];
+        match ReadAuthenticatedPayloads::new(empty(), SESSION_ID, &no_peers, RoundCounter::new()) {
+            Err(err) => assert_eq!(err, SetupError::NoPeers),
+            Ok(_) => panic!("reader without peers created"),
+        }
+        let invalid = vec![pk, PublicKey::new()];
+        match ReadAuthenticatedPayloads::new(empty(), SESSION_ID, &invalid, RoundCounter::new()) {
+            Err(err) => assert_eq!(err, SetupError::InvalidLtvk(1)),
+            Ok(_) => panic!("reader with invalid key created"),
+        }
+    }
+
+    #[test]
+    fn version() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let good = confirm_message(Header::new(SESSION_ID, 0, 0));
+        let mut wrong_version = good.clone();
+        wrong_version.header.version = PROTOCOL_VERSION + 1;
+        // A broken signature shows that the version is checked before the signature.
+        let mut wrong_version_bytes = sign_message(&wrong_version, &sk).to_vec();
+        let len = wrong_version_bytes.len();
+        wrong_version_bytes[len - 1] ^= 1;
+
+        let items = read_all(vec![
+            (0, sign_message(&good, &sk)),
+            (0, Bytes::from(wrong_version_bytes)),
+        ], &ltvks);
+
+        match items[0] {
+            (0, IncomingPayload::Valid(ref pay)) => assert_eq!(*pay, good.payload),
+            _ => panic!("valid message rejected"),
+        }
+        match items[1] {
+            (0, IncomingPayload::Invalid(reason)) => assert_eq!(reason, RejectReason::WrongVersion),
+            _ => panic!("message with wrong version accepted"),
+        }
+    }
+
+    #[test]
+    fn absurd_length() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let message = Message {
+            header: Header::new(SESSION_ID, 0, 0),
+            payload: Payload::DcExponential(DcExponential { commitment: Commitment([0; 32]), dc_exp: vec![] }),
+        };
+        // The length of the (empty) vector is encoded in the last 8 bytes.
+        let mut bytes = encode(&message);
+        let len = bytes.len();
+        for b in bytes[len - 8..].iter_mut() {
+            *b = 0xff;
+        }
+        let mut frame = sign_message(&message, &sk).to_vec();
+        frame.splice(..len, bytes);
+
+        let items = read_all(vec![(0, Bytes::from(frame))], &ltvks);
+        match items[0] {
+            (0, IncomingPayload::Invalid(reason)) => assert_eq!(reason, RejectReason::MalformedMessage),
+            _ => panic!("message with absurd length accepted"),
+        }
+    }
+
+    #[test]
+    fn too_many_elements() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let message = Message {
+            header: Header::new(SESSION_ID, 0, 0),
+            payload: Payload::DcMain(DcMain {
+                ok: true,
+                dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; 8]); 2]),
+                ke_pk: pk,
+                extension: Extension::None,
+                early_confirm: None,
+            }),
+        };
+
+        let items = read_all(vec![(0, sign_message(&message, &sk))], &ltvks);
+        match items[0] {
+            (0, IncomingPayload::Invalid(reason)) => assert_eq!(reason, RejectReason::InvalidPayload),
+            _ => panic!("oversized payload accepted"),
+        }
+    }
+
+    #[test]
+    fn invalid_key() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let message = Message {
+            header: Header::new(SESSION_ID, 0, 0),
+            payload: Payload::DcMain(DcMain {
+                ok: true,
+                dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; 8])]),
+                ke_pk: pk,
+                extension: Extension::None,
+                early_confirm: None,
+            }),
+        };
+        // Replace the ke_pk by an encoding with an invalid prefix byte and sign the result.
+        let key_bytes = pk.serialize_vec(&::SECP256K1, true);
+        let mut bytes = encode(&message);
+        let pos = bytes.windows(key_bytes.len()).position(|w| w == &key_bytes[..]).unwrap();
+        bytes[pos] = 0x05;
+
+        let items = read_all(vec![(0, sign_bytes(bytes, &sk))], &ltvks);
+        match items[0] {
+            (0, IncomingPayload::Invalid(reason)) => assert_eq!(reason, RejectReason::InvalidKey),
+            _ => panic!("message with invalid key accepted"),
+        }
+    }
+
+    #[test]
+    fn unknown_peer_index() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let message = confirm_message(Header::new(SESSION_ID, 1, 0));
+        let items = read_all(vec![
+            (1, sign_message(&message, &sk)),
+            (7, Bytes::from(vec![0; 3])),
+            (PeerIndex::max_value(), sign_message(&message, &sk)),
+        ], &ltvks);
+        assert_eq!(items.len(), 3);
+        for item in items {
+            match item {
+                (_, IncomingPayload::Invalid(reason)) => assert_eq!(reason, RejectReason::UnknownPeer),
+                _ => panic!("message from unknown peer accepted"),
+            }
+        }
+
+        // Excluding an unknown peer does not allocate a bit for its index, and its messages are
+        // still rejected rather than dropped or attributed to another peer.
+        let frames = vec![(PeerIndex::max_value(), sign_message(&message, &sk))];
+        let mut reader = ReadAuthenticatedPayloads::new(stream::iter_ok::<_, io::Error>(frames),
+                                                        SESSION_ID, &ltvks, RoundCounter::new()).unwrap();
+        reader.exclude(PeerIndex::max_value());
+        assert!(reader.excluded.is_empty());
+        match reader.wait().next() {
+            Some(Ok((_, IncomingPayload::Invalid(reason)))) => assert_eq!(reason, RejectReason::UnknownPeer),
+            _ => panic!("message from unknown peer not rejected"),
+        }
+    }
+
+    #[test]
+    fn transport_error() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+
+        let message = confirm_message(Header::new(SESSION_ID, 0, 0));
+        let frames: Vec<Result<_, io::Error>> = vec![
+            Ok((0, sign_message(&message, &sk))),
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "injected")),
+        ];
+        let inner = stream::iter_result(frames);
+        let mut items = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::new()).unwrap().wait();
+
+        match items.next() {
+            Some(Ok((0, IncomingPayload::Valid(_)))) => {},
+            _ => panic!("valid message rejected"),
+        }
+        match items.next() {
+            Some(Err(ReadError::Transport(err))) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+            _ => panic!("transport error not surfaced"),
+        }
+    }
+
+    #[test]
+    fn message_digest() {
+        let digest = |input: &[u8]| {
+            let mut hasher = new_message_hasher();
+            hasher.input(input);
+            hasher.result().iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        };
+        // Obtained from the reference implementation with the personalization `DMSigMsg`.
+        assert_eq!(digest(b""), "11b82c28da3a7a072a72d0d923577985b1149111e07819d6247a6938de8132ce");
+        assert_eq!(digest(b"abc"), "730cbf2e9e8013fff1478fcaf344e8f3170bc0b9a1b6bf0844d4cc29fba3eac9");
+        let long: Vec<u8> = (0..200).collect();
+        assert_eq!(digest(&long), "e8dc9da4ac7c24ffa257b7a345fbe88a9cb0607a521df36bf8a157bb0bdb7401");
+    }
+
+    #[test]
+    fn finalized_digest() {
+        // The hasher is configured for DIGEST_SIZE bytes, which is what secp256k1 expects.
+        assert_eq!(DIGEST_SIZE, secp256k1::constants::MESSAGE_SIZE);
+        let mut hasher = new_message_hasher();
+        hasher.input(b"abc");
+        let expected = secp256k1::Message::from_slice(&hasher.result()).unwrap();
+        assert_eq!(finalize_message_digest(hasher), expected);
+        assert_eq!(&expected[..2], &[0x73, 0x0c]);
+    }
+
+    #[test]
+    fn encode_signed_vector() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let header = Header::new(SESSION_ID, 0, 0);
+        let payload = Payload::Confirm(Confirm { data: vec![1, 2, 3] });
+
+        let frame = encode_signed(&payload, &header, &sk);
+        let hex: String = frame.iter().map(|b| format!("{:02x}", b)).collect();
+        // ECDSA signatures are deterministic (RFC 6979), so the whole frame can be pinned: the
+        // message followed by the compact signature.
+        assert_eq!(hex, concat!(
+            "03005e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e",
+            "0000000000000000040000000300000000000000010203",
+            "ea0c762bf26e5d67f9a0ccf83912e77fbce7da932b1de35ec03b7f6f04579c83",
+            "7715bfb57a51669a137e88d07d9abd024ffa952a1dd3006f9e48e3a594fae6f1"));
+
+        // Pin the digest that is signed, which must not change within a protocol version.
+        let mut hasher = new_message_hasher();
+        hasher.input(&SignedFrame::split(&frame).unwrap().message);
+        let digest: String = hasher.result().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(digest, "f00d77681fec1aa89a11a56eb65dd593fd67f98af7e1915f5f867bee745d2585");
+
+        let items = read_all(vec![(0, frame)], &ltvks);
+        match items[0] {
+            (0, IncomingPayload::Valid(ref pay)) => assert_eq!(*pay, payload),
+            _ => panic!("encoded frame rejected"),
+        }
+    }
+
+    #[test]
+    fn multiple_sessions() {
+        const OTHER_SESSION_ID: SessionId = SessionId([0x0e; 32]);
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let other_ltvks = vec![pk, pk];
+        let round = RoundCounter::new();
+        let other_round = RoundCounter::new();
+
+        let frames = vec![
+            (0, sign_message(&confirm_message(Header::new(SESSION_ID, 0, 0)), &sk)),
+            (1, sign_message(&confirm_message(Header::new(OTHER_SESSION_ID, 1, 0)), &sk)),
+            // Dropped because the session is unknown
+            (0, sign_message(&confirm_message(Header::new(SessionId([0xff; 32]), 0, 0)), &sk)),
+            (0, sign_message(&confirm_message(Header::new(SESSION_ID, 0, 1)), &sk)),
+            (0, sign_message(&confirm_message(Header::new(OTHER_SESSION_ID, 0, 1)), &sk)),
+        ];
+        let mut reader = MultiSessionReader::new(stream::iter_ok::<_, io::Error>(frames));
+        reader.add_session(SESSION_ID, &ltvks, round.clone());
+        reader.add_session(OTHER_SESSION_ID, &other_ltvks, other_round.clone());
+        let mut items = reader.wait().map(Result::unwrap);
+
+        match items.next() {
+            Some((SESSION_ID, 0, IncomingPayload::Valid(_))) => {},
+            _ => panic!("message of first session not attributed correctly"),
+        }
+        match items.next() {
+            Some((OTHER_SESSION_ID, 1, IncomingPayload::Valid(_))) => {},
+            _ => panic!("message of second session not attributed correctly"),
+        }
+
+        // Only the first session moves on to the next round.
+        round.advance().unwrap();
+        match items.next() {
+            Some((SESSION_ID, 0, IncomingPayload::Valid(_))) => {},
+            _ => panic!("message of first session in next round rejected"),
+        }
+        match items.next() {
+            Some((OTHER_SESSION_ID, 0, IncomingPayload::Invalid(reason))) => {
+                assert_eq!(reason, RejectReason::WrongSequence);
+            },
+            _ => panic!("message of second session with wrong sequence number accepted"),
+        }
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn shared_round_counter() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let round = RoundCounter::new();
+
+        // The bus loops our own messages back to us.
+        let (tx, rx) = broadcast_bus(1).pop().unwrap();
+        let mut writer = WriteSignedPayloads::new(tx, SESSION_ID, 0, sk, round.clone());
+        let mut reader = ReadAuthenticatedPayloads::new(rx, SESSION_ID, &ltvks, round.clone()).unwrap();
+
+        future::lazy(|| {
+            for i in 0..2 {
+                let payload = Payload::Confirm(Confirm { data: vec![i] });
+                assert_eq!(writer.start_send(payload.clone()).unwrap(), AsyncSink::Ready);
+                assert_eq!(writer.poll_complete().unwrap(), Async::Ready(()));
+                match reader.poll().unwrap() {
+                    Async::Ready(Some((0, IncomingPayload::Valid(pay)))) => assert_eq!(pay, payload),
+                    _ => panic!("message rejected"),
+                }
+                // This advances the writer as well.
+                reader.advance_round(MAX_MESSAGE_SIZE).unwrap();
+            }
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+
+        assert_eq!(round.get(), 2);
+    }
+
+    #[test]
+    fn rounds_exhausted() {
+        let (_, pk) = new_key();
+        let ltvks = vec![pk];
+        let round = RoundCounter(Rc::new(Cell::new(SequenceNum::max_value() - 1)));
+        let mut reader = ReadAuthenticatedPayloads::new(stream::empty::<(PeerIndex, Bytes), io::Error>(),
+                                                        SESSION_ID, &ltvks, round.clone()).unwrap();
+
+        assert_eq!(reader.advance_round(100), Ok(()));
+        assert_eq!(round.get(), SequenceNum::max_value());
+        assert_eq!(reader.max_frame_length, 100);
+
+        // The counter does not wrap around to the first round.
+        assert_eq!(reader.advance_round(200), Err(RoundsExhausted));
+        assert_eq!(round.get(), SequenceNum::max_value());
+        assert_eq!(reader.max_frame_length, 100);
+        assert_eq!(round.advance(), Err(RoundsExhausted));
+        assert_eq!(round.get(), SequenceNum::max_value());
+    }
+
+    #[test]
+    fn signed_frame() {
+        let frame = SignedFrame { message: Bytes::from(&b"message"[..]), signature: [0x5f; COMPACT_SIGNATURE_SIZE] };
+        let bytes = frame.clone().into_bytes();
+        assert_eq!(bytes.len(), 7 + COMPACT_SIGNATURE_SIZE);
+        assert_eq!(SignedFrame::split(&bytes), Ok(frame));
+
+        let empty = SignedFrame::split(&bytes.slice_from(7)).unwrap();
+        assert!(empty.message.is_empty());
+        assert_eq!(SignedFrame::split(&bytes.slice_from(8)), Err(TooShort));
+    }
+
+    #[test]
+    fn frames_as_proof() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let message = confirm_message(Header::new(SESSION_ID, 0, 0));
+        let frames = vec![
+            (0, sign_message(&message, &sk)),
+            (0, Bytes::from(vec![0; 3])),
+        ];
+        let inner = stream::iter_ok::<_, io::Error>(frames.clone());
+        let reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::new()).unwrap();
+        let items: Vec<_> = ReadAuthenticatedPayloadsWithProof::new(reader).wait().map(Result::unwrap).collect();
+
+        let proof = match items[0] {
+            (0, IncomingPayload::Valid(ref pay), Some(ref proof)) => {
+                assert_eq!(*pay, message.payload);
+                proof.clone()
+            },
+            _ => panic!("valid message without proof"),
+        };
+        match items[1] {
+            (0, IncomingPayload::Invalid(RejectReason::TooShort), None) => {},
+            _ => panic!("proof for invalid message"),
+        }
+
+        // The retained frame is exactly the frame on the wire and still verifies.
+        assert!(proof.verify(&pk));
+        let bytes = proof.into_bytes();
+        assert_eq!(bytes, frames[0].1);
+        match read_all(vec![(0, bytes)], &ltvks)[0] {
+            (0, IncomingPayload::Valid(ref pay)) => assert_eq!(*pay, message.payload),
+            _ => panic!("retained frame rejected"),
+        }
+    }
+
+    #[test]
+    fn replay_cache() {
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk];
+        let frame = sign_message(&confirm_message(Header::new(SESSION_ID, 0, 0)), &sk);
+        let frames = vec![(0, frame.clone()), (0, Bytes::from(vec![0; 3])), (0, Bytes::from(vec![0; 3])), (0, frame)];
+        let inner = stream::iter_ok::<_, io::Error>(frames);
+        let mut reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::new()).unwrap();
+        reader.enable_replay_cache(1);
+
+        let reasons: Vec<_> = reader.by_ref().wait().map(|item| match item.unwrap() {
+            (0, IncomingPayload::Valid(_)) => None,
+            (0, IncomingPayload::Invalid(reason)) => Some(reason),
+            _ => panic!("wrong peer index"),
+        }).collect();
+        // Invalid frames are not cached, so they are rejected for what they are.
+        assert_eq!(reasons, vec![
+            None,
+            Some(RejectReason::TooShort),
+            Some(RejectReason::TooShort),
+            Some(RejectReason::Replay),
+        ]);
+
+        assert_eq!(reader.replay_cache.as_ref().unwrap().order.len(), 1);
+        reader.advance_round(100).unwrap();
+        assert!(reader.replay_cache.as_ref().unwrap().digests.is_empty());
+    }
+
+    #[test]
+    fn reject_counts() {
+        let (sk, pk) = new_key();
+        let other_sk = SecretKey::from_slice(&::SECP256K1, &[0x22; 32]).unwrap();
+        let ltvks = vec![pk];
+        let valid = sign_message(&confirm_message(Header::new(SESSION_ID, 0, 0)), &sk);
+        let wrong_sequence = sign_message(&confirm_message(Header::new(SESSION_ID, 0, 1)), &sk);
+        let bad_signature = sign_message(&confirm_message(Header::new(SESSION_ID, 0, 0)), &other_sk);
+        // The message is too short to contain a header.
+        let malformed = Bytes::from(vec![0; COMPACT_SIGNATURE_SIZE + 16]);
+        let frames = vec![
+            (0, malformed.clone()),
+            (0, bad_signature.clone()),
+            (0, valid),
+            (0, wrong_sequence),
+            (0, bad_signature),
+            (0, malformed.clone()),
+            (0, malformed),
+        ];
+        let inner = stream::iter_ok::<_, io::Error>(frames);
+        let mut reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::new()).unwrap();
+        assert!(reader.reject_counts().is_empty());
+        assert_eq!(reader.by_ref().wait().count(), 7);
+
+        let expected: HashMap<_, _> = vec![
+            (RejectReason::MalformedMessage, 3),
+            (RejectReason::WrongSequence, 1),
+            (RejectReason::BadSignature, 2),
+        ].into_iter().collect();
+        assert_eq!(*reader.reject_counts(), expected);
+    }
+
+    #[test]
+    fn rate_limiter() {
+        use std::cell::RefCell;
+
+        // Allows two frames per peer and records what it has been asked.
+        struct Mock(Rc<RefCell<Vec<(PeerIndex, SequenceNum, usize)>>>);
+        impl RateLimiter for Mock {
+            fn allow(&mut self, peer_index: PeerIndex, sequence_num: SequenceNum, len: usize) -> bool {
+                let mut calls = self.0.borrow_mut();
+                calls.push((peer_index, sequence_num, len));
+                calls.iter().filter(|call| call.0 == peer_index).count() <= 2
+            }
+        }
+
+        let (sk, pk) = new_key();
+        let ltvks = vec![pk, pk];
+        let valid = |peer_index| sign_message(&confirm_message(Header::new(SESSION_ID, peer_index, 0)), &sk);
+        let garbage = Bytes::from(vec![0; 3]);
+        let frames = vec![
+            (0, valid(0)),
+            (0, garbage.clone()),
+            (1, valid(1)),
+            // Peer 0 floods, so its further frames are dropped without being authenticated.
+            (0, valid(0)),
+            (0, garbage),
+            (1, valid(1)),
+        ];
+        let inner = stream::iter_ok::<_, io::Error>(frames);
+        let mut reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, RoundCounter::new()).unwrap();
+        let calls = Rc::new(RefCell::new(vec![]));
+        reader.set_rate_limiter(Box::new(Mock(calls.clone())));
+
+        let items: Vec<_> = reader.by_ref().wait().map(|item| match item.unwrap() {
+            (peer_index, IncomingPayload::Valid(_)) => (peer_index, None),
+            (peer_index, IncomingPayload::Invalid(reason)) => (peer_index, Some(reason)),
+        }).collect();
+        assert_eq!(items, vec![(0, None), (0, Some(RejectReason::TooShort)), (1, None), (1, None)]);
+        assert_eq!(calls.borrow().len(), 6);
+        assert_eq!(calls.borrow()[1], (0, 0, 3));
+        assert_eq!(reader.reject_counts().values().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn frame_budget() {
+        let mut budget = FrameBudget::new(2);
+        assert!(budget.allow(0, 0, 10) && budget.allow(0, 0, 10));
+        assert!(!budget.allow(0, 0, 10));
+        assert!(budget.allow(1, 0, 10));
+        // The budget is per round.
+        assert!(budget.allow(0, 1, 10));
+        assert!(!FrameBudget::new(0).allow(0, 0, 0));
+    }
+
+    #[test]
+    fn replay_cache_bounded() {
+        let mut cache = ReplayCache::new(2);
+        for i in 0..3 {
+            cache.insert([i; 32]);
+        }
+        assert!(!cache.contains(&[0; 32]));
+        assert!(cache.contains(&[1; 32]) && cache.contains(&[2; 32]));
+        cache.insert([2; 32]);
+        assert_eq!(cache.order.len(), 2);
+    }
+}
@@ -1,99 +1,870 @@
 #![feature(discriminant_value)]
+// Without the `std` feature, only the DC-net arithmetic in `dc` is available.
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
 extern crate rand;
+#[cfg(feature = "std")]
 extern crate byteorder;
+#[cfg(feature = "std")]
 extern crate secp256k1;
+#[cfg(feature = "std")]
 extern crate bytes;
+#[cfg(feature = "std")]
 extern crate tokio_io;
 extern crate serde;
-#[macro_use]
+#[cfg_attr(feature = "std", macro_use)]
 extern crate serde_derive;
+#[cfg(feature = "std")]
 extern crate bincode;
+#[cfg(feature = "std")]
 extern crate vec_map;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate futures;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "std")]
 extern crate bit_set;
+#[cfg(feature = "std")]
 extern crate blake2;
+#[cfg(feature = "std")]
+extern crate zeroize;
+#[cfg(feature = "std")]
+extern crate subtle;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
 
+#[cfg(feature = "std")]
 use std::mem;
+#[cfg(feature = "std")]
+use std::slice;
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::convert::TryFrom;
+#[cfg(feature = "std")]
 use secp256k1::Secp256k1;
+#[cfg(feature = "std")]
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "std")]
+use blake2::{Blake2s, Digest};
+#[cfg(feature = "std")]
+use zeroize::Zeroize;
+#[cfg(feature = "std")]
+use subtle::ConstantTimeEq;
 
+#[cfg(feature = "std")]
 pub use messages::PublicKey;
 
+#[cfg(feature = "std")]
 mod solver;
+#[cfg(feature = "std")]
 mod rng;
+#[cfg(feature = "std")]
 mod messages;
+#[cfg(feature = "std")]
 mod state;
+#[cfg(feature = "std")]
 mod io;
-mod dc;
+#[cfg(all(test, feature = "std"))]
+mod testutil;
+pub mod dc;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 
+#[cfg(feature = "std")]
 lazy_static! {
     pub static ref SECP256K1: Secp256k1 = Secp256k1::new();
 }
 
-type ExtensionVariant = mem::Discriminant<messages::Extension>;
+#[cfg(feature = "std")]
+pub type ExtensionVariant = mem::Discriminant<messages::Extension>;
 
 // These types are sent over the wire, so there may be a need to change them easily.
-type Commitment = [u8; 32];
-type SymmetricKey = [u8; 32];
-type SessionId = [u8; 32];
+#[cfg(feature = "std")]
 type PeerIndex = u32;
+#[cfg(feature = "std")]
 type SequenceNum = u32;
 
-// FIXME We store the peer ID in two [u8; 32], as this allows us to derive various traits.
-// This can be resolved in the future using const generics, see the corresponding Rust RFC:
-// https://github.com/rust-lang/rfcs/pull/2000/files
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
-pub struct PeerId([u8; 32], [u8; 32]);
+/// Converts a peer index into an index of a vector with one entry per peer.
+///
+/// Returns `None` if the peer index does not fit into a `usize`, which is possible only on
+/// targets where `usize` is smaller than `PeerIndex`. Such a peer index cannot refer to any
+/// peer, because a vector cannot have more than `usize::max_value()` entries.
+#[cfg(feature = "std")]
+fn peer_index_to_usize(peer_index: PeerIndex) -> Option<usize> {
+    usize::try_from(peer_index).ok()
+}
+
+/// Converts an index of a vector with one entry per peer into a peer index.
+///
+/// Returns `None` if the index does not fit into a `PeerIndex`, i.e., if there are more peers
+/// than peer indices.
+#[cfg(feature = "std")]
+fn usize_to_peer_index(index: usize) -> Option<PeerIndex> {
+    PeerIndex::try_from(index).ok()
+}
+
+/// The ID of a session, which is part of the header of every message
+///
+/// The ID is serialized as 32 contiguous bytes. `SessionId`, `Commitment` and the symmetric
+/// keys are distinct types, even though all of them are 32 bytes:
+///
+/// ```compile_fail
+/// let commitment = dicemix::Commitment::from_bytes(&[0; 32]);
+/// let session_id: dicemix::SessionId = commitment;
+/// ```
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct SessionId([u8; 32]);
+
+#[cfg(feature = "std")]
+impl SessionId {
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        SessionId(*bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Derives the session ID from the set of peers and the options of the session.
+    ///
+    /// This allows all peers to agree on the session ID without a coordinator. The ID is a
+    /// BLAKE2s hash of the compressed long-term verification keys of the peers, sorted by their
+    /// bytes so that the order of `peers` does not matter, followed by the options.
+    pub fn derive(peers: &[state::Peer], options: &Options) -> SessionId {
+        let mut ltvks: Vec<_> = peers.iter()
+            .map(|peer| peer.ltvk().serialize_vec(&SECP256K1, true))
+            .collect();
+        ltvks.sort();
+
+        let mut hasher = Blake2s::default();
+        hasher.input(&(ltvks.len() as u64).to_le_bytes());
+        for ltvk in ltvks.iter() {
+            hasher.input(ltvk);
+        }
+        hasher.input(&options.to_bytes());
+
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&hasher.result());
+        SessionId(bytes)
+    }
+}
+
+/// A commitment to the vector of the exponential DC-net
+///
+/// The commitment is serialized as 32 contiguous bytes.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Commitment([u8; 32]);
+
+#[cfg(feature = "std")]
+impl Commitment {
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Commitment(*bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Compares two byte strings in time that depends only on their lengths.
+///
+/// This is used for comparing secrets, where the position of the first differing byte must
+/// not leak through the timing of the comparison.
+#[cfg(feature = "std")]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    // Slices of different lengths compare unequal without looking at the bytes.
+    a.ct_eq(b).into()
+}
+
+/// A symmetric key shared between two peers
+///
+/// The key is zeroed when it is dropped. Since the key is not `Copy`, every copy is explicit
+/// and zeroed as well. Keys are compared in constant time, and `Debug` does not print them.
+#[cfg(feature = "std")]
+#[derive(Clone, Deserialize)]
+pub struct SymmetricKey([u8; 32]);
+
+#[cfg(feature = "std")]
+impl fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SymmetricKey(..)")
+    }
+}
+
+// Keys are serialized only once they have been revealed, i.e., as part of a `Reveal` payload
+// and of the histories in a checkpoint, where they are public anyway. The encoding is the one
+// that `#[derive(Serialize)]` would produce, i.e., the 32 bytes of the key.
+#[cfg(feature = "std")]
+impl Serialize for SymmetricKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct("SymmetricKey", &self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PartialEq for SymmetricKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Eq for SymmetricKey {}
+
+// Equal keys have equal bytes, so this is consistent with the constant-time `eq()`.
+#[cfg(feature = "std")]
+impl Hash for SymmetricKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+#[cfg(feature = "std")]
+impl SymmetricKey {
+    fn from_bytes(bytes: &[u8; 32]) -> Self {
+        SymmetricKey(*bytes)
+    }
+
+    /// Derives the key shared between our ephemeral secret key and another peer's ephemeral
+    /// public key, by hashing the ECDH shared secret with BLAKE2s.
+    ///
+    /// Both peers of a pair derive the same key. This is the only derivation of symmetric keys,
+    /// so revealed keys can be checked by deriving them again and comparing with `ct_eq()`.
+    pub fn derive(sk: &secp256k1::key::SecretKey, pk: &PublicKey) -> Self {
+        let mut shared_secret = secp256k1::ecdh::SharedSecret::new(&SECP256K1, pk, sk);
+        let mut hasher = Blake2s::default();
+        hasher.input(&shared_secret[0..32]);
+        // The bindings offer no way to wipe a shared secret, but it is a plain array of bytes
+        // that we own, so we overwrite it through a pointer derived from a mutable borrow.
+        unsafe {
+            let ptr = &mut shared_secret as *mut secp256k1::ecdh::SharedSecret as *mut u8;
+            slice::from_raw_parts_mut(ptr, mem::size_of::<secp256k1::ecdh::SharedSecret>()).zeroize();
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&hasher.result());
+        let derived = SymmetricKey::from_bytes(&key);
+        key.zeroize();
+        derived
+    }
+
+    /// Compares two keys in constant time, see `constant_time_eq()`.
+    ///
+    /// This is what `==` does as well, but spelling it out makes the intent explicit where
+    /// secrets are compared.
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+
+    /// Returns the bytes of the key, e.g., to seed the pad generator of the DC-nets.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for SymmetricKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
 
+/// A peer ID
+///
+/// The ID is serialized as 64 contiguous bytes.
+// serde derives its traits only for arrays of up to 32 elements, so we implement them manually.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PeerId([u8; 64]);
+
+#[cfg(feature = "std")]
+impl PeerId {
+    pub fn from_bytes(bytes: &[u8; 64]) -> Self {
+        PeerId(*bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 64] {
+        &self.0
+    }
+
+    /// Derives the peer ID from the long-term verification key of the peer.
+    ///
+    /// This binds the identity of the peer to the key that it uses to sign its protocol
+    /// messages (see `io`). Each half of the ID is a BLAKE2s hash of the compressed key,
+    /// prefixed with the index of the half for domain separation.
+    pub fn from_ltvk(pk: &PublicKey) -> PeerId {
+        let ser = pk.serialize_vec(&SECP256K1, true);
+        let mut bytes = [0u8; 64];
+        for (i, half) in bytes.chunks_mut(32).enumerate() {
+            let mut hasher = Blake2s::default();
+            hasher.input(&[i as u8]);
+            hasher.input(&ser);
+            half.copy_from_slice(&hasher.result());
+        }
+        PeerId(bytes)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Serialize for PeerId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for b in self.0.iter() {
+            tuple.serialize_element(b)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for PeerId {
+    fn deserialize<D>(deserializer: D) -> Result<PeerId, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        use serde::de;
+
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = PeerId;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                formatter.write_str("64 bytes")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<PeerId, A::Error>
+                where A: de::SeqAccess<'de>
+            {
+                let mut bytes = [0u8; 64];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                Ok(PeerId(bytes))
+            }
+        }
+
+        deserializer.deserialize_tuple(64, Visitor)
+    }
+}
+
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Variant {
     PlainEcdsa,
     ValueShuffleElementsEcdsa,
-    // TODO This requires support for early confirmation data, i.e., confirmation data before
-    // the actual confirmation phase.
-    // PlainSchnorrMulti,
+    /// Peers commit to their signing nonces in the main DC-net and aggregate their partial
+    /// signatures into a Schnorr multisignature.
+    PlainSchnorrMulti,
+    // TODO This requires combining the scalar extension with early confirmation data.
     // ValueShuffleElementsSchnorrMulti.
 }
 
+#[cfg(feature = "std")]
+impl Variant {
+    /// Returns `true` if peers send early confirmation data in the main DC-net.
+    fn has_early_confirm(self) -> bool {
+        match self {
+            Variant::PlainEcdsa | Variant::ValueShuffleElementsEcdsa => false,
+            Variant::PlainSchnorrMulti => true,
+        }
+    }
+}
+
+/// The extension of the main DC-net
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ExtensionKind {
+    None,
+    /// Additive DC-net over secp256k1 scalars, used by the ValueShuffle variants
+    DcAddSecp256k1Scalar,
+}
+
+#[cfg(feature = "std")]
+impl ExtensionKind {
+    const ALL: [ExtensionKind; 2] = [ExtensionKind::None, ExtensionKind::DcAddSecp256k1Scalar];
+
+    fn discriminant(self) -> ExtensionVariant {
+        match self {
+            ExtensionKind::None => mem::discriminant(&messages::Extension::None),
+            ExtensionKind::DcAddSecp256k1Scalar => {
+                mem::discriminant(&messages::Extension::DcAddSecp256k1Scalar(vec![]))
+            },
+        }
+    }
+
+    /// Returns `true` if `extension` is of this kind.
+    fn matches(self, extension: &messages::Extension) -> bool {
+        mem::discriminant(extension) == self.discriminant()
+    }
+}
+
+/// The extension of a payload differs from the extension of the session, see
+/// `Options::validate_payload()`.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct VariantMismatch;
+
+/// The reason why a set of options is invalid
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OptionsError {
+    /// The extension cannot be used with the variant.
+    IncompatibleExtension,
+    /// The minimum number of peers is smaller than two.
+    MinPeersTooSmall,
+    /// The size of a slot is zero or larger than `MAX_SLOT_SIZE`.
+    InvalidSlotBytes,
+}
+
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Options {
     variant: Variant,
     extension_variant: ExtensionVariant,
+    min_peers: usize,
+    slot_bytes: usize,
+    retain_frames: bool,
 }
 
+#[cfg(feature = "std")]
 impl Options {
     fn new_simple(variant: Variant) -> Self {
-        match variant {
-            Variant::PlainEcdsa => {
-                Self {
-                    variant: Variant::PlainEcdsa,
-                    extension_variant: mem::discriminant(&messages::Extension::None),
-                }
-            },
-            Variant::ValueShuffleElementsEcdsa => {
-                Self {
-                    variant: Variant::ValueShuffleElementsEcdsa,
-                    extension_variant: mem::discriminant(&messages::Extension::DcAddSecp256k1Scalar()),
-                }
-            },
+        Self {
+            variant,
+            extension_variant: default_extension(variant).discriminant(),
+            min_peers: state::MIN_PEERS,
+            slot_bytes: messages::MAX_SLOT_SIZE,
+            retain_frames: false,
         }
     }
 
-    fn variant(&self) -> Variant {
+    pub fn variant(&self) -> Variant {
         self.variant
     }
 
-    fn extension_variant(&self) -> ExtensionVariant {
+    pub fn extension_variant(&self) -> ExtensionVariant {
         self.extension_variant
     }
+
+    /// Returns the minimum number of peers with which a run can take place.
+    ///
+    /// The anonymity set of a peer consists of the honest peers that remain in a successful
+    /// run, so a higher minimum guarantees a larger anonymity set if enough peers are honest.
+    /// The execution is aborted as soon as fewer peers remain, see `RunOutcome::Aborted`.
+    ///
+    /// Like all options, the minimum is part of the session ID, so all peers of a session agree
+    /// on it and abort at the same point.
+    pub fn min_peers(&self) -> usize {
+        self.min_peers
+    }
+
+    /// Returns the maximum size of a slot of the main DC-net in bytes.
+    ///
+    /// The number of slots is not an option: every run has one slot per peer at the beginning
+    /// of the run.
+    pub fn slot_bytes(&self) -> usize {
+        self.slot_bytes
+    }
+
+    /// Returns `true` if the signed frames of the DC-net payloads are kept until the end of a
+    /// run, so that the signed messages of malicious peers can be presented as proof.
+    ///
+    /// This is disabled by default, because it roughly doubles the memory needed for a run.
+    pub fn retain_frames(&self) -> bool {
+        self.retain_frames
+    }
+
+    /// Checks that a payload carries the extension of the session in the main DC-net.
+    ///
+    /// Payloads of the other phases carry no extension and always pass. The state machine of a
+    /// run performs this check on every `DcMain` payload and excludes peers that fail it.
+    pub fn validate_payload(&self, payload: &messages::Payload) -> Result<(), VariantMismatch> {
+        match *payload {
+            messages::Payload::DcMain(ref pay) if !self.extension_kind().matches(&pay.extension) => {
+                Err(VariantMismatch)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn extension_kind(&self) -> ExtensionKind {
+        *ExtensionKind::ALL.iter()
+            .find(|kind| kind.discriminant() == self.extension_variant)
+            .expect("unknown extension variant")
+    }
+
+    /// Returns a canonical encoding of all options.
+    ///
+    /// The sizes are encoded as `u64`, so the encoding does not depend on the size of `usize`.
+    fn to_bytes(self) -> Vec<u8> {
+        let options = (self.variant, self.extension_kind(), self.min_peers as u64, self.slot_bytes as u64,
+                       self.retain_frames);
+        bincode::serialize(&options, bincode::Infinite).expect("options are always serializable")
+    }
 }
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+fn default_extension(variant: Variant) -> ExtensionKind {
+    match variant {
+        Variant::PlainEcdsa | Variant::PlainSchnorrMulti => ExtensionKind::None,
+        Variant::ValueShuffleElementsEcdsa => ExtensionKind::DcAddSecp256k1Scalar,
+    }
+}
+
+/// A builder for the `Options` of a session
+///
+/// If no extension is set, the default extension of the variant is used.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OptionsBuilder {
+    variant: Variant,
+    extension: Option<ExtensionKind>,
+    min_peers: usize,
+    slot_bytes: usize,
+    retain_frames: bool,
+}
+
+#[cfg(feature = "std")]
+impl OptionsBuilder {
+    pub fn new() -> Self {
+        Self {
+            variant: Variant::PlainEcdsa,
+            extension: None,
+            min_peers: state::MIN_PEERS,
+            slot_bytes: messages::MAX_SLOT_SIZE,
+            retain_frames: false,
+        }
+    }
+
+    pub fn variant(&mut self, variant: Variant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn extension(&mut self, extension: ExtensionKind) -> &mut Self {
+        self.extension = Some(extension);
+        self
+    }
+
+    /// Sets the minimum number of peers, which defaults to two, see `Options::min_peers()`.
+    pub fn min_peers(&mut self, min_peers: usize) -> &mut Self {
+        self.min_peers = min_peers;
+        self
+    }
+
+    /// Sets the maximum size of a slot, which defaults to `MAX_SLOT_SIZE`, see
+    /// `Options::slot_bytes()`.
+    pub fn slot_bytes(&mut self, slot_bytes: usize) -> &mut Self {
+        self.slot_bytes = slot_bytes;
+        self
+    }
+
+    /// Sets whether signed frames are retained, which is disabled by default, see
+    /// `Options::retain_frames()`.
+    pub fn retain_frames(&mut self, retain_frames: bool) -> &mut Self {
+        self.retain_frames = retain_frames;
+        self
+    }
+
+    pub fn build(&self) -> Result<Options, OptionsError> {
+        // Every variant currently supports only its default extension.
+        let default = default_extension(self.variant);
+        match self.extension {
+            Some(extension) if extension != default => return Err(OptionsError::IncompatibleExtension),
+            _ => {},
+        }
+        if self.min_peers < state::MIN_PEERS {
+            return Err(OptionsError::MinPeersTooSmall);
+        }
+        if self.slot_bytes == 0 || self.slot_bytes > messages::MAX_SLOT_SIZE {
+            return Err(OptionsError::InvalidSlotBytes);
+        }
+        Ok(Options {
+            min_peers: self.min_peers,
+            slot_bytes: self.slot_bytes,
+            retain_frames: self.retain_frames,
+            ..Options::new_simple(self.variant)
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for OptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
+    use bincode;
+
+    use super::*;
+
+    #[test]
+    fn peer_index_conversion() {
+        assert_eq!(peer_index_to_usize(0), Some(0));
+        assert_eq!(peer_index_to_usize(7), Some(7));
+        // PeerIndex is a u32, which fits into usize on all targets with at least 32-bit pointers.
+        #[cfg(not(target_pointer_width = "16"))]
+        assert_eq!(peer_index_to_usize(PeerIndex::max_value()), Some(PeerIndex::max_value() as usize));
+        #[cfg(target_pointer_width = "16")]
+        assert_eq!(peer_index_to_usize(PeerIndex::max_value()), None);
+
+        assert_eq!(usize_to_peer_index(0), Some(0));
+        assert_eq!(usize_to_peer_index(7), Some(7));
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(usize_to_peer_index(PeerIndex::max_value() as usize + 1), None);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(usize_to_peer_index(usize::max_value()), None);
+    }
+
     #[test]
     fn it_works() {
     }
+
+    #[test]
+    fn hash_consistent_with_eq() {
+        use std::collections::HashSet;
+        use std::collections::hash_map::DefaultHasher;
+
+        fn check<T: Hash + Eq + Clone>(a: T, b: T) {
+            let hash = |x: &T| {
+                let mut hasher = DefaultHasher::new();
+                x.hash(&mut hasher);
+                hasher.finish()
+            };
+            assert_eq!(hash(&a), hash(&a.clone()));
+            let mut set = HashSet::new();
+            assert!(set.insert(a.clone()));
+            assert!(!set.insert(a.clone()));
+            assert!(set.contains(&a) && !set.contains(&b));
+            assert!(set.insert(b.clone()));
+            assert_eq!(set.len(), 2);
+        }
+
+        check(PeerId::from_bytes(&[1; 64]), PeerId::from_bytes(&[2; 64]));
+        check(SessionId([1; 32]), SessionId([2; 32]));
+        check(Commitment([1; 32]), Commitment([2; 32]));
+        check(SymmetricKey([1; 32]), SymmetricKey([2; 32]));
+    }
+
+    #[test]
+    fn peer_id_roundtrip() {
+        let mut bytes = [0u8; 64];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let peer_id = PeerId::from_bytes(&bytes);
+        assert_eq!(peer_id.as_bytes()[..], bytes[..]);
+
+        let ser = bincode::serialize(&peer_id, bincode::Infinite).unwrap();
+        let peer_id2: PeerId = bincode::deserialize(&ser).unwrap();
+        assert_eq!(peer_id, peer_id2);
+    }
+
+    #[test]
+    fn symmetric_key_not_printed() {
+        let key = SymmetricKey([0xab; 32]);
+        assert_eq!(format!("{:?}", key), "SymmetricKey(..)");
+
+        // Revealed keys are serialized as their plain bytes.
+        let ser = bincode::serialize(&key, bincode::Infinite).unwrap();
+        assert_eq!(ser, vec![0xab; 32]);
+        let key2: SymmetricKey = bincode::deserialize(&ser).unwrap();
+        assert!(key.ct_eq(&key2));
+    }
+
+    #[test]
+    fn peer_id_from_ltvk() {
+        let pk = |b| {
+            let sk = messages::SecretKey::from_slice(&SECP256K1, &[b; 32]).unwrap();
+            PublicKey::from_secret_key(&SECP256K1, &sk).unwrap()
+        };
+        let id1 = PeerId::from_ltvk(&pk(1));
+        assert_eq!(id1, PeerId::from_ltvk(&pk(1)));
+        assert!(id1 != PeerId::from_ltvk(&pk(2)));
+        assert!(id1.as_bytes()[..32] != id1.as_bytes()[32..]);
+        // Pin the derivation, the ID must not change between versions.
+        assert_eq!(id1.as_bytes()[..8], [0xe2, 0x98, 0xd5, 0x04, 0xf4, 0x10, 0x23, 0xeb]);
+    }
+
+    #[test]
+    fn build_options() {
+        let options = OptionsBuilder::new()
+            .variant(Variant::ValueShuffleElementsEcdsa)
+            .build()
+            .unwrap();
+        assert_eq!(options, Options::new_simple(Variant::ValueShuffleElementsEcdsa));
+        assert_eq!(options.variant(), Variant::ValueShuffleElementsEcdsa);
+        assert_eq!(options.extension_variant(),
+                   mem::discriminant(&messages::Extension::DcAddSecp256k1Scalar(vec![])));
+
+        let options = OptionsBuilder::new().extension(ExtensionKind::None).build().unwrap();
+        assert_eq!(options.variant(), Variant::PlainEcdsa);
+        assert_eq!(options.extension_variant(), mem::discriminant(&messages::Extension::None));
+    }
+
+    #[test]
+    fn build_options_incompatible() {
+        let result = OptionsBuilder::new()
+            .variant(Variant::PlainEcdsa)
+            .extension(ExtensionKind::DcAddSecp256k1Scalar)
+            .build();
+        assert_eq!(result, Err(OptionsError::IncompatibleExtension));
+
+        let result = OptionsBuilder::new()
+            .variant(Variant::ValueShuffleElementsEcdsa)
+            .extension(ExtensionKind::None)
+            .build();
+        assert_eq!(result, Err(OptionsError::IncompatibleExtension));
+    }
+
+    #[test]
+    fn validate_payload() {
+        use messages::{Payload, DcMain, Extension, Confirm};
+        use dc::scalar::Scalar;
+        use dc::xor::XorVec;
+
+        let sk = messages::SecretKey::from_slice(&SECP256K1, &[0x01; 32]).unwrap();
+        let dc_main = |extension| Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![]),
+            ke_pk: PublicKey::from_secret_key(&SECP256K1, &sk).unwrap(),
+            extension,
+            early_confirm: None,
+        });
+        let none = dc_main(Extension::None);
+        let scalar = dc_main(Extension::DcAddSecp256k1Scalar(vec![Scalar::default()]));
+
+        for &variant in [Variant::PlainEcdsa, Variant::ValueShuffleElementsEcdsa, Variant::PlainSchnorrMulti].iter() {
+            let options = Options::new_simple(variant);
+            let (matching, mismatched) = match default_extension(variant) {
+                ExtensionKind::None => (&none, &scalar),
+                ExtensionKind::DcAddSecp256k1Scalar => (&scalar, &none),
+            };
+            assert_eq!(options.validate_payload(matching), Ok(()));
+            assert_eq!(options.validate_payload(mismatched), Err(VariantMismatch));
+            assert_eq!(options.validate_payload(&Payload::Confirm(Confirm { data: vec![] })), Ok(()));
+        }
+    }
+
+    #[test]
+    fn build_options_min_peers() {
+        assert_eq!(OptionsBuilder::new().build().unwrap().min_peers(), 2);
+        assert_eq!(OptionsBuilder::new().min_peers(5).build().unwrap().min_peers(), 5);
+        assert_eq!(OptionsBuilder::new().min_peers(1).build(), Err(OptionsError::MinPeersTooSmall));
+    }
+
+    #[test]
+    fn build_options_slot_bytes() {
+        assert_eq!(OptionsBuilder::new().build().unwrap().slot_bytes(), messages::MAX_SLOT_SIZE);
+        assert_eq!(OptionsBuilder::new().slot_bytes(32).build().unwrap().slot_bytes(), 32);
+        assert_eq!(OptionsBuilder::new().slot_bytes(0).build(), Err(OptionsError::InvalidSlotBytes));
+        let too_large = OptionsBuilder::new().slot_bytes(messages::MAX_SLOT_SIZE + 1).build();
+        assert_eq!(too_large, Err(OptionsError::InvalidSlotBytes));
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(&[0x17; 32], &[0x17; 32]));
+        assert!(!constant_time_eq(&[0x17; 32], &[0x17; 31]));
+        for i in 0..32 {
+            let mut other = [0x17; 32];
+            other[i] ^= 0x80;
+            assert!(!constant_time_eq(&[0x17; 32], &other));
+            assert!(SymmetricKey([0x17; 32]) != SymmetricKey(other));
+        }
+        assert_eq!(SymmetricKey([0x17; 32]), SymmetricKey([0x17; 32]));
+        for &(a, b) in [([0x17; 32], [0x17; 32]), ([0x17; 32], [0x18; 32]), ([0; 32], [0; 32])].iter() {
+            assert_eq!(SymmetricKey(a).ct_eq(&SymmetricKey(b)), a == b);
+        }
+
+        let blame = |b| {
+            let sk = messages::SecretKey::from_slice(&SECP256K1, &[b; 32]).unwrap();
+            messages::Blame { ke_sk: messages::Secret::new(sk) }
+        };
+        assert_eq!(blame(1), blame(1));
+        assert!(blame(1) != blame(2));
+    }
+
+    #[test]
+    fn derive_session_id() {
+        let peer = |b| {
+            let sk = messages::SecretKey::from_slice(&SECP256K1, &[b; 32]).unwrap();
+            let pk = PublicKey::from_secret_key(&SECP256K1, &sk).unwrap();
+            state::Peer::new(PeerId::from_ltvk(&pk), pk)
+        };
+        let options = OptionsBuilder::new().build().unwrap();
+        let peers = vec![peer(1), peer(2), peer(3)];
+        let id = SessionId::derive(&peers, &options);
+
+        let permuted = vec![peer(3), peer(1), peer(2)];
+        assert_eq!(SessionId::derive(&permuted, &options), id);
+
+        let other_key = vec![peer(1), peer(2), peer(4)];
+        assert!(SessionId::derive(&other_key, &options) != id);
+        assert!(SessionId::derive(&peers[..2], &options) != id);
+
+        let other_options = OptionsBuilder::new()
+            .variant(Variant::ValueShuffleElementsEcdsa)
+            .build()
+            .unwrap();
+        assert!(SessionId::derive(&peers, &other_options) != id);
+        let other_options = OptionsBuilder::new().variant(Variant::PlainSchnorrMulti).build().unwrap();
+        assert!(SessionId::derive(&peers, &other_options) != id);
+        let other_options = OptionsBuilder::new().min_peers(3).build().unwrap();
+        assert!(SessionId::derive(&peers, &other_options) != id);
+        let other_options = OptionsBuilder::new().slot_bytes(16).build().unwrap();
+        assert!(SessionId::derive(&peers, &other_options) != id);
+        let other_options = OptionsBuilder::new().retain_frames(true).build().unwrap();
+        assert!(SessionId::derive(&peers, &other_options) != id);
+    }
+
+    #[test]
+    fn key_encoding_irrelevant() {
+        let sk = messages::SecretKey::from_slice(&SECP256K1, &[0x57; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&SECP256K1, &sk).unwrap();
+        let compressed = PublicKey::from_slice(&SECP256K1, &pk.serialize_vec(&SECP256K1, true)).unwrap();
+        let uncompressed = PublicKey::from_slice(&SECP256K1, &pk.serialize_vec(&SECP256K1, false)).unwrap();
+        assert_eq!(compressed, uncompressed);
+        assert_eq!(messages::parse_public_key(&pk.serialize_vec(&SECP256K1, true)), Ok(uncompressed));
+
+        assert_eq!(PeerId::from_ltvk(&compressed), PeerId::from_ltvk(&uncompressed));
+        let options = OptionsBuilder::new().build().unwrap();
+        let session_id = |pk| SessionId::derive(&[state::Peer::new(PeerId::from_ltvk(&pk), pk)], &options);
+        assert_eq!(session_id(compressed), session_id(uncompressed));
+    }
+
+    #[test]
+    fn peer_id_encoding() {
+        // The previous representation of a peer ID
+        #[derive(Serialize)]
+        struct OldPeerId([u8; 32], [u8; 32]);
+
+        let mut bytes = [0x11u8; 64];
+        bytes[32..].copy_from_slice(&[0x22u8; 32]);
+        let old = OldPeerId([0x11; 32], [0x22; 32]);
+
+        let ser = bincode::serialize(&PeerId::from_bytes(&bytes), bincode::Infinite).unwrap();
+        assert_eq!(ser, bincode::serialize(&old, bincode::Infinite).unwrap());
+        assert_eq!(ser[..], bytes[..]);
+    }
 }
@@ -2,18 +2,121 @@
 //!
 //! All protocol messages are serialized by serde according to the bincode data format. For
 //! transmission on the wire, `tokio_io::codec::length_delimited` is used to prepend protocol
-//! messages by an additional length header, thereby creating frames. It is crucial to
+//! messages by an additional length header, thereby creating frames. It is crucial to encode
+//! and decode with the same bincode configuration, so all code must use `encode()` and
+//! `decode()` instead of calling bincode directly.
 //!
 //! The main type `Message` and the types in its fields are dump containers, which are not
 //! responsible for any protocol logic (except for syntactic validation including validation of
 //! `PublicKey` and `SecretKey` fields). Consequently, all fields of `Message` and all fields of
 //! its contained types such as `Header` and `Payload` are public.
 
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::slice;
+use secp256k1;
 pub use secp256k1::key::{PublicKey, SecretKey};
-use ::{SessionId, PeerIndex, SymmetricKey, SequenceNum, Commitment};
+use secp256k1::constants::{COMPRESSED_PUBLIC_KEY_SIZE, SECRET_KEY_SIZE, COMPACT_SIGNATURE_SIZE};
+use zeroize::Zeroize;
+use blake2::{Blake2s, Digest};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use bincode;
+
+use ::{SessionId, PeerIndex, SymmetricKey, SequenceNum, Commitment, peer_index_to_usize};
 
 use dc::xor::XorVec;
 use dc::fp::Fp;
+use dc::scalar::Scalar;
+
+/// Version of the wire format, peers reject messages with a different version
+pub const PROTOCOL_VERSION: u16 = 3;
+
+/// Maximum size of a slot in the main DC-net in bytes
+pub const MAX_SLOT_SIZE: usize = 1 << 16;
+
+/// Maximum size of a serialized message (without signature) in bytes
+pub const MAX_MESSAGE_SIZE: usize = 1 << 24;
+
+/// Serializes a value, e.g., a `Message`, in the wire format.
+///
+/// The encoding is not size-limited, because we trust our own values. Peers reject messages
+/// larger than `MAX_MESSAGE_SIZE`.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    bincode::serialize(value, bincode::Infinite).expect("values are always serializable")
+}
+
+/// Deserializes a value, e.g., a `Message`, from the beginning of `bytes` in the wire format.
+///
+/// Trailing bytes are ignored. The size limit ensures that we never read beyond `bytes` or
+/// `MAX_MESSAGE_SIZE`, even if the encoding claims to contain a vector of absurd length.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    let limit = cmp::min(bytes.len(), MAX_MESSAGE_SIZE) as u64;
+    bincode::deserialize_from(&mut &bytes[..], bincode::Bounded(limit))
+}
+
+/// The reason why a payload is syntactically invalid
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ValidationError {
+    /// A vector has more elements than there are peers.
+    TooManyElements,
+    /// A slot of the main DC-net is larger than `MAX_SLOT_SIZE`.
+    SlotTooLarge,
+    /// A peer index is out of range.
+    InvalidPeerIndex,
+    /// The early confirmation data is larger than `MAX_SLOT_SIZE`.
+    EarlyConfirmTooLarge,
+    /// The confirmation data is larger than `MAX_SLOT_SIZE`.
+    ConfirmTooLarge,
+    /// A public key is not a valid point.
+    ///
+    /// Encodings of invalid points are deserialized as `PublicKey::new()`, so this catches both
+    /// invalid keys received from peers and those constructed locally.
+    InvalidKey,
+}
+
+/// The reason why a serialized public key is invalid
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// The key is not `COMPRESSED_PUBLIC_KEY_SIZE` bytes long.
+    WrongLength,
+    /// The bytes do not encode a point on the curve.
+    InvalidKey,
+}
+
+/// Parses a compressed public key, e.g., the long-term verification key of a peer.
+///
+/// Only compressed keys are accepted, because keys are compressed on the wire. The identity
+/// has no compressed encoding and is rejected like all other invalid encodings.
+///
+/// Note that a `PublicKey` does not remember its encoding, so keys parsed by other means, e.g.,
+/// from an uncompressed encoding, compare equal to the same key parsed here. Everything derived
+/// from a key (signature verification, `PeerId::from_ltvk()` and `SessionId::derive()`) uses
+/// the compressed encoding, so it does not depend on how the key has been ingested.
+pub fn parse_public_key(bytes: &[u8]) -> Result<PublicKey, ParseError> {
+    if bytes.len() != COMPRESSED_PUBLIC_KEY_SIZE {
+        return Err(ParseError::WrongLength);
+    }
+    PublicKey::from_slice(&::SECP256K1, bytes).map_err(|_| ParseError::InvalidKey)
+}
+
+// Deserializes a public key in a payload.
+//
+// An encoding of the right length that is not a valid point is deserialized as the invalid key
+// `PublicKey::new()` instead of failing, so that `Payload::validate()` reports it as
+// `ValidationError::InvalidKey` rather than the whole message being malformed.
+fn deserialize_public_key<'de, D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+    let bytes: Vec<u8> = ::serde::Deserialize::deserialize(deserializer)?;
+    match parse_public_key(&bytes) {
+        Ok(key) => Ok(key),
+        Err(ParseError::InvalidKey) => Ok(PublicKey::new()),
+        Err(ParseError::WrongLength) => {
+            Err(::serde::de::Error::invalid_length(bytes.len(), &"a compressed public key"))
+        },
+    }
+}
 
 /// A protocol message
 ///
@@ -26,11 +129,28 @@ pub struct Message {
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Header {
+    pub version: u16,
     pub session_id: SessionId, // just for consistency checks
     pub peer_index: PeerIndex,
     pub sequence_num: SequenceNum, // just for consistency checks
 }
 
+/// Size of the bincode encoding of a `Header`
+pub const HEADER_SERIALIZED_LEN: usize = mem::size_of::<u16>() + mem::size_of::<SessionId>()
+    + mem::size_of::<PeerIndex>() + mem::size_of::<SequenceNum>();
+
+impl Header {
+    /// Creates a header for the current protocol version.
+    pub fn new(session_id: SessionId, peer_index: PeerIndex, sequence_num: SequenceNum) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            session_id,
+            peer_index,
+            sequence_num,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Payload {
     KeyExchange(KeyExchange),
@@ -41,8 +161,112 @@ pub enum Payload {
     Reveal(Reveal),
 }
 
+impl Payload {
+    /// Checks that the sizes of all vectors in the payload are within the bounds given by the
+    /// number of peers.
+    ///
+    /// This prevents peers from forcing us to process overly large payloads.
+    pub fn validate(&self, num_peers: usize) -> Result<(), ValidationError> {
+        let check_len = |len: usize| {
+            if len <= num_peers { Ok(()) } else { Err(ValidationError::TooManyElements) }
+        };
+
+        let check_key = |key: &PublicKey| {
+            if key.is_valid() { Ok(()) } else { Err(ValidationError::InvalidKey) }
+        };
+
+        match *self {
+            Payload::KeyExchange(ref pay) => check_key(&pay.ke_pk),
+            Payload::Blame(_) => Ok(()),
+            Payload::Confirm(ref pay) => {
+                if pay.data.len() > MAX_SLOT_SIZE { Err(ValidationError::ConfirmTooLarge) } else { Ok(()) }
+            },
+            Payload::DcExponential(ref pay) => check_len(pay.dc_exp.len()),
+            Payload::DcMain(ref pay) => {
+                check_key(&pay.ke_pk)?;
+                check_len(pay.dc_xor.len())?;
+                if pay.dc_xor.iter().any(|slot| slot.len() > MAX_SLOT_SIZE) {
+                    return Err(ValidationError::SlotTooLarge);
+                }
+                if pay.early_confirm.as_ref().map_or(false, |data| data.len() > MAX_SLOT_SIZE) {
+                    return Err(ValidationError::EarlyConfirmTooLarge);
+                }
+                match pay.extension {
+                    Extension::None => Ok(()),
+                    Extension::DcAddSecp256k1Scalar(ref scalars) => check_len(scalars.len()),
+                }
+            },
+            Payload::Reveal(ref pay) => {
+                check_len(pay.keys.len())?;
+                let in_range = |peer_index| peer_index_to_usize(peer_index).map_or(false, |index| index < num_peers);
+                if pay.keys.iter().any(|&(peer_index, _)| !in_range(peer_index)) {
+                    return Err(ValidationError::InvalidPeerIndex);
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns an upper bound on the size of the bincode encoding of any payload that passes
+    /// `validate(num_peers)` and whose slots in the main DC-net have at most `slot_bytes` bytes.
+    ///
+    /// This can be used to set the frame limits of the broadcast mechanism.
+    pub fn max_serialized_len(num_peers: usize, slot_bytes: usize) -> usize {
+        let bounds = Self::serialized_len_bounds(num_peers, slot_bytes);
+        *[bounds.key_exchange, bounds.dc_exponential, bounds.dc_main,
+          bounds.blame, bounds.confirm, bounds.reveal].iter().max().unwrap()
+    }
+
+    /// Returns upper bounds on the size of the bincode encoding of each kind of payload, under
+    /// the same assumptions as `max_serialized_len()`.
+    pub fn serialized_len_bounds(num_peers: usize, slot_bytes: usize) -> SerializedLenBounds {
+        // bincode uses 4 bytes for enum tags, 1 byte for booleans and option tags, and
+        // 8 bytes for the length prefix of sequences.
+        const TAG: usize = 4;
+        const LEN: usize = 8;
+        // Public and secret keys are serialized as sequences.
+        const PUBLIC_KEY: usize = LEN + COMPRESSED_PUBLIC_KEY_SIZE;
+        const SECRET_KEY: usize = LEN + SECRET_KEY_SIZE;
+        const FP: usize = 16;
+        const SCALAR: usize = 32;
+
+        let slot_bytes = cmp::min(slot_bytes, MAX_SLOT_SIZE);
+        let key_exchange = PUBLIC_KEY;
+        let dc_exponential = mem::size_of::<Commitment>() + LEN + num_peers * FP;
+        let dc_main = 1
+            + LEN + num_peers * (LEN + slot_bytes)
+            + PUBLIC_KEY
+            + TAG + LEN + num_peers * SCALAR
+            + 1 + LEN + MAX_SLOT_SIZE;
+        let blame = SECRET_KEY;
+        let confirm = LEN + MAX_SLOT_SIZE;
+        let reveal = LEN + num_peers * (mem::size_of::<PeerIndex>() + mem::size_of::<SymmetricKey>());
+
+        SerializedLenBounds {
+            key_exchange: TAG + key_exchange,
+            dc_exponential: TAG + dc_exponential,
+            dc_main: TAG + dc_main,
+            blame: TAG + blame,
+            confirm: TAG + confirm,
+            reveal: TAG + reveal,
+        }
+    }
+}
+
+/// Upper bounds on the size of the bincode encoding of each kind of payload, including the tag
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SerializedLenBounds {
+    pub key_exchange: usize,
+    pub dc_exponential: usize,
+    pub dc_main: usize,
+    pub blame: usize,
+    pub confirm: usize,
+    pub reveal: usize,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct KeyExchange {
+    #[serde(deserialize_with = "deserialize_public_key")]
     pub ke_pk: PublicKey,
 }
 
@@ -52,31 +276,189 @@ pub struct DcExponential {
     pub dc_exp: Vec<Fp>,
 }
 
+const DC_EXPONENTIAL_COMMITMENT_PREFIX: &[u8; 32] = b"DICEMIX_DC_EXPONENTIAL_COMMIT___";
+
+impl DcExponential {
+    /// Creates a `DcExponential` payload committing to `dc_exp`.
+    pub fn new(dc_exp: Vec<Fp>) -> Self {
+        Self {
+            commitment: Self::compute_commitment(&dc_exp),
+            dc_exp,
+        }
+    }
+
+    /// Checks that the commitment matches the contents of `dc_exp`.
+    pub fn verify_commitment(&self) -> bool {
+        Self::compute_commitment(&self.dc_exp) == self.commitment
+    }
+
+    /// Computes the commitment to the DC-net vector.
+    ///
+    /// The commitment is the BLAKE2s hash of the 32-byte prefix
+    /// `DICEMIX_DC_EXPONENTIAL_COMMIT___`, followed by the number of elements as a 64-bit
+    /// little-endian integer, followed by the elements of `dc_exp` in order, each as 16
+    /// little-endian bytes. The part after the prefix is exactly the bincode encoding of `dc_exp`.
+    fn compute_commitment(dc_exp: &[Fp]) -> Commitment {
+        let mut hasher = Blake2s::default();
+        hasher.input(DC_EXPONENTIAL_COMMITMENT_PREFIX);
+        hasher.input(&(dc_exp.len() as u64).to_le_bytes());
+        for &x in dc_exp {
+            hasher.input(&u128::from(x).to_le_bytes());
+        }
+        let mut commitment = [0u8; 32];
+        commitment.copy_from_slice(&hasher.result());
+        Commitment(commitment)
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct DcMain {
+    /// Whether the peer has found its own message among the messages recovered from the
+    /// exponential DC-net
+    ///
+    /// A peer sets this to `false` if and only if its message is missing, which means that
+    /// some peer has disrupted the exponential DC-net. Every peer then treats the main DC-net
+    /// as failed and continues with the blame phase, after the reveal round if peers have gone
+    /// missing. The slots are still sent, but they carry no message. A peer that claims its
+    /// message is missing although it has been recovered is found malicious in the blame phase.
     pub ok: bool,
     // TODO This is not an efficient serialization.
     pub dc_xor: XorVec<XorVec<u8>>,
+    #[serde(deserialize_with = "deserialize_public_key")]
     pub ke_pk: PublicKey,
     pub extension: Extension,
+    /// Confirmation data sent ahead of the confirmation phase, e.g., a nonce commitment in the
+    /// multisignature variants
+    pub early_confirm: Option<Vec<u8>>,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Extension {
     None,
-    DcAddSecp256k1Scalar(/* TODO */),
+    /// Additive DC-net over secp256k1 scalars, used by the ValueShuffle variants
+    DcAddSecp256k1Scalar(Vec<Scalar>),
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Blame {
-    pub ke_sk: SecretKey,
+    pub ke_sk: Secret<SecretKey>,
+}
+
+/// Overwrites a secret key with zeros.
+///
+/// `SecretKey` does not zero itself on drop, so owners of secret keys need to call this or
+/// wrap the key in a `Secret`. The resulting key is invalid and must not be used anymore.
+pub fn scrub_secret_key(sk: &mut SecretKey) {
+    // The pointer is valid for the whole key and we hold the only reference.
+    unsafe { slice::from_raw_parts_mut(sk.as_mut_ptr(), SECRET_KEY_SIZE) }.zeroize();
+}
+
+/// Trait for secrets that can be overwritten with zeros
+pub trait Scrub {
+    fn scrub(&mut self);
+}
+
+impl Scrub for SecretKey {
+    fn scrub(&mut self) {
+        scrub_secret_key(self)
+    }
+}
+
+/// A secret that is overwritten with zeros when it is dropped
+///
+/// Every clone is scrubbed on its own, so cloning a payload does not leave copies of the secret
+/// behind. The wrapper is transparent on the wire, i.e., it is serialized like the secret itself,
+/// and it is not printed by `Debug`.
+#[derive(Clone)]
+pub struct Secret<T: Scrub>(T);
+
+impl<T: Scrub> Secret<T> {
+    pub fn new(secret: T) -> Self {
+        Secret(secret)
+    }
+}
+
+impl<T: Scrub> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Scrub> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.scrub();
+    }
+}
+
+// SecretKey compares with `==` on its bytes, which may exit early.
+impl PartialEq for Secret<SecretKey> {
+    fn eq(&self, other: &Self) -> bool {
+        ::constant_time_eq(&self.0[..], &other.0[..])
+    }
+}
+
+impl Eq for Secret<SecretKey> {}
+
+impl<T: Scrub> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl<T: Scrub + Serialize> Serialize for Secret<T> {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Scrub + ::serde::Deserialize<'de>> ::serde::Deserialize<'de> for Secret<T> {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
 }
 
+/// Confirmation of the outcome of the DC-nets
+///
+/// In the ECDSA variants, `data` is the concatenation of compact ECDSA signatures, one for
+/// each input the peer has contributed, where the `i`-th signature signs the `i`-th sighash.
+/// In the Schnorr variants, `data` holds a partial signature.
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Confirm {
     pub data: Vec<u8>,
 }
 
+impl Confirm {
+    /// Creates ECDSA confirmation data by signing each of the 32-byte `sighashes` with `sk`.
+    pub fn sign(sighashes: &[[u8; 32]], sk: &SecretKey) -> Self {
+        let mut data = Vec::with_capacity(sighashes.len() * COMPACT_SIGNATURE_SIZE);
+        for sighash in sighashes {
+            let msg = secp256k1::Message::from_slice(sighash).expect("a sighash has the size of a message digest");
+            let sig = ::SECP256K1.sign(&msg, sk).expect("the signing key is valid");
+            data.extend_from_slice(&sig.serialize_compact(&::SECP256K1));
+        }
+        Self { data }
+    }
+
+    /// Checks that `data` consists of valid ECDSA signatures of `sighashes` by `pk`.
+    pub fn verify(&self, pk: &PublicKey, sighashes: &[[u8; 32]]) -> bool {
+        if self.data.len() != sighashes.len() * COMPACT_SIGNATURE_SIZE {
+            return false;
+        }
+        self.data.chunks(COMPACT_SIGNATURE_SIZE).zip(sighashes.iter()).all(|(sig, sighash)| {
+            let msg = secp256k1::Message::from_slice(sighash).expect("a sighash has the size of a message digest");
+            match secp256k1::Signature::from_compact(&::SECP256K1, sig) {
+                Ok(sig) => ::SECP256K1.verify(&msg, &sig, pk).is_ok(),
+                Err(_) => false,
+            }
+        })
+    }
+}
+
+/// The symmetric keys that a peer shares with peers that have gone missing
+///
+/// The keys are compared in constant time, see `SymmetricKey`.
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Reveal {
     pub keys: Vec<(PeerIndex, SymmetricKey)>,
@@ -85,7 +467,6 @@ pub struct Reveal {
 #[cfg(test)]
 mod tests {
     use secp256k1::key::SecretKey;
-    use bincode;
 
     use super::*;
 
@@ -97,16 +478,352 @@ mod tests {
         assert!(ke_pk.is_valid());
 
         let payload = Payload::KeyExchange(KeyExchange {
-            ke_pk: ke_pk,
+            ke_pk,
+        });
+
+        roundtrip_serde_bincode(payload);
+    }
+
+    #[test]
+    fn roundtrip_dcmain_scalar_extension() {
+        let slice: [u8; 32] = [0x4f; 32];
+        let sk = SecretKey::from_slice(&::SECP256K1, &slice).unwrap();
+        let ke_pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let mut scalar_bytes = [0u8; 32];
+        scalar_bytes[31] = 0x2a;
+
+        let payload = Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![XorVec::from(vec![0x13u8; 8]); 2]),
+            ke_pk,
+            extension: Extension::DcAddSecp256k1Scalar(vec![
+                Scalar::from_bytes(&scalar_bytes).unwrap(),
+                Scalar::from_bytes(&[0x7f; 32]).unwrap(),
+            ]),
+            early_confirm: Some(vec![0x02; 33]),
         });
 
         roundtrip_serde_bincode(payload);
     }
 
+    #[test]
+    fn xor_slots_wire_compatible() {
+        let slot: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let dc_xor = XorVec::from(vec![XorVec::from(slot.clone()); 2]);
+        let encoded = encode(&dc_xor);
+
+        // Slots are written and read as byte blobs, which bincode encodes exactly like the
+        // derived encoding of the bytes, so the wire format is unchanged.
+        let plain = vec![slot.clone(); 2];
+        assert_eq!(encoded, encode(&plain));
+        assert_eq!(decode::<XorVec<XorVec<u8>>>(&encoded).unwrap(), dc_xor);
+        assert_eq!(decode::<Vec<Vec<u8>>>(&encoded).unwrap(), plain);
+    }
+
+    #[test]
+    fn byte_newtypes_as_arrays() {
+        let bytes = [0x3c; 32];
+        let raw = encode(&bytes);
+
+        let session_id = SessionId::from_bytes(&bytes);
+        let encoded = encode(&session_id);
+        assert_eq!(encoded, raw);
+        assert_eq!(decode::<SessionId>(&encoded).unwrap(), session_id);
+
+        let commitment = Commitment::from_bytes(&bytes);
+        let encoded = encode(&commitment);
+        assert_eq!(encoded, raw);
+        assert_eq!(decode::<Commitment>(&encoded).unwrap().as_bytes(), &bytes);
+
+        let key = SymmetricKey::from_bytes(&bytes);
+        let encoded = encode(&key);
+        assert_eq!(encoded, raw);
+        assert_eq!(decode::<SymmetricKey>(&encoded).unwrap(), key);
+    }
+
+    #[test]
+    fn parse_public_keys() {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x4f; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let ser = pk.serialize_vec(&::SECP256K1, true);
+        assert_eq!(parse_public_key(&ser), Ok(pk));
+
+        // Not a valid prefix
+        let mut malformed = ser.clone();
+        malformed[0] = 0x04;
+        assert_eq!(parse_public_key(&malformed), Err(ParseError::InvalidKey));
+        // Not a valid x coordinate
+        let mut malformed = [0xff; COMPRESSED_PUBLIC_KEY_SIZE];
+        malformed[0] = 0x02;
+        assert_eq!(parse_public_key(&malformed), Err(ParseError::InvalidKey));
+        assert_eq!(parse_public_key(&[0; COMPRESSED_PUBLIC_KEY_SIZE]), Err(ParseError::InvalidKey));
+
+        assert_eq!(parse_public_key(&ser[1..]), Err(ParseError::WrongLength));
+        assert_eq!(parse_public_key(&[]), Err(ParseError::WrongLength));
+        let uncompressed = pk.serialize_vec(&::SECP256K1, false);
+        assert_eq!(parse_public_key(&uncompressed), Err(ParseError::WrongLength));
+    }
+
+    #[test]
+    fn decode_invalid_key() {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x4f; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let mut encoded = encode(&KeyExchange { ke_pk: pk });
+        assert_eq!(decode::<KeyExchange>(&encoded).unwrap().ke_pk, pk);
+
+        // An invalid point is decoded as an invalid key, which validation rejects.
+        let len = encoded.len();
+        encoded[len - COMPRESSED_PUBLIC_KEY_SIZE] = 0x05;
+        let pay = Payload::KeyExchange(decode::<KeyExchange>(&encoded).unwrap());
+        assert_eq!(pay.validate(3), Err(ValidationError::InvalidKey));
+
+        // An encoding of the wrong length is malformed.
+        let uncompressed = encode(&pk.serialize_vec(&::SECP256K1, false)[..]);
+        assert!(decode::<KeyExchange>(&uncompressed).is_err());
+    }
+
+    #[test]
+    fn validate_sizes() {
+        let slice: [u8; 32] = [0x4f; 32];
+        let sk = SecretKey::from_slice(&::SECP256K1, &slice).unwrap();
+        let ke_pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let dc_main = |num_slots, slot_size| Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; slot_size]); num_slots]),
+            ke_pk,
+            extension: Extension::None,
+            early_confirm: None,
+        });
+
+        assert_eq!(dc_main(3, 32).validate(3), Ok(()));
+        assert_eq!(dc_main(3, MAX_SLOT_SIZE + 1).validate(3), Err(ValidationError::SlotTooLarge));
+        assert_eq!(dc_main(4, 32).validate(3), Err(ValidationError::TooManyElements));
+        let mut early = dc_main(3, 32);
+        if let Payload::DcMain(ref mut pay) = early {
+            pay.early_confirm = Some(vec![0; MAX_SLOT_SIZE + 1]);
+        }
+        assert_eq!(early.validate(3), Err(ValidationError::EarlyConfirmTooLarge));
+
+        let reveal = Payload::Reveal(Reveal { keys: vec![(3, SymmetricKey([0; 32]))] });
+        assert_eq!(reveal.validate(3), Err(ValidationError::InvalidPeerIndex));
+        assert_eq!(reveal.validate(4), Ok(()));
+
+        let mut invalid_key = dc_main(3, 32);
+        if let Payload::DcMain(ref mut pay) = invalid_key {
+            pay.ke_pk = PublicKey::new();
+        }
+        assert_eq!(invalid_key.validate(3), Err(ValidationError::InvalidKey));
+        let key_exchange = |ke_pk| Payload::KeyExchange(KeyExchange { ke_pk });
+        assert_eq!(key_exchange(ke_pk).validate(3), Ok(()));
+        assert_eq!(key_exchange(PublicKey::new()).validate(3), Err(ValidationError::InvalidKey));
+    }
+
+    #[test]
+    fn max_serialized_len() {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x4f; 32]).unwrap();
+        let ke_pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let num_peers = 5;
+        let slot_bytes = 100;
+
+        let payloads = vec![
+            Payload::KeyExchange(KeyExchange { ke_pk }),
+            Payload::DcExponential(DcExponential {
+                commitment: Commitment([0x01; 32]),
+                dc_exp: vec![Fp::from_u127(7); num_peers],
+            }),
+            Payload::DcMain(DcMain {
+                ok: true,
+                dc_xor: XorVec::from(vec![XorVec::from(vec![0xffu8; slot_bytes]); num_peers]),
+                ke_pk,
+                extension: Extension::DcAddSecp256k1Scalar(vec![Scalar::default(); num_peers]),
+                early_confirm: Some(vec![0x02; MAX_SLOT_SIZE]),
+            }),
+            Payload::Blame(Blame { ke_sk: Secret::new(sk) }),
+            Payload::Confirm(Confirm { data: vec![0x03; MAX_SLOT_SIZE] }),
+            Payload::Reveal(Reveal { keys: vec![(4, SymmetricKey([0x04; 32])); num_peers] }),
+        ];
+
+        let bound = Payload::max_serialized_len(num_peers, slot_bytes);
+        for payload in payloads {
+            assert_eq!(payload.validate(num_peers), Ok(()));
+            let len = encode(&payload).len();
+            assert!(len <= bound, "{} > {} for {:?}", len, bound, mem::discriminant(&payload));
+        }
+
+        // The main DC-net dominates, and the bound is tight for it.
+        let dc_main = Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; slot_bytes]); num_peers]),
+            ke_pk,
+            extension: Extension::DcAddSecp256k1Scalar(vec![Scalar::default(); num_peers]),
+            early_confirm: Some(vec![0; MAX_SLOT_SIZE]),
+        });
+        assert_eq!(encode(&dc_main).len(), bound);
+    }
+
+    #[test]
+    fn confirm_signatures() {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x4f; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let other_sk = SecretKey::from_slice(&::SECP256K1, &[0x50; 32]).unwrap();
+        let other_pk = PublicKey::from_secret_key(&::SECP256K1, &other_sk).unwrap();
+        let sighashes = [[0x01; 32], [0x02; 32]];
+
+        let confirm = Confirm::sign(&sighashes, &sk);
+        assert_eq!(confirm.data.len(), 2 * COMPACT_SIGNATURE_SIZE);
+        assert!(confirm.verify(&pk, &sighashes));
+        assert!(!confirm.verify(&other_pk, &sighashes));
+        assert!(!confirm.verify(&pk, &[[0x02; 32], [0x01; 32]]));
+        assert!(!confirm.verify(&pk, &sighashes[..1]));
+
+        let mut tampered = confirm.clone();
+        tampered.data[70] ^= 1;
+        assert!(!tampered.verify(&pk, &sighashes));
+    }
+
+    #[test]
+    fn dc_exponential_commitment() {
+        let pay = DcExponential::new(vec![Fp::from_u127(1), Fp::from_u127(2)]);
+        assert!(pay.verify_commitment());
+
+        // The pre-image after the prefix is the bincode encoding of the vector.
+        let mut hasher = Blake2s::default();
+        hasher.input(DC_EXPONENTIAL_COMMITMENT_PREFIX);
+        hasher.input(&encode(&pay.dc_exp));
+        assert_eq!(hasher.result()[..], pay.commitment.as_bytes()[..]);
+
+        let mut tampered = pay.clone();
+        tampered.dc_exp[1] += Fp::from_u127(1);
+        assert!(!tampered.verify_commitment());
+        let mut truncated = pay;
+        truncated.dc_exp.pop();
+        assert!(!truncated.verify_commitment());
+    }
+
+    /// Representative payloads of every variant along with their committed bincode encodings
+    ///
+    /// The vectors cover the encodings of public and secret keys, of `Fp` elements larger than
+    /// 64 bits, and of the discriminants of `Payload` and `Extension`. They are unit tests
+    /// rather than integration tests, because the payloads are private to the crate. The public
+    /// types `Fp` and `PublicKey` have vectors of their own in `tests/wire_vectors.rs`.
+    fn wire_vectors() -> Vec<(Payload, &'static str)> {
+        // Each vector is split into the encodings of the discriminant and the fields.
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x01; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap();
+        let fps = vec![Fp::from_u127(1), Fp::from_u127(Fp::prime() - 1)];
+        vec![
+            (Payload::KeyExchange(KeyExchange { ke_pk: pk }),
+             concat!("00000000",
+                     "2100000000000000",
+                     "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f")),
+            (Payload::DcExponential(DcExponential::new(fps)),
+             concat!("01000000",
+                     "591009f0a644774b68703290976b08cecc0bb27e1e60bd8435d29891df143586",
+                     "0200000000000000",
+                     "01000000000000000000000000000000",
+                     "feffffffffffffffffffffffffffff7f")),
+            (Payload::DcMain(DcMain {
+                ok: true,
+                dc_xor: XorVec::from(vec![XorVec::from(vec![0x01, 0x02, 0x03]), XorVec::from(vec![0xfd, 0xfe, 0xff])]),
+                ke_pk: pk,
+                extension: Extension::None,
+                early_confirm: Some(vec![0xee]),
+            }),
+             concat!("02000000",
+                     "01",
+                     "0200000000000000",
+                     "0300000000000000010203",
+                     "0300000000000000fdfeff",
+                     "2100000000000000031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                     "00000000",
+                     "010100000000000000ee")),
+            (Payload::DcMain(DcMain {
+                ok: false,
+                dc_xor: XorVec::from(vec![]),
+                ke_pk: pk,
+                extension: Extension::DcAddSecp256k1Scalar(vec![Scalar::from_bytes(&[0x7f; 32]).unwrap()]),
+                early_confirm: None,
+            }),
+             concat!("02000000",
+                     "00",
+                     "0000000000000000",
+                     "2100000000000000031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                     "01000000",
+                     "0100000000000000",
+                     "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                     "00")),
+            (Payload::Blame(Blame { ke_sk: Secret::new(SecretKey::from_slice(&::SECP256K1, &[0x02; 32]).unwrap()) }),
+             concat!("03000000",
+                     "2000000000000000",
+                     "0202020202020202020202020202020202020202020202020202020202020202")),
+            (Payload::Confirm(Confirm { data: vec![1, 2, 3] }),
+             "040000000300000000000000010203"),
+            (Payload::Reveal(Reveal { keys: vec![(1, SymmetricKey([0x33; 32])), (4, SymmetricKey([0x44; 32]))] }),
+             concat!("05000000",
+                     "0200000000000000",
+                     "01000000",
+                     "3333333333333333333333333333333333333333333333333333333333333333",
+                     "04000000",
+                     "4444444444444444444444444444444444444444444444444444444444444444")),
+        ]
+    }
+
+    #[test]
+    fn wire_vectors_stable() {
+        for (payload, hex) in wire_vectors() {
+            let ser = encode(&payload);
+            let ser_hex: String = ser.iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(ser_hex, hex, "encoding of {:?} has changed", payload);
+            let deserialized: Payload = decode(&ser).unwrap();
+            assert_eq!(deserialized, payload);
+        }
+    }
+
+    #[test]
+    fn secret_transparent() {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x4f; 32]).unwrap();
+        let blame = Blame { ke_sk: Secret::new(sk) };
+        assert_eq!(encode(&blame), encode(&sk));
+        // SecretKey is serialized as a length-prefixed byte sequence.
+        assert_eq!(&encode(&blame)[8..], &sk[..]);
+        assert_eq!(*decode::<Blame>(&encode(&sk)).unwrap().ke_sk, sk);
+        assert_eq!(format!("{:?}", blame), "Blame { ke_sk: Secret(..) }");
+    }
+
+    #[test]
+    fn same_configuration() {
+        let mut variants = vec![];
+        for (payload, _) in wire_vectors() {
+            variants.push(match payload {
+                Payload::KeyExchange(_) => 0,
+                Payload::DcExponential(_) => 1,
+                Payload::DcMain(_) => 2,
+                Payload::Blame(_) => 3,
+                Payload::Confirm(_) => 4,
+                Payload::Reveal(_) => 5,
+            });
+
+            // Whole messages are decoded like the reader does, which must see the encoding of
+            // the writer.
+            let message = Message { header: Header::new(SessionId([0x5e; 32]), 2, 7), payload };
+            let ser = encode(&message);
+            assert_eq!(decode::<Message>(&ser).unwrap(), message);
+            assert_eq!(decode::<Header>(&ser).unwrap(), message.header);
+            assert!(decode::<Message>(&ser[..ser.len() - 1]).is_err());
+        }
+        variants.sort();
+        variants.dedup();
+        assert_eq!(variants, (0..6).collect::<Vec<_>>());
+
+        // A length prefix beyond the size limit is rejected before anything is allocated.
+        let absurd = encode(&(MAX_MESSAGE_SIZE as u64 + 1));
+        assert!(decode::<Vec<u8>>(&[&absurd[..], &vec![0; MAX_MESSAGE_SIZE + 1][..]].concat()).is_err());
+    }
+
     #[cfg(test)]
     fn roundtrip_serde_bincode(payload1: Payload) {
-        let ser = bincode::serialize(&payload1, bincode::Infinite).unwrap();
-        let payload2 : Payload = bincode::deserialize(&ser).unwrap();
+        let ser = encode(&payload1);
+        let payload2 : Payload = decode(&ser).unwrap();
         assert_eq!(payload1, payload2);
     }
 }
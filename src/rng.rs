@@ -1,43 +1,252 @@
-use rand::{RngCore, SeedableRng, ChaChaRng, Error};
+use rand::{RngCore, SeedableRng, ChaChaRng, Error, ErrorKind};
+use std::cmp;
 use std::io::Cursor;
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use messages::MAX_SLOT_SIZE;
+
+/// Number of 32-bit words in a ChaCha block
+const BLOCK_WORDS: u128 = 16;
+
+/// Maximum number of bytes of the keystream of a single round
+///
+/// The keystream of a round consists of the ChaCha blocks with a block count from 1 to
+/// 2^64 - 1, see `DiceMixRng::prepare_round()`. Reading further would wrap the 64-bit block
+/// count around and repeat pads, so the generator refuses to do so.
+pub const MAX_ROUND_BYTES: u128 = ((1 << 64) - 1) * BLOCK_WORDS * 4;
+
+/// Maximum number of slots of `MAX_SLOT_SIZE` bytes that the keystream of a round can pad
+pub const MAX_SLOTS_PER_ROUND: u128 = MAX_ROUND_BYTES / MAX_SLOT_SIZE as u128;
 
 // TODO Extend this to an RNG that produces the "sum" (in a DcGroup sense) of multiple RNGs
 
+/// The pad generator of the DC-nets
+///
+/// The output is a keystream of bytes, which does not depend on how it is consumed: reading
+/// it with a single call to `fill_bytes()`, with several calls of arbitrary lengths, or with
+/// `next_u32()` and `next_u64()` (as little-endian integers) yields the same bytes. This is
+/// essential, because the pads of two peers cancel only if they are identical.
+///
+/// At most `MAX_ROUND_BYTES` bytes can be read per round. Reading more panics, or returns an
+/// error with `try_fill_bytes()`.
 pub struct DiceMixRng {
-    chacha : ChaChaRng
+    chacha : ChaChaRng,
+    // The unused bytes of the last word drawn from `chacha`, which are `leftover[4 - leftover_len..]`
+    leftover : [u8; 4],
+    leftover_len : usize,
+    // The number of words that can still be drawn from `chacha` in this round
+    words_left : u128,
 }
 
 impl DiceMixRng {
-    pub fn new(&key: &[u8; 32]) -> DiceMixRng {
-        let mut dc_rng = DiceMixRng { chacha : ChaChaRng::from_seed(key) };
+    pub fn new(key: &[u8; 32]) -> DiceMixRng {
+        let mut dc_rng = DiceMixRng {
+            chacha : ChaChaRng::from_seed(*key),
+            leftover : [0; 4],
+            leftover_len : 0,
+            words_left : 0,
+        };
         dc_rng.prepare_round(0);
         dc_rng
     }
 
-    pub fn prepare_round(&mut self, round: u32) {
-        // This sets
-        //   blockcount = 1 (We skip the first block because it's typically used for Poly1305)
-        //   nonce = round
-        self.chacha.set_word_pos(1 as u128);
-        self.chacha.set_stream(round as u64);
+    /// Moves to the beginning of the keystream of a round.
+    ///
+    /// The ChaCha state uses a 64-bit block count (words 12 and 13) and a 64-bit nonce (words
+    /// 14 and 15). The keystream of a round starts at
+    ///   blockcount = 1 (We skip the first block because it's typically used for Poly1305)
+    ///   nonce = round
+    /// and ends before the block count wraps around, i.e., after the block with
+    /// blockcount = 2^64 - 1. Different rounds use different nonces, so their keystreams never
+    /// overlap.
+    pub fn prepare_round(&mut self, round: u64) {
+        // The position is given in 32-bit words.
+        self.chacha.set_word_pos(BLOCK_WORDS);
+        self.chacha.set_stream(round);
+        self.leftover_len = 0;
+        self.words_left = MAX_ROUND_BYTES / 4;
+    }
+
+    /// Returns `true` if `len` more bytes can be read in this round.
+    fn can_read(&self, len: usize) -> bool {
+        let from_chacha = len.saturating_sub(self.leftover_len) as u128;
+        (from_chacha + 3) / 4 <= self.words_left
+    }
+
+    /// Moves to the position `words` words before the end of the keystream of the round.
+    #[cfg(test)]
+    fn seek_from_end(&mut self, words: u128) {
+        self.chacha.set_word_pos((1 << 64) * BLOCK_WORDS - words);
+        self.leftover_len = 0;
+        self.words_left = words;
+    }
+}
+
+impl Drop for DiceMixRng {
+    fn drop(&mut self) {
+        // The state of the ChaCha RNG contains the seed, so it is overwritten with the state for
+        // an all-zero seed. The volatile write prevents the compiler from optimizing it away.
+        unsafe {
+            ptr::write_volatile(&mut self.chacha, ChaChaRng::from_seed([0; 32]));
+            ptr::write_volatile(&mut self.leftover, [0; 4]);
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
     }
 }
 
 impl RngCore for DiceMixRng {
     fn next_u32(&mut self) -> u32 {
-        self.chacha.next_u32()
+        if self.leftover_len == 0 && self.words_left >= 1 {
+            self.words_left -= 1;
+            return self.chacha.next_u32();
+        }
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        LittleEndian::read_u32(&bytes)
     }
 
     fn next_u64(&mut self) -> u64 {
-        self.chacha.next_u64()
+        if self.leftover_len == 0 && self.words_left >= 2 {
+            self.words_left -= 2;
+            return self.chacha.next_u64();
+        }
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        LittleEndian::read_u64(&bytes)
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.chacha.fill_bytes(dest)
+        assert!(self.can_read(dest.len()), "keystream of the round exhausted");
+
+        // ChaChaRng discards the unused bytes of a word at the end of every call, so we keep them
+        // for the next call.
+        let from_leftover = cmp::min(self.leftover_len, dest.len());
+        let start = 4 - self.leftover_len;
+        dest[..from_leftover].copy_from_slice(&self.leftover[start..start + from_leftover]);
+        self.leftover_len -= from_leftover;
+
+        let rest = &mut dest[from_leftover..];
+        let whole_words = rest.len() - rest.len() % 4;
+        self.chacha.fill_bytes(&mut rest[..whole_words]);
+        self.words_left -= (whole_words / 4) as u128;
+
+        let tail = &mut rest[whole_words..];
+        if !tail.is_empty() {
+            self.words_left -= 1;
+            LittleEndian::write_u32(&mut self.leftover, self.chacha.next_u32());
+            tail.copy_from_slice(&self.leftover[..tail.len()]);
+            self.leftover_len = 4 - tail.len();
+        }
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
-        self.chacha.try_fill_bytes(dest)
+        if !self.can_read(dest.len()) {
+            return Err(Error::new(ErrorKind::Unavailable, "keystream of the round exhausted"));
+        }
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::PeerIndex;
+
+    #[test]
+    fn chunked_fill_bytes() {
+        let mut rng = DiceMixRng::new(&[0x63; 32]);
+        rng.prepare_round(1);
+        let mut at_once = [0u8; 300];
+        rng.fill_bytes(&mut at_once);
+
+        for chunk_len in 1..9 {
+            rng.prepare_round(1);
+            let mut chunked = [0u8; 300];
+            for chunk in chunked.chunks_mut(chunk_len) {
+                rng.fill_bytes(chunk);
+            }
+            assert_eq!(&chunked[..], &at_once[..], "chunks of {} bytes", chunk_len);
+        }
+
+        // Mixed consumption, starting at an odd position
+        rng.prepare_round(1);
+        let mut first = [0u8; 3];
+        rng.fill_bytes(&mut first);
+        assert_eq!(rng.next_u32(), LittleEndian::read_u32(&at_once[3..7]));
+        assert_eq!(rng.next_u64(), LittleEndian::read_u64(&at_once[7..15]));
+        let mut rest = [0u8; 5];
+        rng.fill_bytes(&mut rest);
+        assert_eq!(&rest[..], &at_once[15..20]);
+        assert_eq!(rng.next_u32(), LittleEndian::read_u32(&at_once[20..24]));
+    }
+
+    #[test]
+    fn prepare_round_skips_first_block() {
+        let mut rng = DiceMixRng::new(&[0x63; 32]);
+        rng.prepare_round(2);
+        let mut bytes = [0u8; 100];
+        rng.fill_bytes(&mut bytes);
+
+        let mut chacha = ChaChaRng::from_seed([0x63; 32]);
+        chacha.set_stream(2);
+        let mut expected = [0u8; 164];
+        chacha.fill_bytes(&mut expected);
+        assert_eq!(&bytes[..], &expected[64..]);
+    }
+
+    #[test]
+    fn read_up_to_end_of_round() {
+        let mut rng = DiceMixRng::new(&[0x63; 32]);
+        rng.seek_from_end(BLOCK_WORDS + 1);
+        let mut last_block = [0u8; 64];
+        rng.fill_bytes(&mut last_block[..3]);
+        rng.fill_bytes(&mut last_block[3..]);
+        assert_eq!(rng.words_left, 1);
+
+        // The keystream ends with the block with the highest block count.
+        let mut chacha = ChaChaRng::from_seed([0x63; 32]);
+        chacha.set_word_pos(((1 << 64) - 1) * BLOCK_WORDS - 1);
+        let mut expected = [0u8; 68];
+        chacha.fill_bytes(&mut expected);
+        assert_eq!(&last_block[..], &expected[..64]);
+
+        // The last word can still be read, but nothing after it.
+        assert_eq!(rng.next_u32(), LittleEndian::read_u32(&expected[64..]));
+        assert_eq!(rng.words_left, 0);
+        assert!(rng.try_fill_bytes(&mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn read_past_end_of_round() {
+        let mut rng = DiceMixRng::new(&[0x63; 32]);
+        rng.seek_from_end(2);
+        let mut bytes = [0u8; 9];
+        assert!(rng.try_fill_bytes(&mut bytes).is_err());
+        assert!(rng.try_fill_bytes(&mut bytes[..7]).is_ok());
+        assert!(rng.try_fill_bytes(&mut bytes[..1]).is_ok());
+        assert!(rng.try_fill_bytes(&mut bytes[..1]).is_err());
+
+        // A new round starts with a fresh keystream.
+        rng.prepare_round(1);
+        assert!(rng.try_fill_bytes(&mut bytes).is_ok());
+        assert_eq!(rng.words_left, MAX_ROUND_BYTES / 4 - 3);
+    }
+
+    #[test]
+    fn max_slots_per_round() {
+        // The main DC-net has one slot per peer, so the pads of a round never run out.
+        assert!(u128::from(PeerIndex::max_value()) <= MAX_SLOTS_PER_ROUND);
+        assert!(MAX_SLOTS_PER_ROUND * MAX_SLOT_SIZE as u128 <= MAX_ROUND_BYTES);
+    }
+
+    #[test]
+    #[should_panic(expected = "keystream of the round exhausted")]
+    fn panic_past_end_of_round() {
+        let mut rng = DiceMixRng::new(&[0x63; 32]);
+        rng.seek_from_end(1);
+        rng.next_u64();
     }
 }
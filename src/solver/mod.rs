@@ -1,9 +1,349 @@
+#[cfg(feature = "flint")]
 mod solver_flint;
 
+use std::time::{Duration, Instant};
+
 use ::dc::fp::Fp;
 
-use self::solver_flint::Solver;
+#[cfg(feature = "flint")]
+pub use self::solver_flint::Solver;
+
+/// The reason why solving failed
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SolveError {
+    /// The solution does not reproduce the power sums.
+    VerificationFailed,
+    /// The solution contains the zero message.
+    ///
+    /// Honest peers choose random messages, so the zero message indicates that some peer has
+    /// not contributed a message at all. This includes the all-zero power sums.
+    DegenerateInput,
+    /// The power sums are not the power sums of any messages, e.g., because some peer has
+    /// disrupted the DC-net.
+    NoSolution,
+    /// The solution contains a message more than once, see `Solve::recover_distinct_messages()`.
+    ///
+    /// Random messages of honest peers collide only with negligible probability, so this is
+    /// most likely a bug in the caller, e.g., a contribution that has been accumulated twice.
+    RepeatedMessage,
+}
+
+/// The kind of result of a call to the solver
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SolveOutcome {
+    /// The power sums have been solved.
+    Solved,
+    /// The power sums have no solution, which indicates a disruption or a collision.
+    Invalid,
+    /// The solution has been rejected.
+    Error(SolveError),
+}
+
+/// Statistics about a call to the solver, e.g., for monitoring
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SolveStats {
+    /// The time spent solving and verifying the solution
+    pub duration: Duration,
+    /// The number of power sums, i.e., the degree of the polynomial to be solved
+    pub degree: usize,
+    pub result: SolveOutcome,
+}
 
-trait Solve {
+pub trait Solve {
+    /// Finds the messages whose power sums are given, with repeated messages as often as they
+    /// occur.
+    ///
+    /// The zero message is a valid solution, e.g., all-zero power sums give all-zero messages.
     fn solve(power_sums: &Vec<Fp>) -> Option<Vec<Fp>>;
+
+    /// Solves like `solve()` and verifies that the solution reproduces the power sums.
+    ///
+    /// This guards against silent corruption of the solution, e.g., in the conversions at the
+    /// boundary to the external solver. In contrast to `solve()`, a solution containing the
+    /// zero message is rejected with `SolveError::DegenerateInput`.
+    fn solve_checked(power_sums: &[Fp]) -> Result<Option<Vec<Fp>>, SolveError> {
+        match Self::solve(&power_sums.to_vec()) {
+            Some(messages) => {
+                if self::power_sums(&messages) != power_sums {
+                    Err(SolveError::VerificationFailed)
+                } else if messages.contains(&Fp::from_u127(0)) {
+                    Err(SolveError::DegenerateInput)
+                } else {
+                    Ok(Some(messages))
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Recovers the messages whose power sums are given, in canonical order.
+    ///
+    /// The solver returns the messages as a multiset in no particular order. All honest peers
+    /// sort them in ascending order, so the order is the same for all of them and determines
+    /// which slot of the main DC-net belongs to which message.
+    fn recover_messages(power_sums: &[Fp]) -> Result<Vec<Fp>, SolveError> {
+        let mut messages = Self::solve_checked(power_sums)?.ok_or(SolveError::NoSolution)?;
+        messages.sort();
+        Ok(messages)
+    }
+
+    /// Recovers the messages like `recover_messages()` and additionally checks that they are
+    /// pairwise distinct.
+    ///
+    /// This is an optional sanity check for integrators, which tells a bug in the accumulation
+    /// of the power sums (`SolveError::RepeatedMessage`) apart from a disruption by a peer
+    /// (`SolveError::NoSolution`). The state machine does not use it, because it handles
+    /// repeated messages as a collision of honest peers.
+    fn recover_distinct_messages(power_sums: &[Fp]) -> Result<Vec<Fp>, SolveError> {
+        let messages = Self::recover_messages(power_sums)?;
+        // The messages are sorted, so repeated messages are adjacent.
+        if messages.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(SolveError::RepeatedMessage);
+        }
+        Ok(messages)
+    }
+
+    /// Solves like `solve_checked()` and returns statistics about the call.
+    fn solve_with_stats(power_sums: &[Fp]) -> (Result<Option<Vec<Fp>>, SolveError>, SolveStats) {
+        let start = Instant::now();
+        let result = Self::solve_checked(power_sums);
+        let stats = SolveStats {
+            duration: start.elapsed(),
+            degree: power_sums.len(),
+            result: match result {
+                Ok(Some(_)) => SolveOutcome::Solved,
+                Ok(None) => SolveOutcome::Invalid,
+                Err(err) => SolveOutcome::Error(err),
+            },
+        };
+        (result, stats)
+    }
+}
+
+/// Takes the place of the FLINT solver if the crate is built without the `flint` feature
+///
+/// This allows building and testing the crate on systems without FLINT, because the tests use
+/// a solver for small messages instead. There is no other solver, and failing to solve would
+/// make every run fail, so solving panics.
+#[cfg(not(feature = "flint"))]
+pub struct Solver;
+
+#[cfg(not(feature = "flint"))]
+impl Solve for Solver {
+    fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+        panic!("dicemix has been built without the `flint` feature, which provides the solver");
+    }
+}
+
+/// Computes the power sums `sum_j messages[j]^(i+1)` for `0 <= i < messages.len()`.
+pub fn power_sums(messages: &[Fp]) -> Vec<Fp> {
+    (1..messages.len() as u64 + 1).map(|k| {
+        messages.iter().fold(Fp::from_u127(0), |sum, &m| sum + m.pow(k))
+    }).collect()
+}
+
+/// Running sums of the power sums contributed by the peers in the exponential DC-net
+///
+/// Contributions are added one at a time, so only the sums are kept in memory.
+pub struct PowerSumAccumulator {
+    sums: Vec<Fp>,
+}
+
+impl PowerSumAccumulator {
+    /// Creates an accumulator for `num_slots` power sums.
+    pub fn new(num_slots: usize) -> Self {
+        Self {
+            sums: vec![Fp::from_u127(0); num_slots],
+        }
+    }
+
+    /// Adds the power sums contributed by a single peer.
+    ///
+    /// Panics if the contribution does not have exactly one element per slot. With the `rayon`
+    /// feature, the slots are added in parallel.
+    pub fn add_contribution(&mut self, dc_exp: &[Fp]) {
+        assert_eq!(dc_exp.len(), self.sums.len(), "wrong number of power sums");
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            self.sums.par_iter_mut().zip(dc_exp.par_iter()).for_each(|(sum, x)| *sum += x);
+        }
+        #[cfg(not(feature = "rayon"))]
+        for (sum, x) in self.sums.iter_mut().zip(dc_exp.iter()) {
+            *sum += x;
+        }
+    }
+
+    /// Returns the combined power sums, which are the input to the solver.
+    pub fn finish(self) -> Vec<Fp> {
+        self.sums
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Fp> {
+        vec![
+            Fp::from_u127(0x0b1b5dcbb65d530c4a19d3cfe5033887),
+            Fp::from_u127(0x27d9803748f6be6875282823a6ac5d5a),
+            Fp::from_u127(0x3a3112db6e48449711521bbc42944db3),
+        ]
+    }
+
+    // Solvers that return a fixed solution, independently of the input.
+    struct Correct;
+    struct Corrupted;
+    struct Zeros;
+    struct WithZero;
+    struct Unsolvable;
+    struct Reversed;
+    struct Repeated;
+
+    impl Solve for Correct {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            Some(messages())
+        }
+    }
+
+    impl Solve for Corrupted {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            let mut messages = messages();
+            messages[1] += Fp::from_u127(1);
+            Some(messages)
+        }
+    }
+
+    impl Solve for Zeros {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            Some(vec![Fp::from_u127(0); 3])
+        }
+    }
+
+    impl Solve for WithZero {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            let mut messages = messages();
+            messages[1] = Fp::from_u127(0);
+            Some(messages)
+        }
+    }
+
+    impl Solve for Reversed {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            Some(messages().into_iter().rev().collect())
+        }
+    }
+
+    // The solution of the power sums of `messages()`, in which the first contribution has been
+    // accumulated twice and the second not at all
+    impl Solve for Repeated {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            let messages = messages();
+            Some(vec![messages[0], messages[2], messages[0]])
+        }
+    }
+
+    impl Solve for Unsolvable {
+        fn solve(_: &Vec<Fp>) -> Option<Vec<Fp>> {
+            None
+        }
+    }
+
+    #[test]
+    fn power_sums_simple() {
+        let sums = power_sums(&[Fp::from_u127(2), Fp::from_u127(3)]);
+        assert_eq!(sums, vec![Fp::from_u127(5), Fp::from_u127(13)]);
+    }
+
+    #[test]
+    fn solve_checked() {
+        let sums = power_sums(&messages());
+        assert_eq!(Correct::solve_checked(&sums), Ok(Some(messages())));
+        assert_eq!(Corrupted::solve_checked(&sums), Err(SolveError::VerificationFailed));
+    }
+
+    #[test]
+    fn solve_checked_zero() {
+        let zeros = vec![Fp::from_u127(0); 3];
+        assert_eq!(power_sums(&zeros), zeros);
+        assert_eq!(Zeros::solve(&zeros), Some(zeros.clone()));
+        assert_eq!(Zeros::solve_checked(&zeros), Err(SolveError::DegenerateInput));
+
+        let mut with_zero = messages();
+        with_zero[1] = Fp::from_u127(0);
+        let sums = power_sums(&with_zero);
+        assert_eq!(WithZero::solve(&sums), Some(with_zero));
+        assert_eq!(WithZero::solve_checked(&sums), Err(SolveError::DegenerateInput));
+    }
+
+    #[test]
+    fn recover_messages() {
+        // Two peers whose solvers return the messages in different orders
+        let sums = power_sums(&messages());
+        assert!(Correct::solve(&sums.to_vec()) != Reversed::solve(&sums.to_vec()));
+        let recovered = Correct::recover_messages(&sums).unwrap();
+        assert_eq!(Reversed::recover_messages(&sums), Ok(recovered.clone()));
+
+        let mut sorted = messages();
+        sorted.sort();
+        assert_eq!(recovered, sorted);
+        assert_eq!(Unsolvable::recover_messages(&sums), Err(SolveError::NoSolution));
+        assert_eq!(Corrupted::recover_messages(&sums), Err(SolveError::VerificationFailed));
+    }
+
+    #[test]
+    fn recover_distinct_messages() {
+        let sums = power_sums(&messages());
+        let mut sorted = messages();
+        sorted.sort();
+        assert_eq!(Correct::recover_distinct_messages(&sums), Ok(sorted));
+
+        // A degenerate accumulation, which is distinguishable from a disruption.
+        let mut accumulator = PowerSumAccumulator::new(3);
+        for &m in [messages()[0], messages()[0], messages()[2]].iter() {
+            accumulator.add_contribution(&power_sums(&[m, Fp::from_u127(0), Fp::from_u127(0)]));
+        }
+        let degenerate = accumulator.finish();
+        assert!(Repeated::recover_messages(&degenerate).is_ok());
+        assert_eq!(Repeated::recover_distinct_messages(&degenerate), Err(SolveError::RepeatedMessage));
+        assert_eq!(Unsolvable::recover_distinct_messages(&degenerate), Err(SolveError::NoSolution));
+        assert_eq!(Repeated::recover_distinct_messages(&sums), Err(SolveError::VerificationFailed));
+    }
+
+    #[test]
+    fn solve_with_stats() {
+        let sums = power_sums(&messages());
+        let (result, stats) = Correct::solve_with_stats(&sums);
+        assert_eq!(result, Ok(Some(messages())));
+        assert_eq!(stats.degree, 3);
+        assert_eq!(stats.result, SolveOutcome::Solved);
+        assert!(stats.duration > Duration::new(0, 0));
+
+        assert_eq!(Unsolvable::solve_with_stats(&sums).1.result, SolveOutcome::Invalid);
+        let (result, stats) = Corrupted::solve_with_stats(&sums[..2]);
+        assert_eq!(result, Err(SolveError::VerificationFailed));
+        assert_eq!(stats.degree, 2);
+        assert_eq!(stats.result, SolveOutcome::Error(SolveError::VerificationFailed));
+    }
+
+    #[test]
+    fn accumulate_power_sums() {
+        // Each peer contributes the power sums of its own message.
+        let contributions: Vec<Vec<Fp>> = messages().iter().map(|&m| {
+            (1..4).map(|k| m.pow(k)).collect()
+        }).collect();
+
+        let mut accumulator = PowerSumAccumulator::new(3);
+        for dc_exp in contributions.iter() {
+            accumulator.add_contribution(dc_exp);
+        }
+        let sums = accumulator.finish();
+        assert_eq!(sums, power_sums(&messages()));
+
+        let at_once: Vec<Fp> = (0..3).map(|i| {
+            contributions.iter().fold(Fp::from_u127(0), |sum, dc_exp| sum + dc_exp[i])
+        }).collect();
+        assert_eq!(sums, at_once);
+    }
 }
@@ -28,10 +28,12 @@ impl Solve for Solver {
 
         let hex_len_u128 = ::std::mem::size_of::<u128>() * 2 + 1;
 
+        // The solver writes every message as a null-terminated hex string into its own chunk of
+        // a single buffer.
         let n = power_sums.len();
-        let mut out_messages_hex = vec!(vec!(0u8; hex_len_u128 + 1); n);
+        let mut out_messages_hex = vec!(0u8; n * (hex_len_u128 + 1));
         let out_messages_hex_ptrs : Vec<_> =
-            out_messages_hex.iter_mut().map(|x| x.as_mut_ptr()).collect();
+            out_messages_hex.chunks_mut(hex_len_u128 + 1).map(|x| x.as_mut_ptr()).collect();
         let prime_hex = hex_c_str(Fp::prime());
 
         let power_sums_hex : Vec<_> =
@@ -49,10 +51,9 @@ impl Solve for Solver {
 
         match ret {
             RET_OK => { Some(
-                out_messages_hex.iter().map(|m_hex| {
+                out_messages_hex.chunks(hex_len_u128 + 1).map(|m_hex| {
                     let leading_non_zero = m_hex.iter().take_while(|c| **c != 0).count();
-                    let rust_string = ::std::str::from_utf8(&m_hex[0..leading_non_zero]).unwrap();
-                    Fp::from_u127(u128::from_str_radix(rust_string, 16).unwrap())
+                    Fp::from_u127(parse_hex(&m_hex[0..leading_non_zero]))
                 }).collect()
             )},
             RET_INVALID => None,
@@ -61,6 +62,17 @@ impl Solve for Solver {
     }
 }
 
+/// Parses a non-empty string of hex digits, panicking if it is invalid or too long.
+fn parse_hex(hex: &[u8]) -> u128 {
+    assert!(!hex.is_empty(), "Internal error in flint solver, empty output");
+    hex.iter().fold(0u128, |acc, &c| {
+        let nibble = (c as char).to_digit(16)
+            .unwrap_or_else(|| panic!("Internal error in flint solver, invalid hex digit {}", c));
+        acc.checked_mul(16).and_then(|acc| acc.checked_add(nibble as u128))
+            .expect("Internal error in flint solver, output too large")
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use ::dc::fp::Fp;
@@ -101,4 +113,18 @@ mod tests {
         result.sort();
         assert_eq!(result, power_sums);
     }
+
+    #[test]
+    fn parse_hex() {
+        assert_eq!(super::parse_hex(b"0"), 0);
+        assert_eq!(super::parse_hex(b"3A3112DB6E48449711521BBC42944DB3"), 0x3a3112db6e48449711521bbc42944db3);
+        assert_eq!(super::parse_hex(b"00ff"), 0xff);
+        assert_eq!(super::parse_hex(b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), u128::max_value());
+    }
+
+    #[test]
+    #[should_panic]
+    fn parse_hex_too_long() {
+        super::parse_hex(b"100000000000000000000000000000000");
+    }
 }
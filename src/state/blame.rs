@@ -0,0 +1,246 @@
+//! Replay of DC-net messages in the blame phase
+//!
+//! Once a peer has published its ephemeral secret key, every other peer can derive the pads the
+//! peer has used in the DC-nets of the run and check whether the peer has sent the messages it
+//! was supposed to send.
+//!
+//! For each pair of peers `(i, j)` with `i < j`, peer `i` adds the pad and peer `j` subtracts it,
+//! so that the pads cancel out when all DC-net messages are combined, see `pad_sign()`.
+
+use std::ops::{AddAssign, SubAssign};
+use secp256k1::key::{PublicKey, SecretKey};
+use rand::Rng;
+use bit_set::BitSet;
+
+use messages::{DcExponential, DcMain};
+use dc::fp::Fp;
+use dc::Randomize;
+use rng::DiceMixRng;
+use ::{PeerIndex, SymmetricKey};
+use super::{live_index, to_peer_index};
+
+use super::{DcPhase, PeerVec};
+
+/// Derives the symmetric key shared between our ephemeral secret key and another peer's
+/// ephemeral public key.
+///
+/// This is `SymmetricKey::derive()`.
+pub fn shared_key(sk: &SecretKey, pk: &PublicKey) -> SymmetricKey {
+    SymmetricKey::derive(sk, pk)
+}
+
+/// Whether a peer adds or subtracts a pad that it shares with another peer
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PadSign {
+    Add,
+    Sub,
+}
+
+impl PadSign {
+    /// Returns the sign that cancels this sign.
+    pub fn opposite(self) -> PadSign {
+        match self {
+            PadSign::Add => PadSign::Sub,
+            PadSign::Sub => PadSign::Add,
+        }
+    }
+
+    /// Adds or subtracts `pad` from `x`.
+    pub fn apply<T: AddAssign + SubAssign>(self, x: &mut T, pad: T) {
+        match self {
+            PadSign::Add => *x += pad,
+            PadSign::Sub => *x -= pad,
+        }
+    }
+}
+
+/// Returns the sign with which peer `me` applies the pad shared with peer `other`.
+///
+/// The peer with the lower index adds the pad and the peer with the higher index subtracts it.
+/// Every DC-net computation must use this function, otherwise the pads do not cancel out.
+pub fn pad_sign(me: PeerIndex, other: PeerIndex) -> PadSign {
+    debug_assert!(me != other, "a peer does not share a pad with itself");
+    if me < other { PadSign::Add } else { PadSign::Sub }
+}
+
+/// The round of the pad generator used in a DC-net phase of a run, see
+/// `DiceMixRng::prepare_round()`
+///
+/// Every run uses fresh ephemeral keys, so the symmetric keys differ between runs anyway.
+/// Including the run nevertheless ensures that pads are never reused, even if a key is.
+pub fn dc_round(run: u32, phase: DcPhase) -> u64 {
+    let phase = match phase {
+        DcPhase::Exponential => 0,
+        DcPhase::Main => 1,
+    };
+    u64::from(run) << 1 | phase
+}
+
+/// Creates the pad generator for a DC-net phase of a run from a symmetric key.
+pub fn pad_rng(key: &SymmetricKey, run: u32, phase: DcPhase) -> DiceMixRng {
+    let mut rng = DiceMixRng::new(key.as_bytes());
+    rng.prepare_round(dc_round(run, phase));
+    rng
+}
+
+/// Source of the pads that a single peer shares with the other peers in the DC-nets of a run
+///
+/// Both peers of a pair obtain the same pads from their respective sources.
+pub trait PadSource {
+    /// Returns the pad generator shared with `peer` in the given DC-net phase.
+    fn pads_for(&self, peer: PeerIndex, phase: DcPhase) -> DiceMixRng;
+}
+
+/// Pads derived from the symmetric keys shared with the other peers, indexed by peer
+pub struct SharedKeyPads<'a> {
+    keys: &'a PeerVec<SymmetricKey>,
+    run: u32,
+}
+
+impl<'a> SharedKeyPads<'a> {
+    pub fn new(keys: &'a PeerVec<SymmetricKey>, run: u32) -> Self {
+        SharedKeyPads { keys, run }
+    }
+}
+
+impl<'a> PadSource for SharedKeyPads<'a> {
+    fn pads_for(&self, peer: PeerIndex, phase: DcPhase) -> DiceMixRng {
+        let key = self.keys[live_index(peer)].as_ref().expect("no key shared with this peer");
+        pad_rng(key, self.run, phase)
+    }
+}
+
+/// Adds (or with `undo`, removes) the pads of a peer to a vector of the exponential DC-net.
+fn apply_exponential_pads<P: PadSource>(peer_index: PeerIndex,
+                                        others: &BitSet,
+                                        pads: &P,
+                                        dc_exp: &mut [Fp],
+                                        undo: bool) {
+    for other in others.iter().map(to_peer_index).filter(|&other| other != peer_index) {
+        let mut rng = pads.pads_for(other, DcPhase::Exponential);
+        let sign = pad_sign(peer_index, other);
+        let sign = if undo { sign.opposite() } else { sign };
+        for x in dc_exp.iter_mut() {
+            sign.apply(x, rng.gen::<Fp>());
+        }
+    }
+}
+
+/// Creates the `DcExponential` payload that a peer sends for a message.
+///
+/// `others` is the set of peers in the DC-net, whose pads the peer adds to the power sums.
+pub fn padded_dc_exponential<P: PadSource>(peer_index: PeerIndex,
+                                           others: &BitSet,
+                                           pads: &P,
+                                           msg: Fp,
+                                           len: usize) -> DcExponential {
+    let mut power = msg;
+    let mut dc_exp: Vec<_> = (0..len).map(|_| {
+        let x = power;
+        power *= msg;
+        x
+    }).collect();
+    apply_exponential_pads(peer_index, others, pads, &mut dc_exp, false);
+    DcExponential::new(dc_exp)
+}
+
+/// Removes the pads that a peer shares with the peers whose keys are given from a vector of the
+/// exponential DC-net, e.g., the pads shared with peers that have gone missing, whose keys the
+/// peer has revealed.
+pub fn remove_exponential_pads(peer_index: PeerIndex, keys: &PeerVec<SymmetricKey>, run: u32, dc_exp: &mut [Fp]) {
+    apply_exponential_pads(peer_index, &super::live_peers(keys), &SharedKeyPads::new(keys, run), dc_exp, true);
+}
+
+/// Checks that a `DcExponential` payload has been computed honestly and returns its message.
+///
+/// `others` is the set of the other peers in the DC-net, whose pads are obtained from `pads`.
+/// After removing the pads, an honest payload contains the power sums of a single message.
+/// Returns `None` if the payload is dishonest.
+pub fn dc_exponential_message<P: PadSource>(peer_index: PeerIndex,
+                                            others: &BitSet,
+                                            pads: &P,
+                                            pay: &DcExponential) -> Option<Fp> {
+    let mut sums = pay.dc_exp.clone();
+    apply_exponential_pads(peer_index, others, pads, &mut sums, true);
+
+    let msg = *sums.first()?;
+    let mut power = msg;
+    let honest = sums.iter().all(|&x| {
+        let ok = x == power;
+        power *= msg;
+        ok
+    });
+    if honest { Some(msg) } else { None }
+}
+
+/// Checks that a `DcMain` payload has been computed honestly.
+///
+/// `others` is the set of the other peers in the DC-net, whose pads are obtained from `pads`.
+/// After removing the pads, an honest payload contains zeros in all slots but `slot`, which is
+/// the slot of the peer, or in all slots if the peer has no slot because its message is
+/// missing from the exponential DC-net.
+pub fn dc_main_honest<P: PadSource>(others: &BitSet, pads: &P, slot: Option<usize>, pay: &DcMain) -> bool {
+    let mut slots = pay.dc_xor.clone();
+    for other in others.iter() {
+        let mut rng = pads.pads_for(to_peer_index(other), DcPhase::Main);
+        let mut pad = slots.clone();
+        pad.randomize(&mut rng);
+        slots -= pad;
+    }
+
+    slots.iter().enumerate().all(|(i, contents)| Some(i) == slot || contents.iter().all(|&b| b == 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_key_symmetric() {
+        let sk_a = SecretKey::from_slice(&::SECP256K1, &[0x0a; 32]).unwrap();
+        let sk_b = SecretKey::from_slice(&::SECP256K1, &[0x0b; 32]).unwrap();
+        let pk_a = PublicKey::from_secret_key(&::SECP256K1, &sk_a).unwrap();
+        let pk_b = PublicKey::from_secret_key(&::SECP256K1, &sk_b).unwrap();
+
+        let key = shared_key(&sk_a, &pk_b);
+        assert_eq!(key, shared_key(&sk_b, &pk_a));
+        assert!(key != shared_key(&sk_a, &pk_a));
+        assert!(SymmetricKey::derive(&sk_b, &pk_a).ct_eq(&key));
+        assert!(!SymmetricKey::derive(&sk_b, &pk_b).ct_eq(&key));
+
+        // Both peers obtain the same pads from the key.
+        let mut rng_a = pad_rng(&key, 0, DcPhase::Main);
+        let mut rng_b = pad_rng(&shared_key(&sk_b, &pk_a), 0, DcPhase::Main);
+        assert_eq!(rng_a.gen::<Fp>(), rng_b.gen::<Fp>());
+    }
+
+    #[test]
+    fn pads_differ_between_runs_and_phases() {
+        let key = SymmetricKey::from_bytes(&[0x17; 32]);
+        let pad = |run, phase| pad_rng(&key, run, phase).gen::<Fp>();
+        assert!(pad(0, DcPhase::Exponential) != pad(0, DcPhase::Main));
+        assert!(pad(0, DcPhase::Exponential) != pad(1, DcPhase::Exponential));
+        assert!(pad(0, DcPhase::Main) != pad(1, DcPhase::Exponential));
+        assert!(pad(u32::max_value(), DcPhase::Main) != pad(u32::max_value(), DcPhase::Exponential));
+    }
+
+    #[test]
+    fn pad_signs_cancel() {
+        let mut rng = DiceMixRng::new(&[0x45; 32]);
+        for me in 0..4 {
+            for other in (0..4).filter(|&other| other != me) {
+                let sign = pad_sign(me, other);
+                assert_eq!(pad_sign(other, me), sign.opposite());
+
+                let pad: Fp = rng.gen();
+                let msg: Fp = rng.gen();
+                let mut x = msg;
+                sign.apply(&mut x, pad);
+                assert!(x != msg);
+                pad_sign(other, me).apply(&mut x, pad);
+                assert_eq!(x, msg);
+            }
+        }
+        assert_eq!(pad_sign(0, 1), PadSign::Add);
+    }
+}
@@ -0,0 +1,365 @@
+//! Checkpoints of a run, which allow resuming a run after a restart, e.g., of the process
+//!
+//! A checkpoint contains all state of a `RunStateMachine` between two messages, and an
+//! `ExecutionCheckpoint` adds the state of the `Execution` around it. It contains no
+//! secrets of our own peer, because the machine does not know them: the ephemeral secret key
+//! of the run, our message, and the pads derived from the ephemeral secret key are kept by the
+//! caller, who must persist the ephemeral secret key separately and derive the pads again, see
+//! `SymmetricKey::derive()`. The symmetric keys in the histories have been revealed by the
+//! peers and are zeroed when the checkpoint is dropped, but the serialized checkpoint is a
+//! plain byte string like any other serialized message.
+
+use bit_set::BitSet;
+use secp256k1::key::PublicKey;
+
+use messages::MAX_SLOT_SIZE;
+use dc::fp::Fp;
+use dc::xor::XorVec;
+use io::SignedFrame;
+use ::{PeerIndex, Variant, ExtensionKind, default_extension, peer_index_to_usize, usize_to_peer_index};
+
+use super::{Execution, Peer, RunStateMachine, RunState, DcPhase, PeerVec, KepkQueue, MIN_PEERS, peer_indices, live_index};
+use super::history::RunHistory;
+
+/// The reason why a run cannot be resumed from a checkpoint
+///
+/// A checkpoint is expected to come from our own storage. This catches corruption that would
+/// make the resumed machine panic, but it does not check the recorded payloads again.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ResumeError {
+    /// A vector indexed by peer does not have one entry per peer.
+    WrongNumberOfPeers,
+    /// A set of peers contains this peer index, which does not belong to any peer.
+    InvalidPeerIndex(PeerIndex),
+    /// The options of the run are out of range or incompatible, see `OptionsBuilder::build()`.
+    InvalidOptions,
+    /// The data recorded when the run has entered its current state is missing.
+    MissingStateData,
+    /// The checkpoint violates an invariant of the machine.
+    Inconsistent,
+}
+
+/// A serializable snapshot of a run in progress, see `RunStateMachine::checkpoint()`
+///
+/// Sets of peers are stored as peer indices.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    count: u32,
+    variant: Variant,
+    extension: ExtensionKind,
+    min_peers: usize,
+    slot_bytes: usize,
+    retain_frames: bool,
+    state: RunState,
+    kepks: PeerVec<PublicKey>,
+    received: Vec<PeerIndex>,
+    excluded: Vec<PeerIndex>,
+    histories: PeerVec<RunHistory>,
+    kepks_before_dc_exponential: PeerVec<PublicKey>,
+    peers_before_dc_exponential: Option<Vec<PeerIndex>>,
+    peers_before_dc_main: Option<Vec<PeerIndex>>,
+    exponential_messages: Option<Vec<Fp>>,
+    messages: Option<Vec<XorVec<u8>>>,
+    peers_before_dc_reveal: Option<Vec<PeerIndex>>,
+    kepks_before_blame: Option<PeerVec<PublicKey>>,
+    malicious: Vec<PeerIndex>,
+    proofs: PeerVec<Vec<SignedFrame>>,
+    peers_before_confirm: Option<Vec<PeerIndex>>,
+    confirmations: PeerVec<Vec<u8>>,
+    next_kepks: PeerVec<PublicKey>,
+}
+
+/// A serializable snapshot of an execution, see `Execution::checkpoint()`
+///
+/// The initial peers are not part of the checkpoint, because the execution borrows them. They
+/// are passed to `Execution::resume()` again. Our own peer is not part of it either, because
+/// it holds secrets, so a resumed execution does not compute our payloads.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionCheckpoint {
+    run: Checkpoint,
+    kepks: Vec<KepkQueue>,
+    joined: Vec<Peer>,
+    staged: Vec<(Peer, PublicKey)>,
+    aborted: Option<Vec<PeerIndex>>,
+    proofs: PeerVec<Vec<SignedFrame>>,
+}
+
+/// Converts peer indices into a set sized for all peers, like the sets of a new machine.
+fn peer_set(indices: &[PeerIndex], num_peers: usize) -> Result<BitSet, ResumeError> {
+    let mut set = BitSet::with_capacity(num_peers);
+    for &peer_index in indices {
+        match peer_index_to_usize(peer_index) {
+            Some(index) if index < num_peers => { set.insert(index); },
+            _ => return Err(ResumeError::InvalidPeerIndex(peer_index)),
+        }
+    }
+    Ok(set)
+}
+
+fn optional_peer_set(indices: &Option<Vec<PeerIndex>>, num_peers: usize) -> Result<Option<BitSet>, ResumeError> {
+    match *indices {
+        Some(ref indices) => peer_set(indices, num_peers).map(Some),
+        None => Ok(None),
+    }
+}
+
+impl RunStateMachine {
+    /// Takes a checkpoint of the run, which must be in progress.
+    ///
+    /// The outcome of a finished run is not part of the checkpoint, because `Execution` takes
+    /// it right after the message that has finished the run.
+    pub fn checkpoint(&self) -> Checkpoint {
+        debug_assert!(self.incoming_frame.is_none(), "checkpoint while applying a message");
+        Checkpoint {
+            count: self.count,
+            variant: self.variant,
+            extension: self.extension,
+            min_peers: self.min_peers,
+            slot_bytes: self.slot_bytes,
+            retain_frames: self.retain_frames,
+            state: self.state,
+            kepks: self.kepks.clone(),
+            received: peer_indices(&self.received),
+            excluded: peer_indices(&self.excluded),
+            histories: self.histories.clone(),
+            kepks_before_dc_exponential: self.kepks_before_dc_exponential.clone(),
+            peers_before_dc_exponential: self.peers_before_dc_exponential.as_ref().map(peer_indices),
+            peers_before_dc_main: self.peers_before_dc_main.as_ref().map(peer_indices),
+            exponential_messages: self.exponential_messages.clone(),
+            messages: self.messages.clone(),
+            peers_before_dc_reveal: self.peers_before_dc_reveal.as_ref().map(peer_indices),
+            kepks_before_blame: self.kepks_before_blame.clone(),
+            malicious: peer_indices(&self.malicious),
+            proofs: self.proofs.clone(),
+            peers_before_confirm: self.peers_before_confirm.as_ref().map(peer_indices),
+            confirmations: self.confirmations.clone(),
+            next_kepks: self.next_kepks.clone(),
+        }
+    }
+
+    /// Resumes a run from a checkpoint taken by `checkpoint()`.
+    pub fn resume(checkpoint: Checkpoint) -> Result<Self, ResumeError> {
+        let num_peers = checkpoint.kepks.len();
+        let lengths_valid = usize_to_peer_index(num_peers).is_some()
+            && checkpoint.histories.len() == num_peers
+            && checkpoint.kepks_before_dc_exponential.len() == num_peers
+            && checkpoint.proofs.len() == num_peers
+            && checkpoint.confirmations.len() == num_peers
+            && checkpoint.next_kepks.len() == num_peers
+            && checkpoint.kepks_before_blame.as_ref().map_or(true, |kepks| kepks.len() == num_peers)
+            && checkpoint.histories.live_peers().all(|(_, history)| history.revealed_symmetric_keys.len() == num_peers);
+        if !lengths_valid {
+            return Err(ResumeError::WrongNumberOfPeers);
+        }
+        let options_valid = checkpoint.min_peers >= MIN_PEERS
+            && checkpoint.slot_bytes > 0 && checkpoint.slot_bytes <= MAX_SLOT_SIZE
+            && checkpoint.extension == default_extension(checkpoint.variant);
+        if !options_valid {
+            return Err(ResumeError::InvalidOptions);
+        }
+
+        // The data recorded when entering a state is used until the end of the run.
+        let state_data_present = checkpoint.peers_before_dc_exponential.is_some() && match checkpoint.state {
+            RunState::DcProcess(DcPhase::Exponential) => true,
+            RunState::DcReveal(DcPhase::Exponential) => checkpoint.peers_before_dc_reveal.is_some(),
+            RunState::DcProcess(DcPhase::Main) => checkpoint.peers_before_dc_main.is_some(),
+            RunState::DcReveal(DcPhase::Main) => {
+                checkpoint.peers_before_dc_main.is_some() && checkpoint.peers_before_dc_reveal.is_some()
+            },
+            RunState::Blame => checkpoint.kepks_before_blame.is_some(),
+            RunState::Confirm => {
+                checkpoint.peers_before_dc_main.is_some() && checkpoint.peers_before_confirm.is_some()
+            },
+        };
+        // The main DC-net starts only once the exponential DC-net has been solved.
+        let state_data_present = state_data_present
+            && (checkpoint.peers_before_dc_main.is_none() || checkpoint.exponential_messages.is_some());
+        if !state_data_present {
+            return Err(ResumeError::MissingStateData);
+        }
+
+        let machine = Self {
+            count: checkpoint.count,
+            variant: checkpoint.variant,
+            extension: checkpoint.extension,
+            min_peers: checkpoint.min_peers,
+            slot_bytes: checkpoint.slot_bytes,
+            retain_frames: checkpoint.retain_frames,
+            incoming_frame: None,
+            state: checkpoint.state,
+            received: peer_set(&checkpoint.received, num_peers)?,
+            excluded: peer_set(&checkpoint.excluded, num_peers)?,
+            histories: checkpoint.histories,
+            kepks_before_dc_exponential: checkpoint.kepks_before_dc_exponential,
+            peers_before_dc_exponential: optional_peer_set(&checkpoint.peers_before_dc_exponential, num_peers)?,
+            peers_before_dc_main: optional_peer_set(&checkpoint.peers_before_dc_main, num_peers)?,
+            exponential_messages: checkpoint.exponential_messages,
+            messages: checkpoint.messages,
+            peers_before_dc_reveal: optional_peer_set(&checkpoint.peers_before_dc_reveal, num_peers)?,
+            kepks_before_blame: checkpoint.kepks_before_blame,
+            malicious: peer_set(&checkpoint.malicious, num_peers)?,
+            proofs: checkpoint.proofs,
+            peers_before_confirm: optional_peer_set(&checkpoint.peers_before_confirm, num_peers)?,
+            confirmations: checkpoint.confirmations,
+            // Both are set up again by `Execution::resume()`.
+            ltvks: PeerVec::empty(num_peers),
+            signing: None,
+            next_kepks: checkpoint.next_kepks,
+            outcome: None,
+            kepks: checkpoint.kepks,
+        };
+        if !machine.consistent() {
+            return Err(ResumeError::Inconsistent);
+        }
+        Ok(machine)
+    }
+}
+
+impl<'a> Execution<'a> {
+    /// Takes a checkpoint of the execution between two messages, e.g., after every message.
+    pub fn checkpoint(&self) -> ExecutionCheckpoint {
+        ExecutionCheckpoint {
+            run: self.rsm.checkpoint(),
+            kepks: self.kepks.clone(),
+            joined: self.joined.clone(),
+            staged: self.staged.clone(),
+            aborted: self.aborted.as_ref().map(peer_indices),
+            proofs: self.proofs.clone(),
+        }
+    }
+
+    /// Resumes an execution from a checkpoint taken by `checkpoint()`.
+    ///
+    /// `peers` are the peers that the execution has been created with, in the same order.
+    pub fn resume(peers: &'a Vec<Peer>, checkpoint: ExecutionCheckpoint) -> Result<Self, ResumeError> {
+        let ExecutionCheckpoint { run, kepks, joined, staged, aborted, proofs } = checkpoint;
+        let mut rsm = RunStateMachine::resume(run)?;
+        let num_peers = peers.len() + joined.len();
+        if rsm.kepks.len() != num_peers || kepks.len() != num_peers {
+            return Err(ResumeError::WrongNumberOfPeers);
+        }
+        // The key of the current run of every live peer is the front of its queue.
+        let queues_consistent = kepks.iter().all(KepkQueue::valid)
+            && rsm.kepks.live_peers().all(|(index, kepk)| kepks[live_index(index)].front() == Some(kepk));
+        if !queues_consistent {
+            return Err(ResumeError::Inconsistent);
+        }
+        rsm.set_ltvks(peers.iter().chain(joined.iter()).map(|peer| Some(peer.ltvk)).collect());
+        if rsm.state == RunState::Confirm && rsm.variant.has_early_confirm() && rsm.signing.is_none() {
+            return Err(ResumeError::Inconsistent);
+        }
+
+        Ok(Self {
+            aborted: optional_peer_set(&aborted, num_peers)?,
+            peers,
+            joined,
+            staged,
+            kepks,
+            rsm,
+            proofs,
+            own: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{encode, decode};
+    use state::tests::{new_keys, new_peers, new_execution, dc_exponential, dc_main};
+    use ::OptionsBuilder;
+    use super::*;
+
+    // A run in the main DC-net, in which peers 0 and 1 have sent their messages and peer 3 has
+    // timed out
+    fn mid_run() -> RunStateMachine {
+        let (_, kepks) = new_keys(4);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        rsm.retain_frames = true;
+        for i in 0..4 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+        for i in 0..2 {
+            rsm.apply_incoming_message((i, dc_main(4, kepks[i as usize].unwrap())));
+        }
+        rsm.on_timeout(3);
+        rsm
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_, kepks) = new_keys(4);
+        let mut rsm = mid_run();
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+
+        let checkpoint: Checkpoint = decode(&encode(&rsm.checkpoint())).unwrap();
+        let mut resumed = RunStateMachine::resume(checkpoint).unwrap();
+        assert_eq!(format!("{:?}", resumed), format!("{:?}", rsm));
+        assert!(resumed.received.capacity() >= 4);
+
+        // Both machines behave identically on the remaining messages of the run.
+        rsm.apply_incoming_message((2, dc_main(4, kepks[2].unwrap())));
+        resumed.apply_incoming_message((2, dc_main(4, kepks[2].unwrap())));
+        assert_eq!(resumed.state, RunState::DcReveal(DcPhase::Main));
+        assert_eq!(format!("{:?}", resumed), format!("{:?}", rsm));
+    }
+
+    #[test]
+    fn invalid() {
+        let rsm = mid_run();
+
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.received.push(4);
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::InvalidPeerIndex(4)));
+
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.confirmations.pop();
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::WrongNumberOfPeers));
+
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.slot_bytes = 0;
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::InvalidOptions));
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.extension = ExtensionKind::DcAddSecp256k1Scalar;
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::InvalidOptions));
+
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.state = RunState::Blame;
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::MissingStateData));
+
+        // The timed-out peer is excluded, so it cannot be live.
+        let mut checkpoint = rsm.checkpoint();
+        checkpoint.kepks[3] = checkpoint.kepks[0];
+        assert_eq!(RunStateMachine::resume(checkpoint).err(), Some(ResumeError::Inconsistent));
+    }
+
+    #[test]
+    fn execution_round_trip() {
+        let (sks, kepks) = new_keys(4);
+        let peers = new_peers(&sks[..3]);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks.iter().take(3).cloned().collect());
+        let newcomer = new_peers(&sks).pop().unwrap();
+        assert_eq!(execution.add_peer_for_next_run(newcomer, kepks[3].unwrap()), Ok(()));
+        for i in 0..3 {
+            execution.process((i, dc_exponential(i, 3)));
+        }
+        execution.process((0, dc_main(3, kepks[0].unwrap())));
+
+        let checkpoint: ExecutionCheckpoint = decode(&encode(&execution.checkpoint())).unwrap();
+        let mut resumed = Execution::resume(&peers, checkpoint).unwrap();
+        assert_eq!(resumed.dump_state(), execution.dump_state());
+        assert_eq!((&resumed.kepks, &resumed.staged), (&execution.kepks, &execution.staged));
+        for i in 1..3 {
+            let incoming = || (i, dc_main(3, kepks[i as usize].unwrap()));
+            assert_eq!(resumed.process(incoming()), execution.process(incoming()));
+        }
+        assert_eq!(format!("{:?}", resumed.rsm), format!("{:?}", execution.rsm));
+
+        let mut checkpoint = execution.checkpoint();
+        checkpoint.kepks.swap(0, 1);
+        assert_eq!(Execution::resume(&peers, checkpoint).err(), Some(ResumeError::Inconsistent));
+        let fewer_peers = peers[..2].to_vec();
+        let checkpoint = execution.checkpoint();
+        assert_eq!(Execution::resume(&fewer_peers, checkpoint).err(), Some(ResumeError::WrongNumberOfPeers));
+    }
+}
@@ -0,0 +1,276 @@
+//! Driving an `Execution` with the messages of an authenticated stream
+
+use std::io;
+use std::collections::VecDeque;
+use futures::{Future, Stream, Sink, Poll, Async, AsyncSink};
+use futures::stream::{self, Empty, Fuse};
+use bytes::Bytes;
+
+use io::{ReadAuthenticatedPayloads, WriteSignedPayloads, ReadError, RoundsExhausted};
+use messages::Payload;
+use ::PeerIndex;
+
+use super::{Execution, Action, RunOutcome, RunState, RoundSchedule};
+
+/// The reason why a `DriveExecution` future has failed before the run has finished
+#[derive(Debug)]
+pub enum DriveError {
+    /// The underlying transport has failed.
+    Read(ReadError),
+    /// The stream has ended before the run has finished.
+    StreamEnded,
+    /// The sink of our own payloads has failed.
+    Write(io::Error),
+    /// The stream of timeouts has failed.
+    Timeouts(io::Error),
+    /// The session has used up all rounds and must be terminated, see `io::MAX_ROUNDS`.
+    RoundsExhausted,
+}
+
+impl From<ReadError> for DriveError {
+    fn from(err: ReadError) -> Self {
+        DriveError::Read(err)
+    }
+}
+
+impl From<RoundsExhausted> for DriveError {
+    fn from(_: RoundsExhausted) -> Self {
+        DriveError::RoundsExhausted
+    }
+}
+
+/// A future that feeds the messages of a `ReadAuthenticatedPayloads` stream into an
+/// `Execution` until the current run has finished, and sends the payloads of our own peer
+/// through a `WriteSignedPayloads` sink
+///
+/// Every peer excluded by the execution is excluded in the reader, and the reader moves on to
+/// the next round whenever the execution does, with the frame limit of that round. The writer
+/// must share the `RoundCounter` of the reader, so that our payloads are stamped with the
+/// round they belong to. The future resolves to the outcome of the run along with the
+/// execution, the reader and the writer, so that the caller can drive the next run after a
+/// failed run.
+///
+/// Peers that stay silent are detected by the caller, who owns the round timers: every peer
+/// index from the stream of timeouts is passed to `Execution::handle_timeout()`, see
+/// `with_timeouts()`. Timeouts take precedence over messages that are ready at the same time.
+pub struct DriveExecution<'a, 'b, T, W, U = Empty<PeerIndex, io::Error>>
+    where T: Stream<Item = (PeerIndex, Bytes)>,
+          W: Sink<SinkItem = Bytes>,
+          U: Stream<Item = PeerIndex>,
+{
+    inner: Option<(Execution<'a>, ReadAuthenticatedPayloads<'b, T>, WriteSignedPayloads<W>)>,
+    timeouts: Fuse<U>,
+    schedule: RoundSchedule,
+    started: bool,
+    /// Our payloads that the writer has not accepted yet
+    outgoing: VecDeque<Payload>,
+    /// The outcome of the run, which is returned once our last payloads have been sent
+    outcome: Option<RunOutcome>,
+}
+
+impl<'a, 'b, T, W> DriveExecution<'a, 'b, T, W>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+          W: Sink<SinkItem = Bytes, SinkError = io::Error>,
+{
+    /// Creates a future that drives `execution` with the messages from `reader` and sends our
+    /// payloads to `writer`, without timeouts.
+    ///
+    /// The reader must be in the round of the current state of the execution.
+    pub fn new(execution: Execution<'a>, reader: ReadAuthenticatedPayloads<'b, T>, writer: WriteSignedPayloads<W>)
+        -> Self
+    {
+        DriveExecution::with_timeouts(execution, reader, writer, stream::empty())
+    }
+}
+
+impl<'a, 'b, T, W, U> DriveExecution<'a, 'b, T, W, U>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+          W: Sink<SinkItem = Bytes, SinkError = io::Error>,
+          U: Stream<Item = PeerIndex, Error = io::Error>,
+{
+    /// Creates a future that drives `execution` with the messages from `reader` and the
+    /// timeouts from `timeouts`, and sends our payloads to `writer`.
+    ///
+    /// The stream of timeouts yields the index of a peer whenever its message of the current
+    /// round has not arrived in time. Timeouts for peers whose message has arrived already are
+    /// ignored, so a late timeout of an earlier round is harmless. Once the stream has ended, the
+    /// execution is driven by messages only.
+    pub fn with_timeouts(execution: Execution<'a>,
+                         reader: ReadAuthenticatedPayloads<'b, T>,
+                         writer: WriteSignedPayloads<W>,
+                         timeouts: U)
+        -> Self
+    {
+        Self {
+            schedule: RoundSchedule::new(execution.rsm.slot_bytes),
+            timeouts: timeouts.fuse(),
+            inner: Some((execution, reader, writer)),
+            started: false,
+            outgoing: VecDeque::new(),
+            outcome: None,
+        }
+    }
+
+    /// Hands our pending payloads to the writer and flushes it.
+    fn flush(&mut self) -> Poll<(), DriveError> {
+        let writer = &mut self.inner.as_mut().expect("polled after completion").2;
+        while let Some(payload) = self.outgoing.pop_front() {
+            if let AsyncSink::NotReady(payload) = writer.start_send(payload).map_err(DriveError::Write)? {
+                self.outgoing.push_front(payload);
+                return Ok(Async::NotReady);
+            }
+        }
+        writer.poll_complete().map_err(DriveError::Write)
+    }
+}
+
+/// Identifies the round of an execution, which changes with every state of every run
+fn round_of(execution: &Execution) -> (u32, RunState) {
+    (execution.rsm.count, execution.rsm.state)
+}
+
+impl<'a, 'b, T, W, U> Future for DriveExecution<'a, 'b, T, W, U>
+    where T: Stream<Item = (PeerIndex, Bytes), Error = io::Error>,
+          W: Sink<SinkItem = Bytes, SinkError = io::Error>,
+          U: Stream<Item = PeerIndex, Error = io::Error>,
+{
+    type Item = (RunOutcome, Execution<'a>, ReadAuthenticatedPayloads<'b, T>, WriteSignedPayloads<W>);
+    type Error = DriveError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            // Our payloads are sent before we handle the next event, so that the round counter
+            // shared with the writer stays in the round of the payloads until they are signed.
+            try_ready!(self.flush());
+            if let Some(outcome) = self.outcome.take() {
+                let (execution, reader, writer) = self.inner.take().unwrap();
+                return Ok(Async::Ready((outcome, execution, reader, writer)));
+            }
+
+            let (ref mut execution, ref mut reader, _) = *self.inner.as_mut().unwrap();
+            let round_before = round_of(execution);
+            let actions = if !self.started {
+                self.started = true;
+                execution.start()
+            } else {
+                match self.timeouts.poll().map_err(DriveError::Timeouts)? {
+                    Async::Ready(Some(peer_index)) => execution.handle_timeout(peer_index),
+                    // A fused stream keeps returning `None` once it has ended.
+                    Async::Ready(None) | Async::NotReady => {
+                        let (peer_index, incoming_payload) = match try_ready!(reader.poll()) {
+                            Some(item) => item,
+                            None => return Err(DriveError::StreamEnded),
+                        };
+                        execution.handle_message(peer_index, incoming_payload)
+                    },
+                }
+            };
+
+            // Our payloads are computed when the execution enters a round, i.e., after the event
+            // that has completed the previous round. So they belong to the round that the
+            // execution is in now, and the reader moves on before they are sent.
+            if round_of(execution) != round_before {
+                let frame_limit = self.schedule.frame_limit(execution.rsm.state, execution.num_peers());
+                reader.advance_round(frame_limit)?;
+            }
+            for action in actions {
+                match action {
+                    Action::Exclude(peer_index) => reader.exclude(peer_index),
+                    Action::Send(payload) => self.outgoing.push_back(payload),
+                    Action::Done(run_outcome) => self.outcome = Some(run_outcome),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream};
+    use secp256k1::key::SecretKey;
+
+    use io::{ReadAuthenticatedPayloads, WriteSignedPayloads, RoundCounter};
+    use testutil::{broadcast_bus, BusSink};
+    use state::tests::{new_keys, new_peers, new_execution, own_peer};
+    use ::{OptionsBuilder, SessionId};
+    use super::*;
+
+    const SESSION_ID: SessionId = SessionId([0x5e; 32]);
+
+    #[test]
+    fn three_honest_peers() {
+        let (sks, kepks) = new_keys(6);
+        let (ltsks, ke_sks) = sks.split_at(3);
+        let peers = new_peers(ltsks);
+        let ltvks: Vec<_> = peers.iter().map(|peer| *peer.ltvk()).collect();
+        let kepks: Vec<_> = kepks.iter().skip(3).map(|kepk| kepk.unwrap()).collect();
+        let options = OptionsBuilder::new().slot_bytes(4).build().unwrap();
+
+        // Every peer sends its own payloads, so the bus is empty initially.
+        let drives: Vec<_> = broadcast_bus(3).into_iter().enumerate().map(|(i, (sink, stream))| {
+            let own = own_peer(i, ltsks, ke_sks, &[0xc0 + i as u8]);
+            let execution = Execution::with_own_peer(&peers, options, kepks.clone(), own).unwrap();
+            let round = RoundCounter::new();
+            let reader = ReadAuthenticatedPayloads::new(stream, SESSION_ID, &ltvks, round.clone()).unwrap();
+            let writer = WriteSignedPayloads::new(sink, SESSION_ID, i as PeerIndex, ltsks[i], round);
+            DriveExecution::new(execution, reader, writer)
+        }).collect();
+
+        for (outcome, execution, _, _) in future::join_all(drives).wait().unwrap() {
+            match outcome {
+                RunOutcome::Success(confirmations) => {
+                    assert!(confirmations.iter().all(Option::is_some));
+                    assert!(execution.excluded_peers().is_empty());
+                },
+                outcome => panic!("unexpected outcome {:?}", outcome),
+            }
+        }
+    }
+
+    /// Creates a writer for peer 0 that nobody listens to.
+    fn silent_writer(sks: &[SecretKey], round: RoundCounter) -> WriteSignedPayloads<BusSink> {
+        let (sink, _) = broadcast_bus(1).pop().unwrap();
+        WriteSignedPayloads::new(sink, SESSION_ID, 0, sks[0], round)
+    }
+
+    #[test]
+    fn timeout() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let ltvks: Vec<_> = peers.iter().map(|peer| *peer.ltvk()).collect();
+        let options = OptionsBuilder::new().min_peers(3).build().unwrap();
+        let execution = new_execution(&peers, options, &kepks);
+
+        let (_sink, stream) = broadcast_bus(1).pop().unwrap();
+        let round = RoundCounter::new();
+        let reader = ReadAuthenticatedPayloads::new(stream, SESSION_ID, &ltvks, round.clone()).unwrap();
+        let writer = silent_writer(&sks, round);
+        let timeouts = stream::iter_ok::<_, ::std::io::Error>(vec![2]);
+        match DriveExecution::with_timeouts(execution, reader, writer, timeouts).wait() {
+            Ok((RunOutcome::Aborted { remaining }, execution, _, _)) => {
+                assert_eq!(remaining.iter().collect::<Vec<_>>(), vec![0, 1]);
+                assert_eq!(execution.excluded_peers(), vec![2]);
+            },
+            Ok((outcome, _, _, _)) => panic!("unexpected outcome {:?}", outcome),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn stream_ended() {
+        let (sks, kepks) = new_keys(2);
+        let peers = new_peers(&sks);
+        let ltvks: Vec<_> = peers.iter().map(|peer| *peer.ltvk()).collect();
+        let options = OptionsBuilder::new().build().unwrap();
+        let execution = new_execution(&peers, options, &kepks);
+
+        let inner = stream::iter_ok::<_, ::std::io::Error>(vec![]);
+        let round = RoundCounter::new();
+        let reader = ReadAuthenticatedPayloads::new(inner, SESSION_ID, &ltvks, round.clone()).unwrap();
+        let writer = silent_writer(&sks, round);
+        match DriveExecution::new(execution, reader, writer).wait() {
+            Err(DriveError::StreamEnded) => {},
+            _ => panic!("ended stream not reported"),
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use bit_set::BitSet;
+
+use messages::{Payload, DcExponential, DcMain};
+use io::SignedFrame;
+use ::{PeerIndex, SymmetricKey, peer_index_to_usize};
+
+use super::PeerVec;
+
+/// The reason why a payload cannot be recorded in a `RunHistory`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HistoryError {
+    /// A `DcExponential` payload has been recorded already.
+    DuplicateDcExponential,
+    /// A `DcMain` payload has been recorded already.
+    DuplicateDcMain,
+    /// The payload requires a `DcExponential` payload, which has not been recorded.
+    MissingDcExponential,
+    /// The key shared with this peer has been revealed already.
+    DuplicateKey(PeerIndex),
+    /// The peer index of a revealed key is out of range.
+    InvalidPeerIndex(PeerIndex),
+}
+
+/// Protocol messages received from a single peer during a run
+///
+/// The history is kept until the end of the run, because it is necessary to replay the
+/// behavior of the peer in the blame phase. Optionally, the history also keeps the signed
+/// frames of the DC-net payloads, which prove to others what the peer has sent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunHistory {
+    pub dc_exponential: Option<DcExponential>,
+    pub dc_main: Option<DcMain>,
+    pub revealed_symmetric_keys: PeerVec<SymmetricKey>,
+    pub dc_exponential_frame: Option<SignedFrame>,
+    pub dc_main_frame: Option<SignedFrame>,
+}
+
+impl RunHistory {
+    pub fn new(num_peers: usize) -> Self {
+        Self {
+            dc_exponential: None,
+            dc_main: None,
+            revealed_symmetric_keys: PeerVec::empty(num_peers),
+            dc_exponential_frame: None,
+            dc_main_frame: None,
+        }
+    }
+
+    /// Records a payload, which must be consistent with the recorded payloads.
+    ///
+    /// Panics otherwise. Use `try_record_payload()` for payloads that have not been checked.
+    pub fn record_payload(&mut self, payload: Payload) {
+        self.try_record_payload(payload).expect("inconsistent payload");
+    }
+
+    /// Records a payload if it is consistent with the recorded payloads.
+    ///
+    /// If an error is returned, the history is unchanged.
+    pub fn try_record_payload(&mut self, payload: Payload) -> Result<(), HistoryError> {
+        self.try_record_payload_with_frame(payload, None)
+    }
+
+    /// Records a payload along with the signed frame it has been received in.
+    ///
+    /// The frame is retained only for `DcExponential` and `DcMain` payloads, which are the
+    /// payloads replayed in the blame phase. If an error is returned, the history is unchanged.
+    pub fn try_record_payload_with_frame(&mut self, payload: Payload, frame: Option<SignedFrame>)
+        -> Result<(), HistoryError>
+    {
+        match payload {
+            Payload::DcExponential(pay) => {
+                if self.dc_exponential.is_some() {
+                    return Err(HistoryError::DuplicateDcExponential);
+                }
+                self.dc_exponential = Some(pay);
+                self.dc_exponential_frame = frame;
+            },
+            Payload::DcMain(pay) => {
+                if self.dc_exponential.is_none() {
+                    return Err(HistoryError::MissingDcExponential);
+                }
+                if self.dc_main.is_some() {
+                    return Err(HistoryError::DuplicateDcMain);
+                }
+                self.dc_main = Some(pay);
+                self.dc_main_frame = frame;
+            },
+            Payload::Reveal(pay) => {
+                if self.dc_exponential.is_none() {
+                    return Err(HistoryError::MissingDcExponential);
+                }
+                // Check all keys before recording any of them.
+                let mut revealed = BitSet::with_capacity(self.revealed_symmetric_keys.len());
+                for &(peer_index, _) in pay.keys.iter() {
+                    let index = match peer_index_to_usize(peer_index) {
+                        Some(index) if index < self.revealed_symmetric_keys.len() => index,
+                        _ => return Err(HistoryError::InvalidPeerIndex(peer_index)),
+                    };
+                    if self.revealed_symmetric_keys[index].is_some() || !revealed.insert(index) {
+                        return Err(HistoryError::DuplicateKey(peer_index));
+                    }
+                }
+                // All peer indices have been checked to be in range.
+                for (peer_index, key) in pay.keys {
+                    let index = peer_index_to_usize(peer_index).expect("checked above");
+                    self.revealed_symmetric_keys[index] = Some(key);
+                }
+            },
+            // The other payloads are not necessary for replaying the peer.
+            _ => {},
+        }
+        debug_assert!(self.consistent());
+        Ok(())
+    }
+
+    /// Returns the retained signed frames in the order in which they have been recorded.
+    pub fn frames(&self) -> Vec<SignedFrame> {
+        self.dc_exponential_frame.iter().chain(self.dc_main_frame.iter()).cloned().collect()
+    }
+
+    #[inline]
+    pub fn consistent(&self) -> bool {
+        // Keys are revealed only after a DC-net phase,
+        // and the main phase always follows the exponential phase.
+        let revealed_any = self.revealed_symmetric_keys.iter().any(Option::is_some);
+        (!revealed_any || self.dc_exponential.is_some())
+            && (self.dc_main.is_none() || self.dc_exponential.is_some())
+            && (self.dc_exponential_frame.is_none() || self.dc_exponential.is_some())
+            && (self.dc_main_frame.is_none() || self.dc_main.is_some())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::mem;
+    use std::slice;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use messages::{Reveal, Extension, PublicKey, SecretKey};
+    use dc::xor::XorVec;
+    use super::*;
+
+    pub const KEY_BYTE: u8 = 0xaa;
+
+    // An allocator that counts the leftover key bytes in a watched buffer when it is freed.
+    struct Observer;
+
+    static WATCHED: AtomicUsize = AtomicUsize::new(0);
+    static LEFTOVER: AtomicUsize = AtomicUsize::new(usize::max_value());
+    // Only one buffer is watched at a time, even if tests run in parallel.
+    static WATCHING: Mutex<()> = Mutex::new(());
+
+    unsafe impl GlobalAlloc for Observer {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            // Zeroed memory ensures that uninitialized bytes, e.g., of empty slots, never
+            // resemble leftover keys.
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // Stop watching at the first free, because the memory may be reused by other threads.
+            if WATCHED.compare_exchange(ptr as usize, 0, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                let buf = slice::from_raw_parts(ptr, layout.size());
+                LEFTOVER.store(key_bytes(buf), Ordering::SeqCst);
+            }
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    /// Returns the number of bytes equal to KEY_BYTE in runs of at least 8 such bytes.
+    ///
+    /// Shorter runs occur by chance in bytes that are copied uninitialized from the stack, e.g.,
+    /// the payload of a `None`, where the keys of a test may have left some bytes behind.
+    fn key_bytes(buf: &[u8]) -> usize {
+        buf.split(|&b| b != KEY_BYTE).map(<[u8]>::len).filter(|&len| len >= 8).sum()
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Observer = Observer;
+
+    /// Drops `value` and returns the number of bytes of keys made of `KEY_BYTE` that are left in
+    /// the buffer at `ptr` when it is freed, see `key_bytes()`.
+    ///
+    /// The buffer must be owned by `value`.
+    pub fn leftover_after_drop<T>(ptr: *const u8, value: T) -> usize {
+        let _guard = WATCHING.lock().unwrap_or_else(|err| err.into_inner());
+        LEFTOVER.store(usize::max_value(), Ordering::SeqCst);
+        WATCHED.store(ptr as usize, Ordering::SeqCst);
+        mem::drop(value);
+        WATCHED.store(0, Ordering::SeqCst);
+        LEFTOVER.load(Ordering::SeqCst)
+    }
+
+    fn dc_exponential() -> Payload {
+        Payload::DcExponential(DcExponential { commitment: ::Commitment([0; 32]), dc_exp: vec![] })
+    }
+
+    fn dc_main() -> Payload {
+        let sk = SecretKey::from_slice(&::SECP256K1, &[0x01; 32]).unwrap();
+        Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![]),
+            ke_pk: PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap(),
+            extension: Extension::None,
+            early_confirm: None,
+        })
+    }
+
+    fn reveal(peers: &[PeerIndex]) -> Payload {
+        let keys = peers.iter().map(|&i| (i, SymmetricKey([i as u8; 32]))).collect();
+        Payload::Reveal(Reveal { keys })
+    }
+
+    #[test]
+    fn inconsistent_payloads() {
+        let mut history = RunHistory::new(3);
+        assert_eq!(history.try_record_payload(dc_main()), Err(HistoryError::MissingDcExponential));
+        assert_eq!(history.try_record_payload(reveal(&[1])), Err(HistoryError::MissingDcExponential));
+
+        assert_eq!(history.try_record_payload(dc_exponential()), Ok(()));
+        assert_eq!(history.try_record_payload(dc_exponential()), Err(HistoryError::DuplicateDcExponential));
+        assert_eq!(history.try_record_payload(dc_main()), Ok(()));
+        assert_eq!(history.try_record_payload(dc_main()), Err(HistoryError::DuplicateDcMain));
+
+        assert_eq!(history.try_record_payload(reveal(&[1])), Ok(()));
+        assert_eq!(history.try_record_payload(reveal(&[1])), Err(HistoryError::DuplicateKey(1)));
+        assert_eq!(history.try_record_payload(reveal(&[2, 2])), Err(HistoryError::DuplicateKey(2)));
+        assert_eq!(history.try_record_payload(reveal(&[2, 3])), Err(HistoryError::InvalidPeerIndex(3)));
+
+        // Rejected payloads leave the history unchanged.
+        assert_eq!(history.revealed_symmetric_keys[2], None);
+        assert_eq!(history.try_record_payload(reveal(&[0, 2])), Ok(()));
+        assert!(history.consistent());
+    }
+
+    #[test]
+    fn keys_zeroed_on_drop() {
+        let mut history = RunHistory::new(3);
+        history.dc_exponential = Some(DcExponential { commitment: ::Commitment([0; 32]), dc_exp: vec![] });
+        let keys = (0..3).map(|i| (i, SymmetricKey([KEY_BYTE; 32]))).collect();
+        history.record_payload(Payload::Reveal(Reveal { keys }));
+        assert_eq!(history.revealed_symmetric_keys[2], Some(SymmetricKey([KEY_BYTE; 32])));
+
+        let ptr = history.revealed_symmetric_keys.as_ptr() as *const u8;
+        assert_eq!(leftover_after_drop(ptr, history), 0);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use secp256k1::key::PublicKey;
+
+/// Maximum number of ephemeral public keys queued for a peer
+///
+/// The key exchange for the next run is pipelined into the current run, so the key of the
+/// current run and the key of the next run may be queued at the same time.
+pub const MAX_QUEUED_KEPKS: usize = 2;
+
+/// Queue of the ephemeral public keys of a peer
+///
+/// The front is the key for the earliest run. The queue never holds more than
+/// `MAX_QUEUED_KEPKS` keys.
+///
+/// While the peer takes part in the execution, the queue holds the key of the current run and
+/// possibly the key of the next run, i.e., 1 or 2 keys. An empty queue after `shift()` means
+/// that the peer has not announced a key for the next run, which excludes it from the next
+/// run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KepkQueue(VecDeque<PublicKey>);
+
+impl KepkQueue {
+    pub fn new() -> Self {
+        KepkQueue(VecDeque::with_capacity(MAX_QUEUED_KEPKS))
+    }
+
+    /// Queues an ephemeral public key for a later run.
+    ///
+    /// If there are already `MAX_QUEUED_KEPKS` keys in the queue, the key is rejected and
+    /// handed back.
+    pub fn push(&mut self, kepk: PublicKey) -> Result<(), PublicKey> {
+        if self.0.len() >= MAX_QUEUED_KEPKS {
+            return Err(kepk);
+        }
+        self.0.push_back(kepk);
+        Ok(())
+    }
+
+    /// Removes the ephemeral public key for the earliest run from the queue.
+    pub fn pop(&mut self) -> Option<PublicKey> {
+        self.0.pop_front()
+    }
+
+    /// Returns the ephemeral public key for the earliest run.
+    pub fn front(&self) -> Option<&PublicKey> {
+        self.0.front()
+    }
+
+    /// Returns `false` if the queue holds more than `MAX_QUEUED_KEPKS` keys, which is possible
+    /// only for a queue that has been deserialized.
+    pub fn valid(&self) -> bool {
+        self.0.len() <= MAX_QUEUED_KEPKS
+    }
+
+    /// Moves on to the next run, dropping the key of the finished run.
+    ///
+    /// `announced` is the key that the peer has announced for the next run during the finished
+    /// run, if any. A key queued before takes precedence. Returns the key for the next run, or
+    /// `None` if there is none, in which case the peer must be excluded.
+    pub fn shift(&mut self, announced: Option<PublicKey>) -> Option<PublicKey> {
+        self.pop();
+        if let Some(kepk) = announced {
+            // This cannot fail, because the queue is not full after popping.
+            let _ = self.push(kepk);
+        }
+        self.front().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::key::SecretKey;
+
+    use super::*;
+
+    fn new_kepks(num: usize) -> Vec<PublicKey> {
+        (0..num).map(|i| {
+            let sk = SecretKey::from_slice(&::SECP256K1, &[i as u8 + 1; 32]).unwrap();
+            PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn fifo() {
+        let kepks = new_kepks(3);
+        let mut queue = KepkQueue::new();
+        assert_eq!(queue.pop(), None);
+
+        // The keys for the current and the next run can be queued at the same time.
+        assert_eq!(queue.push(kepks[0]), Ok(()));
+        assert_eq!(queue.push(kepks[1]), Ok(()));
+        assert_eq!(queue.pop(), Some(kepks[0]));
+        assert_eq!(queue.push(kepks[2]), Ok(()));
+        assert_eq!(queue.front(), Some(&kepks[1]));
+        assert_eq!(queue.pop(), Some(kepks[1]));
+        assert_eq!(queue.pop(), Some(kepks[2]));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn shift() {
+        let kepks = new_kepks(4);
+        let mut queue = KepkQueue::new();
+        assert_eq!(queue.push(kepks[0]), Ok(()));
+
+        // The key announced in the current run becomes the key of the next run.
+        assert_eq!(queue.shift(Some(kepks[1])), Some(kepks[1]));
+        // A key queued before takes precedence over the announced key, which stays queued.
+        assert_eq!(queue.push(kepks[2]), Ok(()));
+        assert_eq!(queue.shift(Some(kepks[3])), Some(kepks[2]));
+        assert_eq!(queue.shift(None), Some(kepks[3]));
+
+        // Without a key for the next run, the queue runs empty instead of panicking.
+        assert_eq!(queue.shift(None), None);
+        assert_eq!(queue.front(), None);
+        assert_eq!(queue.shift(None), None);
+    }
+
+    #[test]
+    fn full() {
+        let kepks = new_kepks(3);
+        let mut queue = KepkQueue::new();
+        assert_eq!(queue.push(kepks[0]), Ok(()));
+        assert_eq!(queue.push(kepks[1]), Ok(()));
+        assert_eq!(queue.push(kepks[2]), Err(kepks[2]));
+
+        // The rejected key has not replaced any of the queued keys.
+        assert_eq!(queue.pop(), Some(kepks[0]));
+        assert_eq!(queue.pop(), Some(kepks[1]));
+        assert_eq!(queue.pop(), None);
+    }
+}
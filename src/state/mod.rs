@@ -1,20 +1,58 @@
+use std::cmp;
 use std::cmp::Ordering;
 use std::iter;
-use secp256k1::key::PublicKey;
+use std::mem;
+use secp256k1;
+use secp256k1::key::{PublicKey, SecretKey};
 use bit_set::BitSet;
+use futures::Async;
 
 use messages::*;
 use super::*;
-use io::IncomingPayload;
+use io::{IncomingPayload, SignedFrame};
+use dc::Randomize;
+use dc::fp::Fp;
+use dc::xor::XorVec;
+use solver::{Solve, PowerSumAccumulator};
+
+// The tests use a solver that finds only small messages but does not need FLINT.
+#[cfg(not(test))]
+use solver::Solver as DcSolver;
+#[cfg(test)]
+use self::tests::SmallSolver as DcSolver;
+// The tests choose messages in the exponential DC-net that the solver of the tests can find.
+#[cfg(not(test))]
+use self::own_peer::random_exponential_message as exponential_message;
+#[cfg(test)]
+use self::tests::small_exponential_message as exponential_message;
 
 use self::history::RunHistory;
+use self::kepk_queue::KepkQueue;
+use self::peer_vec::PeerVec;
+use self::blame::SharedKeyPads;
+use self::schnorr::{MultiSignature, SigningSession};
+pub use self::own_peer::OwnPeer;
 
 mod history;
+mod kepk_queue;
+mod peer_vec;
+mod blame;
+mod schnorr;
+mod own_peer;
+pub mod checkpoint;
+pub mod driver;
 
-type PeerVec<T> = Vec<Option<T>>;
+/// Minimum number of peers necessary to continue a run
+///
+/// Mixing makes no sense with fewer peers, because a single peer has no anonymity set. This is
+/// the lower bound and the default of `Options::min_peers()`.
+pub const MIN_PEERS: usize = 2;
 
 /// Static public information about a peer
-#[derive(Clone, Debug, PartialEq, Eq)]
+///
+/// Information that changes from run to run, i.e., the ephemeral public keys and the
+/// messages received in a run, is kept by `Execution` and `RunStateMachine`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Peer {
     peer_id: PeerId,
     ltvk: PublicKey,
@@ -23,45 +61,477 @@ pub struct Peer {
 impl Peer {
     pub fn new(peer_id: PeerId, ltvk: PublicKey) -> Self {
         Peer {
-            peer_id: peer_id,
-            ltvk: ltvk,
+            peer_id,
+            ltvk,
         }
     }
+
+    pub fn ltvk(&self) -> &PublicKey {
+        &self.ltvk
+    }
+
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+}
+
+/// An error in the setup of an execution
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SetupError {
+    /// The number of initial ephemeral public keys differs from the number of peers.
+    WrongNumberOfKepks,
+    /// The initial ephemeral public key of the peer with this index is invalid.
+    InvalidKepk(PeerIndex),
+    /// There are fewer peers than `Options::min_peers()`.
+    TooFewPeers,
+    /// There are more peers than a `PeerIndex` can address.
+    TooManyPeers,
+    /// The index or the keys of our own peer do not match the peers and the initial ephemeral
+    /// public keys.
+    InvalidOwnPeer,
+    /// The message of our own peer is longer than `Options::slot_bytes()`.
+    MessageTooLong,
+}
+
+/// The reason why a peer cannot join an execution for the next run
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum JoinError {
+    /// A peer with the same peer ID or long-term verification key is part of the execution or
+    /// has joined already, possibly excluded in the meantime.
+    DuplicatePeer,
+    /// The ephemeral public key of the peer is invalid.
+    InvalidKepk,
+    /// The execution has been aborted, so there is no next run.
+    Aborted,
+    /// The peer would obtain an index that does not fit into a `PeerIndex`.
+    TooManyPeers,
 }
 
 /// An execution of the DiceMix Light protocol
 pub struct Execution<'a> {
     peers: &'a Vec<Peer>,
-    next_kepks: PeerVec<PublicKey>,
+    /// The peers that have joined after the start of the execution, with indices following
+    /// those of `peers`
+    joined: Vec<Peer>,
+    /// The peers that join in the next run, along with their ephemeral public keys
+    staged: Vec<(Peer, PublicKey)>,
+    /// The queued ephemeral public keys of each peer, starting with the key of the current run
+    kepks: Vec<KepkQueue>,
     rsm: RunStateMachine,
+    /// The remaining peers if the execution has been aborted
+    aborted: Option<BitSet>,
+    /// The signed frames of the peers found malicious in the last failed run
+    proofs: PeerVec<Vec<SignedFrame>>,
+    /// Our own peer, whose payloads the execution computes, see `with_own_peer()`
+    own: Option<OwnPeer>,
 }
 
 impl<'a> Execution<'a> {
-    pub fn new(peers: &'a Vec<Peer>, initial_kepks: Vec<PublicKey>) -> Self {
-        let num_peers = peers.len();
+    /// Creates an execution with the initial ephemeral public keys of all peers, indexed by peer.
+    pub fn new(peers: &'a Vec<Peer>, options: Options, initial_kepks: Vec<PublicKey>)
+        -> Result<Self, SetupError>
+    {
+        if initial_kepks.len() != peers.len() {
+            return Err(SetupError::WrongNumberOfKepks);
+        }
+        if peers.len() < options.min_peers() {
+            return Err(SetupError::TooFewPeers);
+        }
+        if usize_to_peer_index(peers.len()).is_none() {
+            return Err(SetupError::TooManyPeers);
+        }
+        if let Some(index) = initial_kepks.iter().position(|kepk| !kepk.is_valid()) {
+            return Err(SetupError::InvalidKepk(to_peer_index(index)));
+        }
 
-        Self {
-            next_kepks: vec![None; num_peers],
-            peers: peers,
-            rsm: RunStateMachine::new(0, initial_kepks.into_iter().map(Some).collect()),
+        let queues = initial_kepks.iter().map(|&kepk| {
+            let mut queue = KepkQueue::new();
+            queue.push(kepk).expect("an empty queue is not full");
+            queue
+        }).collect();
+        let kepks = initial_kepks.into_iter().map(Some).collect();
+
+        let mut rsm = RunStateMachine::new(0, options.variant(), kepks);
+        rsm.min_peers = options.min_peers();
+        rsm.slot_bytes = options.slot_bytes();
+        rsm.retain_frames = options.retain_frames();
+        rsm.extension = options.extension_kind();
+        rsm.set_ltvks(peers.iter().map(|peer| Some(peer.ltvk)).collect());
+        Ok(Self {
+            kepks: queues,
+            proofs: PeerVec::empty(peers.len()),
+            peers,
+            joined: vec![],
+            staged: vec![],
+            rsm,
+            aborted: None,
+            own: None,
+        })
+    }
+
+    /// Creates an execution like `new()`, in which the execution computes the payloads of our
+    /// own peer.
+    ///
+    /// Our payloads are returned as `Action::Send` by `start()`, `handle_message()` and
+    /// `handle_timeout()`, and they are applied right away, so messages from our own peer index
+    /// must not be passed to the execution again; they are ignored.
+    pub fn with_own_peer(peers: &'a Vec<Peer>, options: Options, initial_kepks: Vec<PublicKey>, own: OwnPeer)
+        -> Result<Self, SetupError>
+    {
+        let (ltvk, kepk) = own.public_keys();
+        let keys_match = peer_index_to_usize(own.peer_index())
+            .map_or(false, |index| {
+                peers.get(index).map(Peer::ltvk) == Some(&ltvk) && initial_kepks.get(index) == Some(&kepk)
+            });
+        if !keys_match {
+            return Err(SetupError::InvalidOwnPeer);
+        }
+        if own.message().len() > options.slot_bytes() {
+            return Err(SetupError::MessageTooLong);
         }
+        let mut execution = Execution::new(peers, options, initial_kepks)?;
+        execution.own = Some(own);
+        Ok(execution)
     }
 
     #[inline]
     fn num_peers(&self) -> usize {
-        self.peers.len()
+        self.peers.len() + self.joined.len()
+    }
+
+    /// Returns the peer with the given index, including peers that have joined later.
+    pub fn peer(&self, peer_index: PeerIndex) -> Option<&Peer> {
+        let index = peer_index_to_usize(peer_index)?;
+        if index < self.peers.len() {
+            self.peers.get(index)
+        } else {
+            self.joined.get(index - self.peers.len())
+        }
+    }
+
+    /// Stages a peer that joins the execution in the next run, e.g., while the current run
+    /// finishes in a pipelined setup.
+    ///
+    /// The current run is unaffected. When it fails, the staged peers are appended in the order
+    /// in which they have been staged, so they obtain the next peer indices, and they take part
+    /// in the next run with `kepk`. If the current run succeeds, there is no next run and the
+    /// staged peers never join. See `withdraw_peer_for_next_run()` for peers that drop out before.
+    pub fn add_peer_for_next_run(&mut self, peer: Peer, kepk: PublicKey) -> Result<(), JoinError> {
+        if self.aborted.is_some() {
+            return Err(JoinError::Aborted);
+        }
+        if !kepk.is_valid() {
+            return Err(JoinError::InvalidKepk);
+        }
+        if usize_to_peer_index(self.num_peers() + self.staged.len() + 1).is_none() {
+            return Err(JoinError::TooManyPeers);
+        }
+        let duplicate = self.peers.iter().chain(self.joined.iter()).chain(self.staged.iter().map(|&(ref p, _)| p))
+            .any(|p| p.peer_id == peer.peer_id || p.ltvk == peer.ltvk);
+        if duplicate {
+            return Err(JoinError::DuplicatePeer);
+        }
+        self.staged.push((peer, kepk));
+        Ok(())
+    }
+
+    /// Removes a staged peer before the next run starts, e.g., because it has disconnected.
+    ///
+    /// Returns whether the peer had been staged.
+    pub fn withdraw_peer_for_next_run(&mut self, peer_id: &PeerId) -> bool {
+        let len_before = self.staged.len();
+        self.staged.retain(|&(ref peer, _)| peer.peer_id != *peer_id);
+        self.staged.len() != len_before
+    }
+
+    /// Returns the peers that take part in the current run, in the order of their indices.
+    pub fn live_peers(&self) -> Vec<PeerIndex> {
+        self.rsm.kepks.live_peers().map(|(peer_index, _)| peer_index).collect()
+    }
+
+    /// Returns the peers that have been excluded in the current or an earlier run, in the order
+    /// of their indices.
+    pub fn excluded_peers(&self) -> Vec<PeerIndex> {
+        (0..self.num_peers()).map(to_peer_index).filter(|&peer_index| !self.rsm.is_live(peer_index)).collect()
+    }
+
+    /// Returns a summary of the state of the current run, e.g., for logging when a run stalls.
+    ///
+    /// The summary contains only public information.
+    pub fn dump_state(&self) -> ExecutionSnapshot {
+        let rsm = &self.rsm;
+        ExecutionSnapshot {
+            run: rsm.count,
+            state: rsm.state,
+            aborted: self.aborted.is_some(),
+            live_peers: self.live_peers(),
+            received: peer_indices(&rsm.received),
+            peers_before_dc_exponential: rsm.peers_before_dc_exponential.as_ref().map(peer_indices),
+            peers_before_dc_main: rsm.peers_before_dc_main.as_ref().map(peer_indices),
+            peers_before_dc_reveal: rsm.peers_before_dc_reveal.as_ref().map(peer_indices),
+            peers_before_confirm: rsm.peers_before_confirm.as_ref().map(peer_indices),
+        }
+    }
+
+    /// Processes an incoming message and drives the execution.
+    ///
+    /// Returns `Async::Ready` with the outcome when a run has finished. If the run has failed, a
+    /// new run without the excluded peers starts immediately, and the caller is responsible for
+    /// forwarding the exclusions to the broadcast mechanism.
+    ///
+    /// Once the execution has been aborted, every further call returns `RunOutcome::Aborted`
+    /// without processing the message.
+    pub fn process(&mut self, incoming: (PeerIndex, IncomingPayload)) -> Async<RunOutcome> {
+        let (peer_index, incoming_payload) = incoming;
+        self.process_with_proof((peer_index, incoming_payload, None))
+    }
+
+    /// Processes an incoming message along with the signed frame it has been received in, e.g.,
+    /// an item of `ReadAuthenticatedPayloadsWithProof`, and drives the execution.
+    ///
+    /// If `Options::retain_frames()` is set, the frames of the peers found malicious in the
+    /// blame phase are available from `blame_proofs()` once the run has failed. Otherwise, this
+    /// is equivalent to `process()`.
+    pub fn process_with_proof(&mut self, incoming: (PeerIndex, IncomingPayload, Option<SignedFrame>))
+        -> Async<RunOutcome>
+    {
+        if let Some(ref remaining) = self.aborted {
+            return Async::Ready(RunOutcome::Aborted { remaining: remaining.clone() });
+        }
+        let (peer_index, incoming_payload, frame) = incoming;
+        if self.own.as_ref().map(OwnPeer::peer_index) == Some(peer_index) {
+            // Our payloads have been applied when they were computed.
+            return Async::NotReady;
+        }
+        self.rsm.apply_incoming_message_with_frame((peer_index, incoming_payload), frame);
+        self.take_outcome()
+    }
+
+    /// Processes a notification that a peer has failed to send its message in the current
+    /// round, e.g., because the round timer of the caller has expired, and drives the execution.
+    ///
+    /// The peer is excluded as offline. If it has taken part in the DC-net, the remaining peers
+    /// reveal the keys they share with it, so that the run can still finish. A timeout for a peer
+    /// that is not live or whose message has arrived already is ignored. The return value is as
+    /// for `process()`.
+    pub fn on_timeout(&mut self, peer_index: PeerIndex) -> Async<RunOutcome> {
+        if let Some(ref remaining) = self.aborted {
+            return Async::Ready(RunOutcome::Aborted { remaining: remaining.clone() });
+        }
+        self.rsm.on_timeout(peer_index);
+        self.take_outcome()
+    }
+
+    /// Takes the outcome of the current run, if any, and starts the next run if it has failed.
+    fn take_outcome(&mut self) -> Async<RunOutcome> {
+        match self.rsm.outcome.take() {
+            None => Async::NotReady,
+            Some(RunOutcome::Failed { excluded }) => {
+                self.proofs = mem::replace(&mut self.rsm.proofs, PeerVec::empty(0));
+                self.restart(&excluded);
+                if self.rsm.num_live_peers() < self.rsm.min_peers {
+                    // Too few peers have announced a key for the next run.
+                    return self.abort(live_peers(&self.rsm.kepks));
+                }
+                Async::Ready(RunOutcome::Failed { excluded })
+            },
+            Some(RunOutcome::Aborted { remaining }) => self.abort(remaining),
+            Some(outcome) => Async::Ready(outcome),
+        }
+    }
+
+    /// Returns the signed DC-net frames of the peers found malicious in the blame phase of the
+    /// last failed run, indexed by peer.
+    ///
+    /// Each frame verifies against the long-term verification key of its peer, see
+    /// `SignedFrame::verify()`. There are no frames unless `Options::retain_frames()` is set.
+    pub fn blame_proofs(&self) -> &PeerVec<Vec<SignedFrame>> {
+        &self.proofs
+    }
+
+    fn abort(&mut self, remaining: BitSet) -> Async<RunOutcome> {
+        self.aborted = Some(remaining.clone());
+        Async::Ready(RunOutcome::Aborted { remaining })
+    }
+
+    /// Processes an incoming message and returns the actions that the caller must perform.
+    ///
+    /// In contrast to `process()`, every peer that drops out of the execution is reported as
+    /// soon as it drops out, not only at the end of a failed run. The execution itself does not
+    /// perform any IO, so it can be driven by any transport.
+    pub fn handle_message(&mut self, peer_index: PeerIndex, incoming_payload: IncomingPayload) -> Vec<Action> {
+        let live_before = self.live_peers();
+        let outcome = self.process((peer_index, incoming_payload));
+        let mut actions = self.actions(live_before, outcome);
+        self.send_own_payloads(&mut actions);
+        actions
+    }
+
+    /// Processes a timeout like `on_timeout()` and returns the actions that the caller must
+    /// perform, see `handle_message()`.
+    pub fn handle_timeout(&mut self, peer_index: PeerIndex) -> Vec<Action> {
+        let live_before = self.live_peers();
+        let outcome = self.on_timeout(peer_index);
+        let mut actions = self.actions(live_before, outcome);
+        self.send_own_payloads(&mut actions);
+        actions
+    }
+
+    /// Returns the payloads of our own peer in the current state, see `with_own_peer()`.
+    ///
+    /// This must be called once after creating the execution, to obtain our payload of the
+    /// first round. Afterwards, our payloads are returned by `handle_message()` and
+    /// `handle_timeout()`, and further calls return no actions.
+    pub fn start(&mut self) -> Vec<Action> {
+        let mut actions = vec![];
+        self.send_own_payloads(&mut actions);
+        actions
+    }
+
+    /// Returns the actions for the peers that have dropped out since `live_before` and for the
+    /// outcome of the run.
+    fn actions(&self, live_before: Vec<PeerIndex>, outcome: Async<RunOutcome>) -> Vec<Action> {
+        let mut actions: Vec<_> = live_before.into_iter()
+            .filter(|&peer_index| !self.rsm.is_live(peer_index))
+            .map(Action::Exclude)
+            .collect();
+        if let Async::Ready(outcome) = outcome {
+            actions.push(Action::Done(outcome));
+        }
+        actions
+    }
+
+    /// Computes and applies the payloads of our own peer until we have sent our message of the
+    /// current round.
+    ///
+    /// Our message may complete a round, so the loop continues with the next state, or with the
+    /// next run if the run has failed.
+    fn send_own_payloads(&mut self, actions: &mut Vec<Action>) {
+        loop {
+            let peer_index = match self.own {
+                Some(ref own) => own.peer_index(),
+                None => return,
+            };
+            if self.aborted.is_some() || !self.rsm.is_live(peer_index) {
+                return;
+            }
+            let key_exchange = match self.own {
+                Some(ref mut own) => own.key_exchange(&self.rsm),
+                None => None,
+            };
+            if let Some(payload) = key_exchange {
+                self.apply_own_payload(payload, actions);
+                continue;
+            }
+            if self.rsm.received.contains(live_index(peer_index)) {
+                return;
+            }
+            let payload = match self.own {
+                Some(ref mut own) => own.payload(&self.rsm),
+                None => None,
+            };
+            match payload {
+                Some(payload) => self.apply_own_payload(payload, actions),
+                None => return,
+            }
+        }
+    }
+
+    fn apply_own_payload(&mut self, payload: Payload, actions: &mut Vec<Action>) {
+        let peer_index = self.own.as_ref().map(OwnPeer::peer_index).expect("our own peer is set");
+        actions.push(Action::Send(payload.clone()));
+        let live_before = self.live_peers();
+        self.rsm.apply_incoming_message((peer_index, IncomingPayload::Valid(payload)));
+        let outcome = self.take_outcome();
+        actions.extend(self.actions(live_before, outcome));
+    }
+
+    /// Starts a new run without the excluded peers but with the staged peers.
+    ///
+    /// The remaining peers use the ephemeral keys they have announced for the next run.
+    fn restart(&mut self, excluded: &BitSet) {
+        let announced = &self.rsm.next_kepks;
+        // Peers without a key for the next run are not live in the next run.
+        let mut next_kepks: PeerVec<_> = self.kepks.iter_mut().zip(announced.iter())
+            .map(|(queue, next_kepk)| queue.shift(*next_kepk))
+            .collect();
+
+        for (peer, kepk) in self.staged.drain(..) {
+            let mut queue = KepkQueue::new();
+            queue.push(kepk).expect("an empty queue is not full");
+            self.kepks.push(queue);
+            self.joined.push(peer);
+            next_kepks.push(Some(kepk));
+        }
+        self.rsm = self.rsm.restart(excluded, next_kepks);
+        let ltvks = self.all_ltvks();
+        self.rsm.set_ltvks(ltvks);
+        if let Some(ref mut own) = self.own {
+            own.restart();
+        }
+    }
+
+    /// Returns the long-term verification keys of all peers, including those that have joined.
+    fn all_ltvks(&self) -> PeerVec<PublicKey> {
+        self.peers.iter().chain(self.joined.iter()).map(|peer| Some(peer.ltvk)).collect()
     }
+}
 
+/// An action that the caller of `Execution::handle_message()` or `handle_timeout()` must perform
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// All further messages from this peer must be dropped, e.g., with
+    /// `ReadAuthenticatedPayloads::exclude()`.
+    Exclude(PeerIndex),
+    /// A run has finished with this outcome.
+    Done(RunOutcome),
+    /// This payload of our own peer must be signed and broadcast, e.g., with
+    /// `WriteSignedPayloads`, see `Execution::with_own_peer()`.
+    Send(Payload),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum DcPhase {
+/// The result of a run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// All peers have confirmed. The confirmation data is indexed by peer.
+    Success(PeerVec<Vec<u8>>),
+    /// All peers have confirmed in a multisignature variant. The signature is valid for the
+    /// digest of the messages of the main DC-net, see `MultiSignature::verify()`.
+    SuccessMulti(MultiSignature),
+    /// The run has failed, and a new run is necessary without the excluded peers.
+    Failed { excluded: BitSet },
+    /// Fewer than `Options::min_peers()` peers remain, so the execution has been aborted.
+    ///
+    /// This outcome is final, no further run takes place.
+    Aborted { remaining: BitSet },
+}
+
+/// A read-only summary of the state of an execution, see `Execution::dump_state()`
+///
+/// Sets of peers are given as sorted peer indices.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExecutionSnapshot {
+    /// The number of the current run, starting at 0
+    pub run: u32,
+    pub state: RunState,
+    pub aborted: bool,
+    pub live_peers: Vec<PeerIndex>,
+    /// The peers whose message of the current state has been received
+    pub received: Vec<PeerIndex>,
+    pub peers_before_dc_exponential: Option<Vec<PeerIndex>>,
+    pub peers_before_dc_main: Option<Vec<PeerIndex>>,
+    pub peers_before_dc_reveal: Option<Vec<PeerIndex>>,
+    pub peers_before_confirm: Option<Vec<PeerIndex>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DcPhase {
     Exponential,
     Main,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum RunState {
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunState {
     DcProcess(DcPhase),
     DcReveal(DcPhase),
     Blame,
@@ -96,40 +566,128 @@ impl PartialOrd for RunState {
     }
 }
 
+/// Frame limits for the rounds of a run
+///
+/// The payloads grow predictably from phase to phase, so the size of incoming frames can be
+/// limited in each round, see `ReadAuthenticatedPayloads::advance_round()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct RoundSchedule {
+    slot_bytes: usize,
+}
+
+impl RoundSchedule {
+    /// Creates a schedule for runs whose slots in the main DC-net have at most `slot_bytes` bytes.
+    fn new(slot_bytes: usize) -> Self {
+        Self {
+            slot_bytes,
+        }
+    }
+
+    /// Returns the maximum length of a frame, i.e., a signed message, in the given state.
+    fn frame_limit(&self, state: RunState, num_peers: usize) -> usize {
+        let bounds = Payload::serialized_len_bounds(num_peers, self.slot_bytes);
+        let payload = match state {
+            RunState::DcProcess(DcPhase::Exponential) => bounds.dc_exponential,
+            RunState::DcProcess(DcPhase::Main) => bounds.dc_main,
+            RunState::DcReveal(_) => bounds.reveal,
+            // Keys for the next run are announced in these states.
+            RunState::Blame => cmp::max(bounds.blame, bounds.key_exchange),
+            RunState::Confirm => cmp::max(bounds.confirm, bounds.key_exchange),
+        };
+        HEADER_SERIALIZED_LEN + payload + secp256k1::constants::COMPACT_SIGNATURE_SIZE
+    }
+}
+
 // State that is cleared after a run
+//
+// A run ends by replacing the machine of the run with a new one, see `Execution::restart()`,
+// which drops the old machine. Secrets in the blame data, e.g., revealed symmetric keys, zero
+// their memory when they are dropped, so no explicit cleanup is necessary.
 #[derive(Clone, Debug)]
 struct RunStateMachine {
     count: u32,
+    variant: Variant,
+    /// The extension of the main DC-net, see `Options::validate_payload()`
+    extension: ExtensionKind,
+    min_peers: usize,
+    slot_bytes: usize,
+    retain_frames: bool,
+    /// The signed frame of the message that is being applied, if frames are retained
+    incoming_frame: Option<SignedFrame>,
     state: RunState,
     kepks: PeerVec<PublicKey>,
     received: BitSet,
+    excluded: BitSet,
 
     // Blame data
     histories: PeerVec<RunHistory>,
+    /// The ephemeral public keys at the beginning of the run, including those of peers
+    /// excluded later
+    kepks_before_dc_exponential: PeerVec<PublicKey>,
     peers_before_dc_exponential: Option<BitSet>,
     peers_before_dc_main: Option<BitSet>,
+    /// The messages of the exponential DC-net, once it has been solved
+    exponential_messages: Option<Vec<Fp>>,
+    /// The messages in the slots of the main DC-net, once all pads have been removed
+    messages: Option<Vec<XorVec<u8>>>,
+    peers_before_dc_reveal: Option<BitSet>,
+    kepks_before_blame: Option<PeerVec<PublicKey>>,
+    malicious: BitSet,
+    /// The retained frames of the malicious peers, whose histories are dropped on exclusion
+    proofs: PeerVec<Vec<SignedFrame>>,
+
+    // Confirmation data
+    peers_before_confirm: Option<BitSet>,
+    confirmations: PeerVec<Vec<u8>>,
+    /// The long-term verification keys of the peers, which sign in the multisignature variants
+    ltvks: PeerVec<PublicKey>,
+    /// The session that the partial signatures are checked against in the multisignature
+    /// variants, once the main DC-net has finished
+    signing: Option<SigningSession>,
+
+    // Ephemeral public keys announced for the next run
+    next_kepks: PeerVec<PublicKey>,
+
+    outcome: Option<RunOutcome>,
 }
 
 impl RunStateMachine {
-    fn new(count: u32, kepks: PeerVec<PublicKey>) -> Self {
+    fn new(count: u32, variant: Variant, kepks: PeerVec<PublicKey>) -> Self {
         let num_peers = kepks.len();
 
         #[inline]
         fn new_peervec<T, U: Clone>(template: &PeerVec<T>, initial: U) -> PeerVec<U> {
-            template.into_iter().map(|opt| match opt {
-                &None => None,
-                &Some(_) => Some(initial.clone()),
-            }).collect()
+            template.iter().map(|opt| opt.as_ref().map(|_| initial.clone())).collect()
         }
 
         let new = Self {
-            count: count,
+            count,
+            variant,
+            extension: default_extension(variant),
+            min_peers: MIN_PEERS,
+            slot_bytes: MAX_SLOT_SIZE,
+            retain_frames: false,
+            incoming_frame: None,
             state: RunState::DcProcess(DcPhase::Exponential),
             received: BitSet::with_capacity(num_peers),
+            excluded: BitSet::with_capacity(num_peers),
             histories: new_peervec(&kepks, RunHistory::new(num_peers)),
-            peers_before_dc_exponential: None,
+            kepks_before_dc_exponential: kepks.clone(),
+            peers_before_dc_exponential: Some(live_peers(&kepks)),
             peers_before_dc_main: None,
-            kepks: kepks,
+            exponential_messages: None,
+            messages: None,
+            peers_before_dc_reveal: None,
+            kepks_before_blame: None,
+            malicious: BitSet::with_capacity(num_peers),
+            proofs: PeerVec::empty(num_peers),
+            peers_before_confirm: None,
+            confirmations: PeerVec::empty(num_peers),
+            ltvks: PeerVec::empty(num_peers),
+            signing: None,
+            next_kepks: PeerVec::empty(num_peers),
+            outcome: None,
+            kepks,
         };
 
         debug_assert!(new.consistent());
@@ -137,57 +695,2328 @@ impl RunStateMachine {
         new
     }
 
+    /// Creates the state machine for the next run.
+    ///
+    /// The excluded peers are dropped and the ephemeral keys of the remaining peers are replaced
+    /// by `next_kepks`. Peers without a key for the next run cannot take part in it. Entries of
+    /// `next_kepks` beyond the current peers belong to peers that join in the next run.
+    fn restart(&self, excluded: &BitSet, next_kepks: PeerVec<PublicKey>) -> RunStateMachine {
+        debug_assert!(next_kepks.len() >= self.kepks.len());
+        let kepks = next_kepks.into_iter().enumerate().map(|(index, next_kepk)| {
+            if index >= self.kepks.len() {
+                next_kepk
+            } else if excluded.contains(index) || self.kepks[index].is_none() {
+                None
+            } else {
+                next_kepk
+            }
+        }).collect();
+
+        let mut next = RunStateMachine::new(self.count + 1, self.variant, kepks);
+        next.min_peers = self.min_peers;
+        next.slot_bytes = self.slot_bytes;
+        next.retain_frames = self.retain_frames;
+        next.extension = self.extension;
+        next
+    }
+
+    /// Moves on to a later state.
+    ///
+    /// Panics if the transition is not allowed, see `can_transition()`.
     #[inline]
     fn set_state(&mut self, state: RunState) {
-        assert!(self.state < state);
+        assert!(can_transition(self.state, state), "illegal transition from {:?} to {:?}", self.state, state);
         self.state = state;
+
+        // Every state is a new round of communication.
+        self.received.clear();
+        match state {
+            RunState::DcProcess(DcPhase::Main) => {
+                self.peers_before_dc_main = Some(live_peers(&self.kepks));
+            },
+            RunState::DcReveal(_) => {
+                self.peers_before_dc_reveal = Some(live_peers(&self.kepks));
+            },
+            RunState::Blame => {
+                self.kepks_before_blame = Some(self.kepks.clone());
+            },
+            RunState::Confirm => {
+                self.peers_before_confirm = Some(live_peers(&self.kepks));
+            },
+            _ => {},
+        }
+    }
+
+    /// Applies an incoming message along with its signed frame, which is recorded in the
+    /// history of the peer if `retain_frames` is set.
+    fn apply_incoming_message_with_frame(&mut self, incoming: (PeerIndex, IncomingPayload), frame: Option<SignedFrame>) {
+        if self.retain_frames {
+            self.incoming_frame = frame;
+        }
+        self.apply_incoming_message(incoming);
+        self.incoming_frame = None;
     }
 
     fn apply_incoming_message(&mut self, incoming: (PeerIndex, IncomingPayload)) {
         let (peer_index, incoming_payload) = incoming;
 
-        // The message has a correct signature and is intended for this state of this session.
-        // So we can record it.
-        let first_from_peer = self.received.insert(peer_index as usize);
-        // The stream should never send us two messages from the same peer in the same round.
-        debug_assert!(first_from_peer);
+        // Frames from a peer that has been excluded during the run may still be on their way,
+        // so they are ignored.
+        if !self.is_live(peer_index) || self.aborted() {
+            return;
+        }
 
-        if let IncomingPayload::Valid(ref pay) = incoming_payload {
-            self.histories[peer_index as usize].as_mut().unwrap().record_payload(pay.clone());
+        // The key exchange for the next run is pipelined into the current run, so it is not
+        // a message of the current round.
+        if let IncomingPayload::Valid(Payload::KeyExchange(pay)) = incoming_payload {
+            self.apply_key_exchange(peer_index, pay);
+            assert!(self.consistent());
+            return;
         }
 
+        // The message has a correct signature and is intended for this state of this session.
+        // So we can record it.
+        let first_from_peer = self.received.insert(live_index(peer_index));
+
         match (self.state, incoming_payload) {
+            _ if !first_from_peer => {
+                // The peer has sent a second, differently signed message in the same round,
+                // so it equivocates.
+                self.exclude(peer_index);
+            },
             (RunState::DcProcess(DcPhase::Exponential), IncomingPayload::Valid(Payload::DcExponential(pay))) => {
-                unimplemented!()
+                self.apply_dc_exponential(peer_index, pay);
             },
             (RunState::DcProcess(DcPhase::Main), IncomingPayload::Valid(Payload::DcMain(pay))) => {
-                unimplemented!()
+                self.apply_dc_main(peer_index, pay);
             },
             (RunState::DcReveal(phase), IncomingPayload::Valid(Payload::Reveal(pay))) => {
-                unimplemented!()
+                self.apply_reveal(peer_index, phase, pay);
             },
             (RunState::Blame, IncomingPayload::Valid(Payload::Blame(pay))) => {
-                unimplemented!()
+                self.apply_blame(peer_index, pay);
             },
             (RunState::Confirm, IncomingPayload::Valid(Payload::Confirm(pay))) => {
-                unimplemented!()
+                self.apply_confirm(peer_index, pay);
             },
             _ => {
-                // TODO Kick the peer out
-                unimplemented!()
+                // The peer has sent an invalid message or a message that is not expected
+                // in this state.
+                self.exclude(peer_index);
             }
         }
+
+        if self.round_complete() {
+            self.finish_round();
+        }
         assert!(self.consistent());
     }
 
+    /// Processes a notification that a peer has failed to send its message in the current round.
+    ///
+    /// The peer is treated as offline and excluded. If the DC-net is affected, the remaining peers
+    /// reveal the keys they share with the peer to recover. A timeout for a peer whose message
+    /// has arrived already is ignored.
+    fn on_timeout(&mut self, peer_index: PeerIndex) {
+        if !self.is_live(peer_index) || self.received.contains(live_index(peer_index)) {
+            return;
+        }
+
+        self.exclude(peer_index);
+
+        if self.round_complete() {
+            self.finish_round();
+        }
+        assert!(self.consistent());
+    }
+
+    /// Returns `true` if all live peers have sent their message in the current round.
+    #[inline]
+    fn round_complete(&self) -> bool {
+        live_peers(&self.kepks).is_subset(&self.received)
+    }
+
+    /// Moves on to the next state after all live peers have sent their message.
+    fn finish_round(&mut self) {
+        if self.aborted() {
+            return;
+        }
+        match self.state {
+            RunState::DcProcess(phase) => {
+                if self.peers_before(phase).is_subset(&live_peers(&self.kepks)) {
+                    self.finish_dc(phase);
+                } else {
+                    // Some peers have gone missing during the DC-net, so the remaining peers need
+                    // to reveal the symmetric keys shared with them to remove their pads.
+                    self.set_state(RunState::DcReveal(phase));
+                }
+            },
+            RunState::DcReveal(phase) => {
+                if live_peers(&self.kepks) == *self.peers_before_dc_reveal.as_ref().unwrap() {
+                    self.finish_dc(phase);
+                } else {
+                    // Some peers have failed to reveal their keys, so their pads cannot be removed.
+                    self.set_state(RunState::Blame);
+                }
+            },
+            RunState::Blame => {
+                self.outcome = Some(RunOutcome::Failed { excluded: self.excluded.clone() });
+            },
+            RunState::Confirm => {
+                self.outcome = Some(
+                    if live_peers(&self.kepks) == *self.peers_before_confirm.as_ref().unwrap() {
+                        self.success()
+                    } else {
+                        // Some peers have not confirmed, so we need a new run without them.
+                        RunOutcome::Failed { excluded: self.excluded.clone() }
+                    }
+                );
+            },
+        }
+    }
+
+    /// Moves on to the next state after a DC-net phase in which all pads can be removed.
+    ///
+    /// The messages of the DC-net are recovered here, without the pads shared with the peers
+    /// that have gone missing. If that fails, some peer has disrupted the DC-net.
+    fn finish_dc(&mut self, phase: DcPhase) {
+        match phase {
+            DcPhase::Exponential => match self.solve_dc_exponential() {
+                None => {
+                    // Some peer has disrupted the DC-net, so we need to find out who.
+                    self.set_state(RunState::Blame);
+                    return;
+                },
+                Some(ref messages) if has_collision(messages) => {
+                    // Two peers have chosen the same message, which is nobody's fault. The peers
+                    // choose fresh messages in a new run, for which they need fresh ephemeral
+                    // keys. The blame phase is the key exchange window: the peers announce
+                    // their next keys and publish their current ones, which protect nothing
+                    // anymore, and the replay finds that every peer has been honest.
+                    self.set_state(RunState::Blame);
+                    return;
+                },
+                // The slot of a peer in the main DC-net is the rank of its message.
+                Some(mut messages) => {
+                    messages.sort();
+                    self.exponential_messages = Some(messages);
+                },
+            },
+            DcPhase::Main => match self.combine_dc_main() {
+                None => {
+                    self.set_state(RunState::Blame);
+                    return;
+                },
+                Some(messages) => {
+                    self.messages = Some(messages);
+                    if self.variant.has_early_confirm() {
+                        self.signing = self.signing_session();
+                        if self.signing.is_none() {
+                            // The nonce commitments add up to the point at infinity, which an
+                            // honest peer hits with negligible probability. The commitments are
+                            // not part of the replay, so the blame phase finds every peer honest
+                            // and the run is retried with fresh nonces.
+                            self.set_state(RunState::Blame);
+                            return;
+                        }
+                    }
+                },
+            },
+        }
+        self.set_state(next_after_dc(phase));
+    }
+
+    /// Returns the outcome of a run in which all peers have confirmed.
+    fn success(&self) -> RunOutcome {
+        match self.signing {
+            // Every partial signature has been verified against the session on arrival.
+            Some(ref session) => {
+                let partials: Vec<_> = self.confirmations.live_peers()
+                    .filter_map(|(_, data)| schnorr::partial_signature(data))
+                    .collect();
+                RunOutcome::SuccessMulti(session.aggregate(&partials))
+            },
+            None => RunOutcome::Success(self.confirmations.clone()),
+        }
+    }
+
+    /// Sets up the signing session of the multisignature variants from the nonce commitments
+    /// of the live peers and the messages of the main DC-net.
+    fn signing_session(&self) -> Option<SigningSession> {
+        let nonces: PeerVec<PublicKey> = self.histories.iter().map(|history| {
+            history.as_ref()
+                .and_then(|history| history.dc_main.as_ref())
+                .and_then(|pay| pay.early_confirm.as_ref())
+                .and_then(|data| schnorr::nonce_commitment(data))
+        }).collect();
+        let message = schnorr::message_digest(self.messages.as_ref()?);
+        SigningSession::new(&nonces, &self.ltvks, &message)
+    }
+
+    /// Sets the long-term verification keys of the peers, indexed by peer.
+    ///
+    /// The signing session depends on the keys, so it is set up again if the run is in the
+    /// confirmation phase, e.g., after resuming from a checkpoint.
+    fn set_ltvks(&mut self, ltvks: PeerVec<PublicKey>) {
+        self.ltvks = ltvks;
+        if self.state == RunState::Confirm && self.variant.has_early_confirm() {
+            self.signing = self.signing_session();
+        }
+    }
+
+    /// Returns the set of peers at the beginning of a DC-net phase.
+    #[inline]
+    fn peers_before(&self, phase: DcPhase) -> &BitSet {
+        match phase {
+            DcPhase::Exponential => self.peers_before_dc_exponential.as_ref().unwrap(),
+            DcPhase::Main => self.peers_before_dc_main.as_ref().unwrap(),
+        }
+    }
+
+    /// Records the ephemeral public key that a peer announces for the next run.
+    ///
+    /// Keys for the next run can be announced only once the outcome of the DC-nets is
+    /// clear, i.e., in the confirmation and in the blame phase, and only once per run.
+    fn apply_key_exchange(&mut self, peer_index: PeerIndex, pay: KeyExchange) {
+        let in_window = self.state == RunState::Confirm || self.state == RunState::Blame;
+        let index = live_index(peer_index);
+        if !in_window || self.next_kepks[index].is_some() || !pay.ke_pk.is_valid() {
+            self.exclude(peer_index);
+            return;
+        }
+        self.next_kepks[index] = Some(pay.ke_pk);
+    }
+
+    /// Records the exponential DC-net vector of a peer.
+    ///
+    /// The vector must have one power sum per slot, and there are as many slots as there are
+    /// peers at the beginning of the exponential DC-net, see `peers_before_dc_exponential`.
     fn apply_dc_exponential(&mut self, peer_index: PeerIndex, pay: DcExponential) {
-        // Perform DC-net
-        unimplemented!();
+        let num_slots = self.peers_before_dc_exponential.as_ref().unwrap().len();
+        if pay.dc_exp.len() != num_slots || !pay.verify_commitment() {
+            self.exclude(peer_index);
+            return;
+        }
+
+        self.record_payload(peer_index, Payload::DcExponential(pay));
+    }
+
+    /// Combines the exponential DC-net vectors of all live peers and solves for the messages.
+    ///
+    /// The pads shared with peers that have gone missing during the DC-net are removed with the
+    /// keys revealed for them. Returns `None` if the combined vector is not the power sums of
+    /// one message per live peer. The messages
+    /// are in canonical order, see `Solve::recover_messages()`.
+    fn solve_dc_exponential(&self) -> Option<Vec<Fp>> {
+        let num_messages = self.num_live_peers();
+        let mut accumulator = PowerSumAccumulator::new(num_messages);
+        for (peer_index, history) in self.histories.live_peers() {
+            let dc_exp = &history.dc_exponential.as_ref().unwrap().dc_exp;
+            if dc_exp.len() < num_messages {
+                return None;
+            }
+            // Only the keys shared with peers that have gone missing have been revealed so far.
+            let mut dc_exp = dc_exp[..num_messages].to_vec();
+            blame::remove_exponential_pads(peer_index, &history.revealed_symmetric_keys, self.count, &mut dc_exp);
+            accumulator.add_contribution(&dc_exp);
+        }
+        let sums = accumulator.finish();
+
+        // If the solver is at fault, the blame phase finds every peer honest, and the run is
+        // retried without exclusions. A zero message is treated as disruption.
+        DcSolver::recover_messages(&sums).ok()
+    }
+
+    /// Records the main DC-net vector of a peer.
+    ///
+    /// The vector must have one slot per peer at the beginning of the main DC-net, and all
+    /// slots must have the same size of at most `slot_bytes` bytes.
+    ///
+    /// A vector with `ok == false` is recorded like any other. It does not finish the round
+    /// early, because the blame phase needs the vectors of all peers, but combining the vectors
+    /// fails once the round is complete, see `combine_dc_main()`.
+    fn apply_dc_main(&mut self, peer_index: PeerIndex, pay: DcMain) {
+        let num_slots = self.peers_before_dc_main.as_ref().unwrap().len();
+        let slot_len = pay.dc_xor.first().map_or(0, |slot| slot.len());
+        let shape_valid = pay.dc_xor.len() == num_slots
+            && slot_len <= self.slot_bytes
+            && pay.dc_xor.iter().all(|slot| slot.len() == slot_len);
+        if !shape_valid {
+            self.exclude(peer_index);
+            return;
+        }
+
+        // The extension must be the one of the session.
+        if !self.extension.matches(&pay.extension) {
+            self.exclude(peer_index);
+            return;
+        }
+
+        // Early confirmation data is sent if and only if the variant requires it.
+        let early_confirm_valid = match pay.early_confirm {
+            Some(ref data) => {
+                self.variant.has_early_confirm() && schnorr::nonce_commitment(data).is_some()
+            },
+            None => !self.variant.has_early_confirm(),
+        };
+        if !early_confirm_valid {
+            self.exclude(peer_index);
+            return;
+        }
+
+        self.record_payload(peer_index, Payload::DcMain(pay));
+    }
+
+    /// Combines the main DC-net vectors of all live peers and returns the messages in the slots.
+    ///
+    /// The pads shared between live peers cancel out. The pads shared with peers that have gone
+    /// missing during the DC-net are removed using the symmetric keys revealed by the live
+    /// peers. Returns `None` if some peer has announced that it has not found its message in
+    /// the exponential DC-net, or if the slots of two peers differ in size.
+    fn combine_dc_main(&self) -> Option<Vec<XorVec<u8>>> {
+        let peers_before_dc_main = self.peers_before_dc_main.as_ref().unwrap();
+        let mut combined: Option<XorVec<XorVec<u8>>> = None;
+        for (_, history) in self.histories.live_peers() {
+            let pay = history.dc_main.as_ref().unwrap();
+            if !pay.ok {
+                return None;
+            }
+
+            let mut slots = pay.dc_xor.clone();
+            for (other, key) in history.revealed_symmetric_keys.live_peers() {
+                // Keys revealed in the exponential DC-net belong to peers without pads here.
+                if peer_index_to_usize(other).map_or(false, |other| peers_before_dc_main.contains(other)) {
+                    let mut pad = slots.clone();
+                    pad.randomize(&mut blame::pad_rng(key, self.count, DcPhase::Main));
+                    slots -= pad;
+                }
+            }
+
+            combined = match combined {
+                None => Some(slots),
+                Some(mut acc) => {
+                    if acc.iter().zip(slots.iter()).any(|(a, b)| a.len() != b.len()) {
+                        return None;
+                    }
+                    acc += slots;
+                    Some(acc)
+                },
+            };
+        }
+        combined.map(|slots| slots.to_vec())
+    }
+
+    fn apply_reveal(&mut self, peer_index: PeerIndex, phase: DcPhase, pay: Reveal) {
+        // The peer must reveal exactly the keys it shares with peers that have gone missing during
+        // the DC-net phase, and it must not reveal any key twice.
+        let valid = {
+            let history = self.histories[live_index(peer_index)].as_ref().unwrap();
+            let peers_before_dc_reveal = self.peers_before_dc_reveal.as_ref().unwrap();
+            let mut missing = BitSet::with_capacity(self.kepks.len());
+            missing.extend(self.peers_before(phase).difference(peers_before_dc_reveal));
+            let mut revealed = BitSet::with_capacity(self.kepks.len());
+            pay.keys.iter().all(|&(other, _)| {
+                peer_index_to_usize(other).map_or(false, |other| {
+                    missing.contains(other)
+                        && revealed.insert(other)
+                        && history.revealed_symmetric_keys[other].is_none()
+                })
+            }) && revealed == missing
+        };
+
+        if valid {
+            self.record_payload(peer_index, Payload::Reveal(pay));
+        } else {
+            self.exclude(peer_index);
+        }
+    }
+
+    fn apply_confirm(&mut self, peer_index: PeerIndex, pay: Confirm) {
+        let valid = match self.variant {
+            Variant::PlainEcdsa | Variant::ValueShuffleElementsEcdsa => confirm_data_valid(&pay.data),
+            Variant::PlainSchnorrMulti => match (schnorr::partial_signature(&pay.data), &self.signing) {
+                (Some(partial), &Some(ref session)) => session.verify_partial(peer_index, &partial),
+                _ => false,
+            },
+        };
+        if valid {
+            self.confirmations[live_index(peer_index)] = Some(pay.data);
+        } else {
+            self.exclude(peer_index);
+        }
+    }
+
+    fn apply_blame(&mut self, peer_index: PeerIndex, pay: Blame) {
+        if !self.replay(peer_index, &pay.ke_sk) {
+            let index = live_index(peer_index);
+            if self.retain_frames {
+                self.proofs[index] = Some(self.histories[index].as_ref().unwrap().frames());
+            }
+            self.malicious.insert(index);
+            self.exclude(peer_index);
+        }
+    }
+
+    /// Replays the DC-net messages of a peer given its ephemeral secret key.
+    ///
+    /// Returns `true` if the peer has sent exactly the messages it was supposed to send.
+    fn replay(&self, peer_index: PeerIndex, ke_sk: &SecretKey) -> bool {
+        let index = live_index(peer_index);
+        let kepks = self.kepks_before_blame.as_ref().unwrap();
+        let history = self.histories[index].as_ref().unwrap();
+
+        // The secret key must belong to the ephemeral public key of this run.
+        match PublicKey::from_secret_key(&::SECP256K1, ke_sk) {
+            Ok(ref kepk) if Some(kepk) == kepks[index].as_ref() => {},
+            _ => return false,
+        }
+
+        // Derive the symmetric keys shared with the other peers in a DC-net from their keys at
+        // the beginning of the run, which are known even for peers that have been excluded in
+        // the meantime. A key that the peer has revealed must be the derived key.
+        let initial_kepks = &self.kepks_before_dc_exponential;
+        let keys_for = |peers: &BitSet| -> Option<PeerVec<SymmetricKey>> {
+            (0..initial_kepks.len()).map(|other| {
+                if other == index || !peers.contains(other) {
+                    return Some(None);
+                }
+                let key = blame::shared_key(ke_sk, initial_kepks[other].as_ref()?);
+                match history.revealed_symmetric_keys[other] {
+                    Some(ref revealed) if !revealed.ct_eq(&key) => None,
+                    _ => Some(Some(key)),
+                }
+            }).collect()
+        };
+
+        let exponential_message = match (&self.peers_before_dc_exponential, &history.dc_exponential) {
+            (&Some(ref peers), &Some(ref pay)) => {
+                if pay.dc_exp.len() != peers.len() {
+                    return false;
+                }
+                let message = keys_for(peers).and_then(|keys| {
+                    blame::dc_exponential_message(peer_index, &live_peers(&keys), &SharedKeyPads::new(&keys, self.count), pay)
+                });
+                if message.is_none() {
+                    return false;
+                }
+                message
+            },
+            _ => None,
+        };
+
+        match (&self.peers_before_dc_main, &history.dc_main) {
+            (&Some(ref peers), &Some(ref pay)) => {
+                // A peer may claim that its message is missing from the solved exponential
+                // DC-net only if it really is. Otherwise, it owns the slot at the rank of its
+                // message.
+                let slot = match (&self.exponential_messages, exponential_message) {
+                    (&Some(ref messages), Some(message)) => messages.binary_search(&message).ok(),
+                    _ => None,
+                };
+                pay.ok == slot.is_some() && pay.dc_xor.len() == peers.len() && keys_for(peers).map_or(false, |keys| {
+                    blame::dc_main_honest(&live_peers(&keys), &SharedKeyPads::new(&keys, self.count), slot, pay)
+                })
+            },
+            _ => true,
+        }
+    }
+
+    /// Records a payload in the history of a peer, or excludes the peer if the payload is
+    /// inconsistent with its history.
+    fn record_payload(&mut self, peer_index: PeerIndex, payload: Payload) {
+        let frame = self.incoming_frame.take();
+        let result = self.histories[live_index(peer_index)].as_mut().unwrap()
+            .try_record_payload_with_frame(payload, frame);
+        if let Err(_err) = result {
+            // TODO log: format!("inconsistent payload: {:?}", err)
+            self.exclude(peer_index);
+        }
+    }
+
+    /// Excludes a peer from the remainder of the run.
+    ///
+    /// The caller is responsible for forwarding the exclusion to the broadcast mechanism,
+    /// e.g., via `ReadAuthenticatedPayloads::exclude()`, which drops further messages from the
+    /// peer. If fewer than `min_peers` peers remain, the execution is aborted.
+    fn exclude(&mut self, peer_index: PeerIndex) {
+        let index = live_index(peer_index);
+        self.kepks[index] = None;
+        self.histories[index] = None;
+        self.next_kepks[index] = None;
+        self.excluded.insert(index);
+
+        if self.num_live_peers() < self.min_peers {
+            self.outcome = Some(RunOutcome::Aborted { remaining: live_peers(&self.kepks) });
+        }
+    }
+
+    /// Returns `true` if too few peers remain to continue, see `exclude()`.
+    #[inline]
+    fn aborted(&self) -> bool {
+        match self.outcome {
+            Some(RunOutcome::Aborted { .. }) => true,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    fn is_live(&self, peer_index: PeerIndex) -> bool {
+        peer_index_to_usize(peer_index).and_then(|index| self.kepks.get(index)).map_or(false, Option::is_some)
+    }
+
+    #[inline]
+    fn num_live_peers(&self) -> usize {
+        self.kepks.iter().filter(|kepk| kepk.is_some()).count()
     }
 
     #[inline]
     fn consistent(&self) -> bool {
-        unimplemented!()
+        self.kepks.len() == self.histories.len()
+            && self.kepks.iter().zip(self.histories.iter()).all(|(kepk, history)| {
+                kepk.is_some() == history.is_some()
+                    && history.as_ref().map_or(true, RunHistory::consistent)
+            })
+            && self.excluded.iter().all(|index| self.kepks[index].is_none())
+            && self.confirmations.len() == self.kepks.len()
+            && self.next_kepks.iter().zip(self.kepks.iter()).all(|(next_kepk, kepk)| {
+                next_kepk.is_none() || kepk.is_some()
+            })
+            && self.kepks_before_dc_exponential.len() == self.kepks.len()
+            && self.kepks.iter().zip(self.kepks_before_dc_exponential.iter()).all(|(kepk, initial_kepk)| {
+                kepk.is_none() || kepk == initial_kepk
+            })
+            && self.peers_before_dc_exponential.as_ref().map_or(true, |peers| {
+                peers.is_subset(&live_peers(&self.kepks_before_dc_exponential))
+            })
+    }
+}
+
+/// Returns the state following a successful DC-net phase.
+#[inline]
+fn next_after_dc(phase: DcPhase) -> RunState {
+    match phase {
+        DcPhase::Exponential => RunState::DcProcess(DcPhase::Main),
+        DcPhase::Main => RunState::Confirm,
+    }
+}
+
+/// Returns `true` if a run may move from state `from` to state `to`.
+///
+/// The run can only move forward: a DC-net phase is followed by the reveal round of the same
+/// phase, by the next phase, or by the blame phase. `Blame` and `Confirm` are final, and in
+/// particular, there is no way from `Blame` to `Confirm` or vice versa.
+fn can_transition(from: RunState, to: RunState) -> bool {
+    match from {
+        RunState::DcProcess(phase) => {
+            to == RunState::DcReveal(phase) || to == next_after_dc(phase) || to == RunState::Blame
+        },
+        RunState::DcReveal(phase) => to == next_after_dc(phase) || to == RunState::Blame,
+        RunState::Blame | RunState::Confirm => false,
     }
 }
 
+/// Checks the syntax of confirmation data in the PlainEcdsa variant.
+///
+/// The confirmation data is a non-empty concatenation of compact ECDSA signatures.
+fn confirm_data_valid(data: &[u8]) -> bool {
+    let sig_size = secp256k1::constants::COMPACT_SIGNATURE_SIZE;
+    !data.is_empty() && data.len() % sig_size == 0
+        && data.chunks(sig_size).all(|sig| secp256k1::Signature::from_compact(&::SECP256K1, sig).is_ok())
+}
+
+/// Returns `true` if some message occurs more than once.
+fn has_collision(messages: &[Fp]) -> bool {
+    let mut sorted = messages.to_vec();
+    sorted.sort();
+    sorted.windows(2).any(|pair| pair[0] == pair[1])
+}
+
+/// Returns the set of peers whose key is present.
+fn peer_indices(set: &BitSet) -> Vec<PeerIndex> {
+    set.iter().map(to_peer_index).collect()
+}
+
+/// Converts the index of a peer that is part of the execution into an index of a vector with
+/// one entry per peer, see `peer_index_to_usize()`.
+///
+/// Such indices have been checked against the vectors of the execution before, so the
+/// conversion cannot fail.
+#[inline]
+fn live_index(peer_index: PeerIndex) -> usize {
+    peer_index_to_usize(peer_index).expect("the index of a peer in the execution fits into a usize")
+}
+
+/// Converts an index of a vector with one entry per peer into a peer index, see
+/// `usize_to_peer_index()`.
+///
+/// The number of peers is checked when the execution is set up and when peers join, so the
+/// conversion cannot fail.
+#[inline]
+fn to_peer_index(index: usize) -> PeerIndex {
+    usize_to_peer_index(index).expect("the number of peers fits into a PeerIndex")
+}
+
+fn live_peers<T>(peervec: &PeerVec<T>) -> BitSet {
+    // Sized for all peers, so that inserting any peer later does not reallocate.
+    let mut set = BitSet::with_capacity(peervec.len());
+    set.extend(peervec.live_peers().map(|(i, _)| live_index(i)));
+    set
+}
+
+#[cfg(test)]
+pub mod tests {
+    use rand::Rng;
+
+    use dc::xor::XorVec;
+    use io::RejectReason;
+    use super::*;
+
+    pub fn new_keys(num_peers: usize) -> (Vec<SecretKey>, PeerVec<PublicKey>) {
+        let sks: Vec<_> = (0..num_peers).map(|i| {
+            SecretKey::from_slice(&::SECP256K1, &[i as u8 + 1; 32]).unwrap()
+        }).collect();
+        let kepks = sks.iter().map(|sk| {
+            Some(PublicKey::from_secret_key(&::SECP256K1, sk).unwrap())
+        }).collect();
+        (sks, kepks)
+    }
+
+    fn new_kepks(num_peers: usize) -> PeerVec<PublicKey> {
+        new_keys(num_peers).1
+    }
+
+    /// Creates a peer for every secret key, whose long-term verification key is its public key.
+    pub fn new_peers(sks: &[SecretKey]) -> Vec<Peer> {
+        sks.iter().enumerate().map(|(i, sk)| {
+            let ltvk = PublicKey::from_secret_key(&::SECP256K1, sk).unwrap();
+            Peer::new(PeerId::from_bytes(&[i as u8; 64]), ltvk)
+        }).collect()
+    }
+
+    /// Creates an execution of `peers`, all of which must have an ephemeral public key.
+    pub fn new_execution<'a>(peers: &'a Vec<Peer>, options: Options, kepks: &PeerVec<PublicKey>) -> Execution<'a> {
+        Execution::new(peers, options, kepks.iter().map(|kepk| kepk.unwrap()).collect()).unwrap()
+    }
+
+    fn honest_dc_exponential(me: usize, sks: &[SecretKey], kepks: &PeerVec<PublicKey>, msg: Fp)
+        -> DcExponential
+    {
+        honest_dc_exponential_in_run(0, me, sks, kepks, msg)
+    }
+
+    fn honest_dc_exponential_in_run(run: u32, me: usize, sks: &[SecretKey], kepks: &PeerVec<PublicKey>, msg: Fp)
+        -> DcExponential
+    {
+        let mut power = msg;
+        let mut dc_exp: Vec<_> = kepks.iter().map(|_| {
+            let x = power;
+            power *= msg;
+            x
+        }).collect();
+
+        for other in (0..kepks.len()).filter(|&other| other != me) {
+            let key = blame::shared_key(&sks[me], kepks[other].as_ref().unwrap());
+            let mut rng = blame::pad_rng(&key, run, DcPhase::Exponential);
+            let sign = blame::pad_sign(me as PeerIndex, other as PeerIndex);
+            for x in dc_exp.iter_mut() {
+                sign.apply(x, rng.gen::<Fp>());
+            }
+        }
+
+        DcExponential::new(dc_exp)
+    }
+
+    /// Chooses a random message that `SmallSolver` finds.
+    pub fn small_exponential_message<R: Rng>(rng: &mut R) -> Fp {
+        Fp::from_u127(rng.gen_range(1, SmallSolver::BOUND))
+    }
+
+    /// A solver for messages smaller than `SmallSolver::BOUND`
+    pub struct SmallSolver;
+
+    impl SmallSolver {
+        const BOUND: u128 = 1024;
+    }
+
+    impl Solve for SmallSolver {
+        fn solve(power_sums: &Vec<Fp>) -> Option<Vec<Fp>> {
+            // Inverts a small integer modulo the prime by the extended Euclidean algorithm.
+            fn inverse(k: u128) -> Fp {
+                let (mut r0, mut r1) = (Fp::prime() as i128, k as i128);
+                let (mut t0, mut t1) = (0i128, 1i128);
+                while r1 != 0 {
+                    let q = r0 / r1;
+                    let (r, t) = (r0 - q * r1, t0 - q * t1);
+                    r0 = r1;
+                    r1 = r;
+                    t0 = t1;
+                    t1 = t;
+                }
+                if t0 < 0 { Fp::from_u127((t0 + Fp::prime() as i128) as u128) } else { Fp::from_u127(t0 as u128) }
+            }
+
+            // Newton's identities give the elementary symmetric polynomials of the messages,
+            // which are the coefficients of the polynomial whose roots are the messages.
+            let n = power_sums.len();
+            let mut e = vec![Fp::from_u127(1)];
+            for k in 1..n + 1 {
+                let mut sum = Fp::from_u127(0);
+                for i in 1..k + 1 {
+                    let term = e[k - i] * power_sums[i - 1];
+                    if i % 2 == 1 { sum += term } else { sum -= term }
+                }
+                e.push(sum * inverse(k as u128));
+            }
+            let mut coeffs: Vec<_> = e.into_iter().enumerate().map(|(k, e)| {
+                if k % 2 == 0 { e } else { -e }
+            }).collect();
+
+            let mut messages = vec![];
+            for c in (0..Self::BOUND).map(Fp::from_u127) {
+                loop {
+                    // Synthetic division by (x - c)
+                    let mut quotient = vec![];
+                    let mut rem = Fp::from_u127(0);
+                    for &a in coeffs.iter() {
+                        rem = rem * c + a;
+                        quotient.push(rem);
+                    }
+                    if coeffs.len() == 1 || rem != Fp::from_u127(0) {
+                        break;
+                    }
+                    quotient.pop();
+                    coeffs = quotient;
+                    messages.push(c);
+                }
+            }
+            if messages.len() == n { Some(messages) } else { None }
+        }
+    }
+
+    /// Creates a `DcExponential` payload without pads for the message `peer_index + 1`.
+    pub fn dc_exponential(peer_index: PeerIndex, num_slots: usize) -> IncomingPayload {
+        let msg = Fp::from_u127(peer_index as u128 + 1);
+        let dc_exp = (1..num_slots as u64 + 1).map(|k| msg.pow(k)).collect();
+        IncomingPayload::Valid(Payload::DcExponential(DcExponential::new(dc_exp)))
+    }
+
+    #[test]
+    fn transitions() {
+        use self::RunState::*;
+        use self::DcPhase::*;
+
+        let states = [DcProcess(Exponential), DcReveal(Exponential), DcProcess(Main), DcReveal(Main), Blame, Confirm];
+        let legal = [
+            (DcProcess(Exponential), DcReveal(Exponential)),
+            (DcProcess(Exponential), DcProcess(Main)),
+            (DcProcess(Exponential), Blame),
+            (DcReveal(Exponential), DcProcess(Main)),
+            (DcReveal(Exponential), Blame),
+            (DcProcess(Main), DcReveal(Main)),
+            (DcProcess(Main), Confirm),
+            (DcProcess(Main), Blame),
+            (DcReveal(Main), Confirm),
+            (DcReveal(Main), Blame),
+        ];
+        for &from in states.iter() {
+            for &to in states.iter() {
+                let is_legal = legal.contains(&(from, to));
+                assert_eq!(can_transition(from, to), is_legal, "{:?} to {:?}", from, to);
+                // The run only moves forward.
+                assert!(!is_legal || from < to);
+            }
+        }
+
+        // Every legal transition succeeds.
+        for &(from, to) in legal.iter() {
+            let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+            rsm.state = from;
+            rsm.peers_before_dc_main = Some(live_peers(&rsm.kepks));
+            rsm.set_state(to);
+            assert_eq!(rsm.state, to);
+        }
+
+        assert!(!can_transition(DcReveal(Exponential), DcReveal(Main)));
+        assert!(!can_transition(DcProcess(Exponential), Confirm));
+        assert!(!can_transition(DcProcess(Main), DcProcess(Exponential)));
+    }
+
+    #[test]
+    #[should_panic(expected = "illegal transition")]
+    fn transition_blame_to_confirm() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+        rsm.set_state(RunState::Blame);
+        rsm.set_state(RunState::Confirm);
+    }
+
+    #[test]
+    #[should_panic(expected = "illegal transition")]
+    fn transition_confirm_to_blame() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+        rsm.set_state(RunState::DcProcess(DcPhase::Main));
+        rsm.set_state(RunState::Confirm);
+        rsm.set_state(RunState::Blame);
+    }
+
+    #[test]
+    fn no_reallocation_in_round() {
+        // More peers than bits in a block of a `BitSet`
+        let num_peers = 40;
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(num_peers));
+        let capacities = |rsm: &RunStateMachine| {
+            (rsm.received.capacity(), rsm.excluded.capacity(), rsm.malicious.capacity(),
+             rsm.histories.capacity(), rsm.confirmations.capacity(), rsm.next_kepks.capacity())
+        };
+        let before = capacities(&rsm);
+        assert!(rsm.received.capacity() >= num_peers);
+        assert!(rsm.peers_before_dc_exponential.as_ref().unwrap().capacity() >= num_peers);
+
+        for i in 0..num_peers as PeerIndex {
+            rsm.apply_incoming_message((i, dc_exponential(i, num_peers)));
+            assert_eq!(capacities(&rsm), before);
+        }
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+    }
+
+    #[test]
+    fn exclude_unexpected_payload() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        let confirm = IncomingPayload::Valid(Payload::Confirm(Confirm { data: vec![] }));
+
+        rsm.apply_incoming_message((0, dc_exponential(0, 4)));
+        rsm.apply_incoming_message((1, confirm));
+        rsm.apply_incoming_message((2, dc_exponential(2, 4)));
+
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Exponential));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1]);
+        assert!(rsm.kepks[1].is_none() && rsm.histories[1].is_none());
+        for &i in &[0, 2] {
+            assert!(rsm.histories[i].as_ref().unwrap().dc_exponential.is_some());
+        }
+        assert!(rsm.is_live(3));
+    }
+
+    #[test]
+    fn exclude_invalid_below_quorum() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(2));
+        rsm.apply_incoming_message((0, IncomingPayload::Invalid(RejectReason::BadSignature)));
+
+        let remaining: BitSet = vec![1].into_iter().collect();
+        assert_eq!(rsm.outcome, Some(RunOutcome::Aborted { remaining }));
+        assert_eq!(rsm.num_live_peers(), 1);
+
+        // Further messages are ignored.
+        rsm.apply_incoming_message((1, dc_exponential(1, 2)));
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Exponential));
+        assert!(rsm.histories[1].as_ref().unwrap().dc_exponential.is_none());
+    }
+
+    #[test]
+    fn execution_aborted() {
+        let (sks, kepks) = new_keys(4);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().min_peers(3).build().unwrap();
+        let kepks: Vec<_> = kepks.into_iter().map(Option::unwrap).collect();
+        let mut execution = Execution::new(&peers, options, kepks.clone()).unwrap();
+
+        let invalid = || IncomingPayload::Invalid(RejectReason::BadSignature);
+        assert_eq!(execution.process((0, dc_exponential(0, 4))), Async::NotReady);
+        assert_eq!(execution.process((3, invalid())), Async::NotReady);
+        let remaining: BitSet = vec![0, 2].into_iter().collect();
+        let aborted = RunOutcome::Aborted { remaining };
+        assert_eq!(execution.process((1, invalid())), Async::Ready(aborted.clone()));
+        assert_eq!(execution.process((2, dc_exponential(2, 4))), Async::Ready(aborted));
+
+        let options = OptionsBuilder::new().min_peers(5).build().unwrap();
+        assert_eq!(Execution::new(&peers, options, kepks).err(), Some(SetupError::TooFewPeers));
+    }
+
+    #[test]
+    fn blame_identifies_deviating_peer() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+
+        for i in 0..3 {
+            let mut pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(100 + i as u128));
+            if i == 1 {
+                // Deviate from the pads but commit properly.
+                pay.dc_exp[1] += Fp::from_u127(1);
+                pay = DcExponential::new(pay.dc_exp);
+            }
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+
+        // The DC-net cannot be solved, so the peers enter the blame phase.
+        assert_eq!(rsm.state, RunState::Blame);
+        for i in 0..3 {
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+
+        assert_eq!(rsm.malicious.iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn blame_after_failed_reveal() {
+        let (sks, kepks) = new_keys(4);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(100 + i as u128));
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+        rsm.on_timeout(3);
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Exponential));
+
+        // Peer 2 fails to reveal its key shared with peer 3, so it has never revealed a key for
+        // any peer and its pads cannot be removed.
+        for i in 0..2 {
+            let key = blame::shared_key(&sks[i], &kepks[3].unwrap());
+            rsm.apply_incoming_message((i as PeerIndex, reveal(vec![(3, key)])));
+        }
+        rsm.on_timeout(2);
+        assert_eq!(rsm.state, RunState::Blame);
+
+        // The keys shared with the excluded peers 2 and 3 are derived from their keys at the
+        // beginning of the run, so the honest peers are not blamed.
+        for i in 0..2 {
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+        assert!(rsm.malicious.is_empty());
+        assert_eq!(rsm.outcome, Some(RunOutcome::Failed { excluded: vec![2, 3].into_iter().collect() }));
+    }
+
+    #[test]
+    fn blame_wrong_revealed_key() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..2 {
+            let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(100 + i as u128));
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+        rsm.on_timeout(2);
+
+        // Peer 1 reveals a wrong key, so the pads of peer 2 cannot be removed.
+        rsm.apply_incoming_message((0, reveal(vec![(2, blame::shared_key(&sks[0], &kepks[2].unwrap()))])));
+        rsm.apply_incoming_message((1, reveal(vec![(2, SymmetricKey([0x12; 32]))])));
+        assert_eq!(rsm.state, RunState::Blame);
+        for i in 0..2 {
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+        assert_eq!(rsm.malicious.iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn retry_after_collision() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+
+        let (_, next_kepks) = new_keys(6);
+
+        // Peers 0 and 2 choose the same message.
+        for &(i, msg) in &[(0, 7), (1, 8), (2, 7)] {
+            let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(msg));
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+
+        // The peers announce their keys for the next run and publish their current keys.
+        assert_eq!(rsm.state, RunState::Blame);
+        assert!(rsm.outcome.is_none());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i as PeerIndex, key_exchange(next_kepks[3 + i].unwrap())));
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+
+        assert_eq!(rsm.outcome, Some(RunOutcome::Failed { excluded: BitSet::new() }));
+        assert_eq!(rsm.num_live_peers(), 3);
+        assert!(rsm.malicious.is_empty());
+        assert_eq!(rsm.next_kepks.iter().cloned().collect::<Vec<_>>(), next_kepks[3..].to_vec());
+    }
+
+    #[test]
+    fn dc_exponential_solved() {
+        let (sks, kepks) = new_keys(3);
+        let msgs = [5, 3, 9];
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(msgs[i]));
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+
+        let mut expected: Vec<_> = msgs.iter().map(|&msg| Fp::from_u127(msg)).collect();
+        expected.sort();
+        let mut messages = rsm.solve_dc_exponential().unwrap();
+        messages.sort();
+        assert_eq!(messages, expected);
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+        assert!(rsm.outcome.is_none());
+    }
+
+    #[test]
+    fn exclude_stale_commitment() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+        let mut pay = DcExponential::new(vec![Fp::from_u127(0); 3]);
+        pay.dc_exp[0] = Fp::from_u127(1);
+
+        rsm.apply_incoming_message((0, dc_exponential(0, 3)));
+        rsm.apply_incoming_message((1, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1]);
+        assert!(rsm.histories[0].as_ref().unwrap().dc_exponential.is_some());
+    }
+
+    #[test]
+    fn blame_wrong_secret_key() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        rsm.set_state(RunState::Blame);
+
+        // Peer 0 claims the secret key of peer 2.
+        let blame = Blame { ke_sk: Secret::new(sks[2]) };
+        rsm.apply_incoming_message((0, IncomingPayload::Valid(Payload::Blame(blame))));
+
+        assert_eq!(rsm.malicious.iter().collect::<Vec<_>>(), vec![0]);
+    }
+
+    fn reveal(keys: Vec<(PeerIndex, SymmetricKey)>) -> IncomingPayload {
+        IncomingPayload::Valid(Payload::Reveal(Reveal { keys }))
+    }
+
+    #[test]
+    fn reveal_after_missing_peer() {
+        let (sks, kepks) = new_keys(3);
+        let run = |msgs: &[u128]| {
+            let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+            for (i, &msg) in msgs.iter().enumerate() {
+                let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(msg));
+                rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+            }
+            rsm.apply_incoming_message((2, IncomingPayload::Invalid(RejectReason::BadSignature)));
+            assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Exponential));
+
+            // The revealed keys remove the pads of the missing peer 2.
+            for i in 0..2 {
+                let key = blame::shared_key(&sks[i], &kepks[2].unwrap());
+                rsm.apply_incoming_message((i as PeerIndex, reveal(vec![(2, key)])));
+            }
+            rsm
+        };
+
+        let rsm = run(&[100, 101]);
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+        let key = blame::shared_key(&sks[1], &kepks[2].unwrap());
+        assert_eq!(rsm.histories[1].as_ref().unwrap().revealed_symmetric_keys[2], Some(key));
+
+        // A collision is detected after removing the pads as well, and the remaining peers
+        // exchange keys for the next run.
+        let mut rsm = run(&[100, 100]);
+        assert_eq!(rsm.state, RunState::Blame);
+        for i in 0..2 {
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+        assert_eq!(rsm.outcome, Some(RunOutcome::Failed { excluded: vec![2].into_iter().collect() }));
+        assert!(rsm.malicious.is_empty());
+    }
+
+    #[test]
+    fn revealed_keys_zeroed_after_run() {
+        use super::history::tests::{leftover_after_drop, KEY_BYTE};
+
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+        rsm.apply_incoming_message((0, dc_exponential(0, 3)));
+        rsm.apply_incoming_message((1, dc_exponential(1, 3)));
+        rsm.apply_incoming_message((2, IncomingPayload::Invalid(RejectReason::BadSignature)));
+        rsm.apply_incoming_message((0, reveal(vec![(2, SymmetricKey([KEY_BYTE; 32]))])));
+        rsm.apply_incoming_message((1, reveal(vec![(2, SymmetricKey([KEY_BYTE; 32]))])));
+        let keys = &rsm.histories[0].as_ref().unwrap().revealed_symmetric_keys;
+        assert_eq!(keys[2], Some(SymmetricKey([KEY_BYTE; 32])));
+
+        let ptr = keys.as_ptr() as *const u8;
+        assert_eq!(leftover_after_drop(ptr, rsm), 0);
+    }
+
+    #[test]
+    fn blame_secret_zeroed_on_drop() {
+        use super::history::tests::{leftover_after_drop, KEY_BYTE};
+
+        let sk = SecretKey::from_slice(&::SECP256K1, &[KEY_BYTE; 32]).unwrap();
+        // Boxed, so that the allocator can observe the memory of the secret when it is freed. A
+        // boxed `Payload` would also hold uninitialized bytes of its larger variants, which may
+        // resemble the key by chance.
+        let blame = Box::new(Blame { ke_sk: Secret::new(sk) });
+        let copy = blame.clone();
+        let ptr = &*blame as *const Blame as *const u8;
+        assert_eq!(leftover_after_drop(ptr, blame), 0);
+        let ptr = &*copy as *const Blame as *const u8;
+        assert_eq!(leftover_after_drop(ptr, copy), 0);
+    }
+
+    #[test]
+    fn dc_exponential_wrong_length() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        rsm.apply_incoming_message((0, dc_exponential(0, 4)));
+        rsm.apply_incoming_message((1, dc_exponential(1, 5)));
+        rsm.apply_incoming_message((2, dc_exponential(2, 3)));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Exponential));
+
+        // The remaining peers proceed to reveal the keys shared with the excluded peers.
+        rsm.apply_incoming_message((3, dc_exponential(3, 4)));
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Exponential));
+        assert!(rsm.is_live(0) && rsm.is_live(3));
+    }
+
+    #[test]
+    fn reveal_twice() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+        rsm.apply_incoming_message((3, IncomingPayload::Invalid(RejectReason::BadSignature)));
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Exponential));
+
+        rsm.apply_incoming_message((0, reveal(vec![(3, SymmetricKey([0x03; 32]))])));
+        rsm.apply_incoming_message((1, reveal(vec![(3, SymmetricKey([0x13; 32])), (3, SymmetricKey([0x13; 32]))])));
+        assert!(rsm.excluded.contains(1));
+        rsm.apply_incoming_message((2, reveal(vec![(3, SymmetricKey([0x23; 32]))])));
+
+        // Peer 1 has not properly revealed its keys, so the run cannot continue.
+        assert_eq!(rsm.state, RunState::Blame);
+    }
+
+    #[test]
+    fn reveal_key_for_live_peer() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+        rsm.apply_incoming_message((3, IncomingPayload::Invalid(RejectReason::BadSignature)));
+
+        rsm.apply_incoming_message((0, reveal(vec![(1, SymmetricKey([0x01; 32]))])));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![0, 3]);
+    }
+
+    #[test]
+    fn reveal_incomplete() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        rsm.apply_incoming_message((0, dc_exponential(0, 4)));
+        rsm.apply_incoming_message((1, dc_exponential(1, 4)));
+        rsm.on_timeout(2);
+        rsm.on_timeout(3);
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Exponential));
+
+        // Peer 0 omits the key shared with peer 3.
+        rsm.apply_incoming_message((0, reveal(vec![(2, SymmetricKey([0x02; 32]))])));
+        assert!(rsm.excluded.contains(0));
+        assert!(rsm.histories[1].as_ref().unwrap().revealed_symmetric_keys.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn reveal_over_inclusive() {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+        rsm.on_timeout(3);
+
+        // Peer 1 reveals the key for the missing peer 3 and additionally the key for peer 2.
+        rsm.apply_incoming_message((0, reveal(vec![(3, SymmetricKey([0x03; 32]))])));
+        rsm.apply_incoming_message((1, reveal(vec![(3, SymmetricKey([0x13; 32])), (2, SymmetricKey([0x12; 32]))])));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1, 3]);
+        assert!(!rsm.excluded.contains(0));
+    }
+
+    pub fn dc_main(num_slots: usize, kepk: PublicKey) -> IncomingPayload {
+        IncomingPayload::Valid(Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; 16]); num_slots]),
+            ke_pk: kepk,
+            extension: Extension::None,
+            early_confirm: None,
+        }))
+    }
+
+    #[test]
+    fn timeout_in_dc_main() {
+        let kepks = new_kepks(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+
+        rsm.apply_incoming_message((0, dc_main(3, kepks[0].unwrap())));
+        // A timeout after the message has arrived is a no-op.
+        rsm.on_timeout(0);
+        assert!(rsm.is_live(0));
+
+        rsm.apply_incoming_message((1, dc_main(3, kepks[1].unwrap())));
+        rsm.on_timeout(2);
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Main));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![2]);
+
+        rsm.apply_incoming_message((0, reveal(vec![(2, SymmetricKey([0x02; 32]))])));
+        rsm.apply_incoming_message((1, reveal(vec![(2, SymmetricKey([0x12; 32]))])));
+        assert_eq!(rsm.state, RunState::Confirm);
+    }
+
+    #[test]
+    fn late_and_duplicate_messages() {
+        let kepks = new_kepks(4);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        rsm.apply_incoming_message((0, dc_exponential(0, 4)));
+        rsm.apply_incoming_message((1, dc_exponential(1, 4)));
+        // A second message in the same round excludes the peer.
+        rsm.apply_incoming_message((1, dc_exponential(1, 4)));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1]);
+
+        rsm.on_timeout(3);
+        rsm.apply_incoming_message((2, dc_exponential(2, 4)));
+        // A late message from an excluded peer is ignored.
+        rsm.apply_incoming_message((3, dc_exponential(3, 4)));
+        assert!(!rsm.is_live(3));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn dc_main_shape() {
+        let kepks = new_kepks(4);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        rsm.slot_bytes = 16;
+        for i in 0..4 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+
+        rsm.apply_incoming_message((0, dc_main(4, kepks[0].unwrap())));
+        // Wrong number of slots
+        rsm.apply_incoming_message((1, dc_main(3, kepks[1].unwrap())));
+        // Slots wider than slot_bytes
+        rsm.apply_incoming_message((2, IncomingPayload::Valid(Payload::DcMain(DcMain {
+            ok: true,
+            dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; 17]); 4]),
+            ke_pk: kepks[2].unwrap(),
+            extension: Extension::None,
+            early_confirm: None,
+        }))));
+        assert!(rsm.is_live(0));
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    /// Creates a `DcMain` payload with pads that contains `msg` in slot `me` of `num_slots`.
+    fn honest_dc_main(me: usize, sks: &[SecretKey], kepks: &PeerVec<PublicKey>, num_slots: usize, msg: &[u8])
+        -> IncomingPayload
+    {
+        honest_dc_main_in_slot(me, sks, kepks, num_slots, Some(me), msg)
+    }
+
+    /// Creates a `DcMain` payload with pads that contains `msg` in `slot` of `num_slots`, or no
+    /// message and `ok == false` if `slot` is `None`.
+    fn honest_dc_main_in_slot(me: usize,
+                              sks: &[SecretKey],
+                              kepks: &PeerVec<PublicKey>,
+                              num_slots: usize,
+                              slot: Option<usize>,
+                              msg: &[u8]) -> IncomingPayload {
+        let mut slots = vec![XorVec::from(vec![0u8; msg.len()]); num_slots];
+        if let Some(slot) = slot {
+            slots[slot] = XorVec::from(msg.to_vec());
+        }
+        let mut dc_xor = XorVec::from(slots);
+        for (_, kepk) in kepks.live_peers().filter(|&(other, _)| other as usize != me) {
+            let key = blame::shared_key(&sks[me], kepk);
+            let mut pad = dc_xor.clone();
+            pad.randomize(&mut blame::pad_rng(&key, 0, DcPhase::Main));
+            dc_xor -= pad;
+        }
+        IncomingPayload::Valid(Payload::DcMain(DcMain {
+            ok: slot.is_some(),
+            dc_xor,
+            ke_pk: kepks[me].unwrap(),
+            extension: Extension::None,
+            early_confirm: None,
+        }))
+    }
+
+    fn main_messages(num_peers: usize) -> Vec<XorVec<u8>> {
+        (0..num_peers).map(|i| XorVec::from(vec![i as u8 + 1, 0xa0, 0xb0 + i as u8])).collect()
+    }
+
+    #[test]
+    fn dc_main_pads_cancel() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+
+        let msgs = main_messages(3);
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, honest_dc_main(i as usize, &sks, &kepks, 3, &msgs[i as usize])));
+            assert!(rsm.is_live(i));
+        }
+        assert_eq!(rsm.state, RunState::Confirm);
+        assert_eq!(rsm.messages, Some(msgs));
+    }
+
+    #[test]
+    fn dc_main_pads_removed_after_reveal() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+
+        // Peer 2 has sent its pads to nobody, so its slot stays empty.
+        let mut msgs = main_messages(3);
+        msgs[2] = XorVec::from(vec![0; 3]);
+        for i in 0..2 {
+            rsm.apply_incoming_message((i, honest_dc_main(i as usize, &sks, &kepks, 3, &msgs[i as usize])));
+        }
+        rsm.on_timeout(2);
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Main));
+
+        for i in 0..2 {
+            let key = blame::shared_key(&sks[i as usize], kepks[2].as_ref().unwrap());
+            rsm.apply_incoming_message((i, reveal(vec![(2, key)])));
+        }
+        assert_eq!(rsm.state, RunState::Confirm);
+        assert_eq!(rsm.messages, Some(msgs));
+    }
+
+    #[test]
+    fn dc_main_not_ok() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+
+        let msgs = main_messages(3);
+        for i in 0..3 {
+            let mut incoming = honest_dc_main(i as usize, &sks, &kepks, 3, &msgs[i as usize]);
+            if let IncomingPayload::Valid(Payload::DcMain(ref mut pay)) = incoming {
+                pay.ok = i != 1;
+            }
+            rsm.apply_incoming_message((i, incoming));
+        }
+        assert_eq!(rsm.state, RunState::Blame);
+        assert_eq!(rsm.messages, None);
+    }
+
+    #[test]
+    fn dc_main_not_ok_after_reveal() {
+        let (sks, kepks) = new_keys(4);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..4 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+        }
+
+        // Peer 1 has not found its message, and peer 3 goes missing.
+        let msgs = main_messages(4);
+        for i in 0..3 {
+            let mut incoming = honest_dc_main(i as usize, &sks, &kepks, 4, &msgs[i as usize]);
+            if let IncomingPayload::Valid(Payload::DcMain(ref mut pay)) = incoming {
+                pay.ok = i != 1;
+            }
+            rsm.apply_incoming_message((i, incoming));
+            assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+        }
+        rsm.on_timeout(3);
+        assert_eq!(rsm.state, RunState::DcReveal(DcPhase::Main));
+
+        // Removing the pads of the missing peer does not help, so the run moves on to blame.
+        for i in 0..3 {
+            let key = blame::shared_key(&sks[i as usize], kepks[3].as_ref().unwrap());
+            rsm.apply_incoming_message((i, reveal(vec![(3, key)])));
+        }
+        assert_eq!(rsm.state, RunState::Blame);
+        assert_eq!(rsm.messages, None);
+    }
+
+    #[test]
+    fn blame_dc_main_not_ok() {
+        let (sks, kepks) = new_keys(3);
+        let run = |replace_msg_of_1: bool| {
+            let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+            for i in 0..3 {
+                let mut pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(100 + i as u128));
+                if i == 0 && replace_msg_of_1 {
+                    // Peer 0 replaces the message of peer 1 with another one.
+                    for (k, x) in pay.dc_exp.iter_mut().enumerate() {
+                        *x += Fp::from_u127(7).pow(k as u64 + 1);
+                        *x -= Fp::from_u127(101).pow(k as u64 + 1);
+                    }
+                    pay = DcExponential::new(pay.dc_exp);
+                }
+                rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+            }
+            assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+
+            // Peer 1 claims that its message is missing. The messages of peers 0 and 2 have
+            // ranks 1 and 2 if the message of peer 1 has been replaced with 7.
+            let msgs = main_messages(3);
+            let slots = if replace_msg_of_1 { [Some(1), None, Some(2)] } else { [Some(0), None, Some(2)] };
+            for i in 0..3 {
+                let incoming = honest_dc_main_in_slot(i, &sks, &kepks, 3, slots[i], &msgs[i]);
+                rsm.apply_incoming_message((i as PeerIndex, incoming));
+            }
+            assert_eq!(rsm.state, RunState::Blame);
+            for i in 0..3 {
+                let blame = Blame { ke_sk: Secret::new(sks[i]) };
+                rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+            }
+            rsm.malicious.iter().collect::<Vec<_>>()
+        };
+
+        // The claim is false if the message of peer 1 has been solved.
+        assert_eq!(run(false), vec![1]);
+        // Otherwise, the peer that has replaced the message is blamed.
+        assert_eq!(run(true), vec![0]);
+    }
+
+    #[test]
+    fn blame_dc_main_slots() {
+        let (sks, kepks) = new_keys(3);
+        // The messages of the peers have ranks 2, 0 and 1.
+        let exponential_messages = [300, 100, 200];
+        let run = |slots: [Option<usize>; 3]| {
+            let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+            for i in 0..3 {
+                let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(exponential_messages[i]));
+                rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+            }
+            let msgs = main_messages(3);
+            for i in 0..3 {
+                let incoming = honest_dc_main_in_slot(i, &sks, &kepks, 3, slots[i], &msgs[i]);
+                rsm.apply_incoming_message((i as PeerIndex, incoming));
+            }
+            if rsm.state != RunState::Blame {
+                return None;
+            }
+            for i in 0..3 {
+                let blame = Blame { ke_sk: Secret::new(sks[i]) };
+                rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+            }
+            Some(rsm.malicious.iter().collect::<Vec<_>>())
+        };
+
+        // Every peer sends its message in the slot at the rank of its message.
+        assert_eq!(run([Some(2), Some(0), Some(1)]), None);
+        // Peer 1 sends its message in the slot of peer 2 and another peer claims that its own
+        // message is missing, which triggers the blame phase.
+        assert_eq!(run([None, Some(1), Some(1)]), Some(vec![0, 1]));
+        // A peer that claims that its message is missing must not send a message.
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            let pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(exponential_messages[i]));
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+        let msgs = main_messages(3);
+        for &(i, slot) in &[(0, Some(2)), (2, Some(1))] {
+            rsm.apply_incoming_message((i as PeerIndex, honest_dc_main_in_slot(i, &sks, &kepks, 3, slot, &msgs[i])));
+        }
+        let mut incoming = honest_dc_main_in_slot(1, &sks, &kepks, 3, Some(0), &msgs[1]);
+        if let IncomingPayload::Valid(Payload::DcMain(ref mut pay)) = incoming {
+            pay.ok = false;
+        }
+        rsm.apply_incoming_message((1, incoming));
+        assert_eq!(rsm.state, RunState::Blame);
+        for i in 0..3 {
+            let blame = Blame { ke_sk: Secret::new(sks[i]) };
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::Blame(blame))));
+        }
+        assert_eq!(rsm.malicious.iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn dc_main_wrong_extension() {
+        use dc::scalar::Scalar;
+
+        let kepks = new_kepks(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+
+        let mut incoming = dc_main(3, kepks[1].unwrap());
+        if let IncomingPayload::Valid(Payload::DcMain(ref mut pay)) = incoming {
+            pay.extension = Extension::DcAddSecp256k1Scalar(vec![Scalar::default(); 3]);
+        }
+        rsm.apply_incoming_message((1, incoming));
+        assert!(!rsm.is_live(1));
+        rsm.apply_incoming_message((0, dc_main(3, kepks[0].unwrap())));
+        assert!(rsm.is_live(0));
+    }
+
+    #[test]
+    fn dc_main_slot_sizes_differ() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        for i in 0..3 {
+            rsm.apply_incoming_message((i, dc_exponential(i, 3)));
+        }
+
+        let msgs = main_messages(3);
+        for i in 0..2 {
+            rsm.apply_incoming_message((i, honest_dc_main(i as usize, &sks, &kepks, 3, &msgs[i as usize])));
+        }
+        rsm.apply_incoming_message((2, honest_dc_main(2, &sks, &kepks, 3, &[0x42; 5])));
+        assert_eq!(rsm.state, RunState::Blame);
+    }
+
+    pub fn confirm(sk: &SecretKey) -> IncomingPayload {
+        let msg = secp256k1::Message::from_slice(&[0xab; 32]).unwrap();
+        let sig = ::SECP256K1.sign(&msg, sk).unwrap();
+        let data = sig.serialize_compact(&::SECP256K1).to_vec();
+        IncomingPayload::Valid(Payload::Confirm(Confirm { data }))
+    }
+
+    fn run_until_confirm(kepks: &PeerVec<PublicKey>) -> RunStateMachine {
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        let num_peers = kepks.len();
+        for i in 0..num_peers {
+            rsm.apply_incoming_message((i as PeerIndex, dc_exponential(i as PeerIndex, num_peers)));
+        }
+        for i in 0..num_peers {
+            rsm.apply_incoming_message((i as PeerIndex, dc_main(num_peers, kepks[i].unwrap())));
+        }
+        assert_eq!(rsm.state, RunState::Confirm);
+        rsm
+    }
+
+    #[test]
+    fn confirm_success() {
+        let (sks, kepks) = new_keys(3);
+        let mut rsm = run_until_confirm(&kepks);
+        for i in 0..3 {
+            rsm.apply_incoming_message((i as PeerIndex, confirm(&sks[i])));
+            assert_eq!(rsm.outcome.is_some(), i == 2);
+        }
+
+        match rsm.outcome {
+            Some(RunOutcome::Success(ref confirmations)) => {
+                assert!(confirmations.iter().all(Option::is_some));
+            },
+            ref outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn confirm_missing() {
+        let (sks, kepks) = new_keys(4);
+        let mut rsm = run_until_confirm(&kepks);
+        rsm.apply_incoming_message((0, confirm(&sks[0])));
+        rsm.apply_incoming_message((1, IncomingPayload::Valid(Payload::Confirm(Confirm { data: vec![0; 3] }))));
+        rsm.on_timeout(2);
+        assert!(rsm.outcome.is_none());
+        rsm.apply_incoming_message((3, confirm(&sks[3])));
+
+        let excluded: BitSet = vec![1, 2].into_iter().collect();
+        assert_eq!(rsm.outcome, Some(RunOutcome::Failed { excluded }));
+    }
+
+    #[test]
+    fn confirm_schnorr_multi() {
+        let (sks, kepks) = new_keys(8);
+        // The long-term keys are distinct from the ephemeral ones, which serve as nonces here.
+        let ltsks = &sks[4..];
+        let ltvks: PeerVec<_> = kepks.iter().skip(4).cloned().collect();
+        let kepks: PeerVec<_> = kepks.iter().take(4).cloned().collect();
+        let confirm_phase = || {
+            let mut rsm = RunStateMachine::new(0, Variant::PlainSchnorrMulti, kepks.clone());
+            rsm.set_ltvks(ltvks.clone());
+            for i in 0..4 {
+                rsm.apply_incoming_message((i, dc_exponential(i, 4)));
+            }
+
+            // Peer 2 fails to send its nonce commitment.
+            for i in 0..4 {
+                let mut incoming = dc_main(4, kepks[i].unwrap());
+                if let IncomingPayload::Valid(Payload::DcMain(ref mut pay)) = incoming {
+                    if i != 2 {
+                        pay.early_confirm = Some(kepks[i].unwrap().serialize_vec(&::SECP256K1, true).to_vec());
+                    }
+                }
+                rsm.apply_incoming_message((i as PeerIndex, incoming));
+            }
+            assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![2]);
+            rsm.apply_incoming_message((0, reveal(vec![(2, SymmetricKey([0x02; 32]))])));
+            rsm.apply_incoming_message((1, reveal(vec![(2, SymmetricKey([0x12; 32]))])));
+            rsm.apply_incoming_message((3, reveal(vec![(2, SymmetricKey([0x32; 32]))])));
+            assert_eq!(rsm.state, RunState::Confirm);
+            rsm
+        };
+        let partial = |rsm: &RunStateMachine, i: usize, ltsk: &SecretKey| {
+            let partial = rsm.signing.as_ref().unwrap().sign(i as PeerIndex, ltsk, &sks[i]).unwrap();
+            IncomingPayload::Valid(Payload::Confirm(Confirm { data: partial.to_bytes().to_vec() }))
+        };
+
+        let mut rsm = confirm_phase();
+        for &i in &[0, 1, 3] {
+            let incoming = partial(&rsm, i, &ltsks[i]);
+            rsm.apply_incoming_message((i as PeerIndex, incoming));
+        }
+        match rsm.outcome {
+            Some(RunOutcome::SuccessMulti(ref multisig)) => {
+                assert!(multisig.verify(&schnorr::message_digest(rsm.messages.as_ref().unwrap())));
+            },
+            ref outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // A partial signature with the wrong key is detected on arrival.
+        let mut rsm = confirm_phase();
+        let incoming = partial(&rsm, 0, &ltsks[0]);
+        rsm.apply_incoming_message((0, incoming));
+        for &(i, ltsk) in &[(1, &ltsks[2]), (3, &ltsks[3])] {
+            let incoming = partial(&rsm, i, ltsk);
+            rsm.apply_incoming_message((i as PeerIndex, incoming));
+        }
+        let excluded: BitSet = vec![1, 2].into_iter().collect();
+        assert_eq!(rsm.outcome, Some(RunOutcome::Failed { excluded }));
+
+        // The signing session is set up again after resuming from a checkpoint.
+        let rsm = confirm_phase();
+        let mut resumed = RunStateMachine::resume(rsm.checkpoint()).unwrap();
+        resumed.set_ltvks(ltvks.clone());
+        resumed.apply_incoming_message((0, partial(&rsm, 0, &ltsks[0])));
+        assert!(resumed.is_live(0));
+        assert!(resumed.received.contains(0));
+    }
+
+    #[test]
+    fn restart_without_excluded() {
+        let (_, kepks) = new_keys(4);
+        let (_, next_kepks) = new_keys(8);
+        let next_kepks: PeerVec<_> = next_kepks.into_iter().skip(4).collect();
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks);
+        rsm.on_timeout(3);
+
+        let excluded: BitSet = vec![2].into_iter().collect();
+        let next = rsm.restart(&excluded, next_kepks.clone());
+
+        assert_eq!(next.count, 1);
+        assert_eq!(next.state, RunState::DcProcess(DcPhase::Exponential));
+        assert_eq!(live_peers(&next.kepks).iter().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(next.kepks[0], next_kepks[0]);
+        assert_eq!(next.kepks[1], next_kepks[1]);
+        assert!(next.histories[0].as_ref().unwrap().dc_exponential.is_none());
+    }
+
+    #[test]
+    fn execution_process() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+
+        for i in 0..3 {
+            assert_eq!(execution.process((i, dc_exponential(i, 3))), Async::NotReady);
+        }
+        for i in 0..3 {
+            let incoming = (i, dc_main(3, kepks[i as usize].unwrap()));
+            assert_eq!(execution.process(incoming), Async::NotReady);
+        }
+        for i in 0..2 {
+            assert_eq!(execution.process((i, confirm(&sks[i as usize]))), Async::NotReady);
+        }
+
+        match execution.process((2, confirm(&sks[2]))) {
+            Async::Ready(RunOutcome::Success(confirmations)) => {
+                assert_eq!(confirmations.len(), 3);
+                assert!(confirmations.iter().all(Option::is_some));
+            },
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn execution_blame_proofs() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().retain_frames(true).build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+
+        let signed = |peer_index: PeerIndex, payload: Payload| {
+            let header = Header::new(SessionId([0x5e; 32]), peer_index, 0);
+            let frame = io::encode_signed(&payload, &header, &sks[peer_index as usize]);
+            (peer_index, IncomingPayload::Valid(payload), Some(SignedFrame::split(&frame).unwrap()))
+        };
+
+        let mut frames = vec![];
+        for i in 0..3 {
+            let mut pay = honest_dc_exponential(i, &sks, &kepks, Fp::from_u127(100 + i as u128));
+            if i == 1 {
+                pay.dc_exp[1] += Fp::from_u127(1);
+                pay = DcExponential::new(pay.dc_exp);
+            }
+            let incoming = signed(i as PeerIndex, Payload::DcExponential(pay));
+            frames.push(incoming.2.clone().unwrap());
+            assert_eq!(execution.process_with_proof(incoming), Async::NotReady);
+        }
+        for i in 0..3 {
+            let blame = Payload::Blame(Blame { ke_sk: Secret::new(sks[i as usize]) });
+            let outcome = execution.process_with_proof(signed(i, blame));
+            assert_eq!(outcome == Async::NotReady, i < 2);
+        }
+
+        // Only the frame of the malicious peer is kept, and it proves who has sent it.
+        let proofs = execution.blame_proofs();
+        assert_eq!(proofs.live_peers().map(|(peer_index, _)| peer_index).collect::<Vec<_>>(), vec![1]);
+        let proof = &proofs[1].as_ref().unwrap()[..];
+        assert_eq!(proof, &frames[1..2]);
+        assert!(proof[0].verify(peers[1].ltvk()));
+        assert!(!proof[0].verify(peers[0].ltvk()));
+    }
+
+    #[test]
+    fn frames_not_retained_by_default() {
+        let (sks, kepks) = new_keys(2);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, kepks.clone());
+        let header = Header::new(SessionId([0x5e; 32]), 0, 0);
+        let payload = Payload::DcExponential(honest_dc_exponential(0, &sks, &kepks, Fp::from_u127(1)));
+        let frame = SignedFrame::split(&io::encode_signed(&payload, &header, &sks[0])).unwrap();
+
+        rsm.apply_incoming_message_with_frame((0, IncomingPayload::Valid(payload.clone())), Some(frame.clone()));
+        assert_eq!(rsm.histories[0].as_ref().unwrap().dc_exponential_frame, None);
+
+        rsm.retain_frames = true;
+        rsm.apply_incoming_message_with_frame((1, IncomingPayload::Valid(payload)), Some(frame.clone()));
+        assert_eq!(rsm.histories[1].as_ref().unwrap().frames(), vec![frame]);
+    }
+
+    /// Creates our own peer `me` with the keys from `new_keys()` and a deterministic rng.
+    pub fn own_peer(me: usize, ltsks: &[SecretKey], ke_sks: &[SecretKey], message: &[u8]) -> OwnPeer {
+        use rand::{SeedableRng, StdRng};
+
+        OwnPeer::new(me as PeerIndex, ltsks[me], ke_sks[me], message.to_vec(), |_| vec![[0xab; 32]])
+            .with_rng(StdRng::from_seed([me as u8; 32]))
+    }
+
+    #[test]
+    fn execution_handle_message() {
+        use std::collections::VecDeque;
+
+        let (sks, kepks) = new_keys(6);
+        let (ltsks, ke_sks) = sks.split_at(3);
+        let peers = new_peers(ltsks);
+        let kepks: Vec<_> = kepks.iter().skip(3).map(|kepk| kepk.unwrap()).collect();
+        let messages: Vec<_> = (0..3).map(|i| vec![0xc0 + i as u8; 1 + i]).collect();
+
+        for &variant in [Variant::PlainEcdsa, Variant::ValueShuffleElementsEcdsa, Variant::PlainSchnorrMulti].iter() {
+            let options = OptionsBuilder::new().variant(variant).slot_bytes(4).build().unwrap();
+            let mut executions: Vec<_> = (0..3).map(|i| {
+                let own = own_peer(i, ltsks, ke_sks, &messages[i]);
+                Execution::with_own_peer(&peers, options, kepks.clone(), own).unwrap()
+            }).collect();
+
+            // An in-memory broadcast bus, which delivers every message to every peer, including
+            // the sender, whose execution ignores it
+            let mut bus = VecDeque::new();
+            let mut done = vec![vec![]; 3];
+            let dispatch = |i: usize, actions: Vec<Action>, bus: &mut VecDeque<_>, done: &mut Vec<Vec<_>>| {
+                for action in actions {
+                    match action {
+                        Action::Send(payload) => bus.push_back((i as PeerIndex, payload)),
+                        Action::Done(outcome) => done[i].push(outcome),
+                        Action::Exclude(peer_index) => panic!("peer {} excluded", peer_index),
+                    }
+                }
+            };
+            for (i, execution) in executions.iter_mut().enumerate() {
+                let actions = execution.start();
+                dispatch(i, actions, &mut bus, &mut done);
+            }
+            while let Some((sender, payload)) = bus.pop_front() {
+                for (i, execution) in executions.iter_mut().enumerate() {
+                    let actions = execution.handle_message(sender, IncomingPayload::Valid(payload.clone()));
+                    dispatch(i, actions, &mut bus, &mut done);
+                }
+            }
+
+            assert!(done.iter().all(|outcomes| outcomes.len() == 1 && outcomes[0] == done[0][0]));
+            match done[0][0] {
+                RunOutcome::Success(ref confirmations) => {
+                    assert!(variant != Variant::PlainSchnorrMulti);
+                    for (i, data) in confirmations.iter().enumerate() {
+                        let confirm = Confirm { data: data.clone().unwrap() };
+                        assert!(confirm.verify(peers[i].ltvk(), &[[0xab; 32]]));
+                    }
+                },
+                RunOutcome::SuccessMulti(_) => assert_eq!(variant, Variant::PlainSchnorrMulti),
+                ref outcome => panic!("unexpected outcome {:?}", outcome),
+            }
+
+            // The slots of the main DC-net carry the messages, padded to the slot size.
+            let mixed = executions[0].rsm.messages.clone().unwrap();
+            for message in messages.iter() {
+                let mut padded = message.clone();
+                padded.resize(4, 0);
+                assert!(mixed.contains(&XorVec::from(padded)));
+            }
+            if let RunOutcome::SuccessMulti(ref multisig) = done[0][0] {
+                assert!(multisig.verify(&schnorr::message_digest(&mixed)));
+            }
+        }
+
+        // Our own message must fit into a slot, and our keys must be those of our peer.
+        let options = OptionsBuilder::new().slot_bytes(2).build().unwrap();
+        let own = own_peer(2, ltsks, ke_sks, &messages[2]);
+        assert_eq!(Execution::with_own_peer(&peers, options, kepks.clone(), own).err(), Some(SetupError::MessageTooLong));
+        let options = OptionsBuilder::new().build().unwrap();
+        let own = OwnPeer::new(1, ltsks[0], ke_sks[0], vec![], |_| vec![]);
+        assert_eq!(Execution::with_own_peer(&peers, options, kepks.clone(), own).err(), Some(SetupError::InvalidOwnPeer));
+
+        // Exclusions are reported immediately.
+        let (_, kepks) = new_keys(3);
+        let mut execution = new_execution(&peers, options, &kepks);
+        let invalid = IncomingPayload::Invalid(RejectReason::BadSignature);
+        assert_eq!(execution.handle_message(2, invalid), vec![Action::Exclude(2)]);
+        assert_eq!(execution.handle_message(0, dc_exponential(0, 3)), vec![]);
+    }
+
+    #[test]
+    fn execution_own_peer_blames_disruptor() {
+        use std::collections::VecDeque;
+
+        let (sks, kepks) = new_keys(8);
+        let (ltsks, ke_sks) = sks.split_at(4);
+        let peers = new_peers(ltsks);
+        let kepks: Vec<_> = kepks.iter().skip(4).map(|kepk| kepk.unwrap()).collect();
+        let options = OptionsBuilder::new().slot_bytes(4).build().unwrap();
+        // Peer 3 is driven by the test. It disrupts the exponential DC-net and goes missing in
+        // the blame phase.
+        let mut executions: Vec<_> = (0..3).map(|i| {
+            let own = own_peer(i, ltsks, ke_sks, &[i as u8 + 1]);
+            Execution::with_own_peer(&peers, options, kepks.clone(), own).unwrap()
+        }).collect();
+
+        let mut bus = VecDeque::new();
+        let mut outcomes = vec![vec![]; 3];
+        for (i, execution) in executions.iter_mut().enumerate() {
+            for action in execution.start() {
+                if let Action::Send(payload) = action {
+                    bus.push_back((i as PeerIndex, payload));
+                }
+            }
+        }
+        bus.push_back((3, Payload::DcExponential(DcExponential::new(vec![Fp::from_u127(5); 4]))));
+        while let Some((sender, payload)) = bus.pop_front() {
+            for (i, execution) in executions.iter_mut().enumerate() {
+                for action in execution.handle_message(sender, IncomingPayload::Valid(payload.clone())) {
+                    match action {
+                        Action::Send(payload) => bus.push_back((i as PeerIndex, payload)),
+                        Action::Done(outcome) => outcomes[i].push(outcome),
+                        Action::Exclude(_) => {},
+                    }
+                }
+            }
+            // Peer 3 does not take part in the blame phase.
+            if executions[0].dump_state().state == RunState::Blame && bus.is_empty() {
+                for (i, execution) in executions.iter_mut().enumerate() {
+                    for action in execution.handle_timeout(3) {
+                        match action {
+                            Action::Send(payload) => bus.push_back((i as PeerIndex, payload)),
+                            Action::Done(outcome) => outcomes[i].push(outcome),
+                            Action::Exclude(_) => {},
+                        }
+                    }
+                }
+            }
+        }
+
+        // The honest peers exclude peer 3 and succeed in the next run without it.
+        for outcome in outcomes.iter() {
+            assert_eq!(outcome[0], RunOutcome::Failed { excluded: vec![3].into_iter().collect() });
+            match outcome[1] {
+                RunOutcome::Success(_) => {},
+                ref outcome => panic!("unexpected outcome {:?}", outcome),
+            }
+        }
+        assert_eq!(executions[0].dump_state().run, 1);
+    }
+
+    #[test]
+    fn execution_own_peer_reveals_keys() {
+        use std::collections::VecDeque;
+
+        let (sks, kepks) = new_keys(8);
+        let (ltsks, ke_sks) = sks.split_at(4);
+        let peers = new_peers(ltsks);
+        let kepks: PeerVec<_> = kepks.iter().skip(4).cloned().collect();
+        let options = OptionsBuilder::new().slot_bytes(4).build().unwrap();
+        let mut executions: Vec<_> = (0..3).map(|i| {
+            let own = own_peer(i, ltsks, ke_sks, &[i as u8 + 1]);
+            Execution::with_own_peer(&peers, options, kepks.iter().map(|kepk| kepk.unwrap()).collect(), own).unwrap()
+        }).collect();
+
+        // Peer 3 is driven by the test. It takes part in the exponential DC-net and goes missing
+        // in the main DC-net, so the other peers reveal the keys they share with it.
+        let mut bus = VecDeque::new();
+        let mut outcomes = vec![vec![]; 3];
+        let mut dispatch = |i: usize, actions: Vec<Action>, bus: &mut VecDeque<_>| {
+            for action in actions {
+                match action {
+                    Action::Send(payload) => bus.push_back((i as PeerIndex, payload)),
+                    Action::Done(outcome) => outcomes[i].push(outcome),
+                    Action::Exclude(peer_index) => assert_eq!(peer_index, 3),
+                }
+            }
+        };
+        for (i, execution) in executions.iter_mut().enumerate() {
+            let actions = execution.start();
+            dispatch(i, actions, &mut bus);
+        }
+        let pay = honest_dc_exponential(3, ke_sks, &kepks, Fp::from_u127(1000));
+        bus.push_back((3, Payload::DcExponential(pay)));
+        loop {
+            while let Some((sender, payload)) = bus.pop_front() {
+                for (i, execution) in executions.iter_mut().enumerate() {
+                    let actions = execution.handle_message(sender, IncomingPayload::Valid(payload.clone()));
+                    dispatch(i, actions, &mut bus);
+                }
+            }
+            if executions[0].dump_state().state != RunState::DcProcess(DcPhase::Main) {
+                break;
+            }
+            for (i, execution) in executions.iter_mut().enumerate() {
+                let actions = execution.handle_timeout(3);
+                dispatch(i, actions, &mut bus);
+            }
+        }
+
+        for (execution, outcome) in executions.iter().zip(outcomes.iter()) {
+            match outcome[..] {
+                [RunOutcome::Success(_)] => {},
+                ref outcome => panic!("unexpected outcomes {:?}", outcome),
+            }
+            assert_eq!(execution.excluded_peers(), vec![3]);
+            assert_eq!(execution.dump_state().run, 0);
+        }
+    }
+
+    #[test]
+    fn execution_restart_with_queued_kepks() {
+        let (sks, kepks) = new_keys(3);
+        let (_, next_kepks) = new_keys(6);
+        let next_kepks: PeerVec<_> = next_kepks.into_iter().skip(3).collect();
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+        execution.process((0, dc_exponential(0, 3)));
+
+        // Peer 2 has not announced a key for the next run.
+        for i in 0..2 {
+            assert_eq!(execution.kepks[i].push(next_kepks[i].unwrap()), Ok(()));
+        }
+        execution.restart(&BitSet::new());
+
+        assert_eq!(execution.rsm.count, 1);
+        assert_eq!(*execution.rsm.kepks, vec![next_kepks[0], next_kepks[1], None]);
+        assert!(execution.rsm.histories[0].as_ref().unwrap().dc_exponential.is_none());
+        assert!(execution.rsm.histories[2].is_none());
+        assert_eq!(execution.kepks[0].front(), next_kepks[0].as_ref());
+        assert_eq!(execution.kepks[2].front(), None);
+    }
+
+    #[test]
+    fn execution_peer_joins_next_run() {
+        let (sks, kepks) = new_keys(9);
+        let peers = new_peers(&sks[..3]);
+        let options = OptionsBuilder::new().build().unwrap();
+        let run_kepks: PeerVec<_> = kepks.iter().take(3).cloned().collect();
+        let mut execution = new_execution(&peers, options, &run_kepks);
+        let colliding = |i: usize| {
+            // All peers choose the same message, so the run fails without excluding anybody.
+            let pay = honest_dc_exponential(i, &sks, &run_kepks, Fp::from_u127(7));
+            IncomingPayload::Valid(Payload::DcExponential(pay))
+        };
+        assert_eq!(execution.process((0, colliding(0))), Async::NotReady);
+
+        let ltvk = |i: usize| PublicKey::from_secret_key(&::SECP256K1, &sks[i]).unwrap();
+        let newcomer = Peer::new(PeerId::from_bytes(&[3; 64]), ltvk(3));
+        assert_eq!(execution.add_peer_for_next_run(newcomer.clone(), kepks[3].unwrap()), Ok(()));
+        assert_eq!(execution.add_peer_for_next_run(newcomer.clone(), kepks[4].unwrap()), Err(JoinError::DuplicatePeer));
+        let impostor = Peer::new(PeerId::from_bytes(&[4; 64]), ltvk(0));
+        assert_eq!(execution.add_peer_for_next_run(impostor, kepks[4].unwrap()), Err(JoinError::DuplicatePeer));
+        // A peer that drops out before the next run starts never joins.
+        let dropout = Peer::new(PeerId::from_bytes(&[5; 64]), ltvk(5));
+        assert_eq!(execution.add_peer_for_next_run(dropout.clone(), kepks[5].unwrap()), Ok(()));
+        assert!(execution.withdraw_peer_for_next_run(dropout.peer_id()));
+        assert!(!execution.withdraw_peer_for_next_run(dropout.peer_id()));
+
+        // The current run is unaffected.
+        for i in 1..3 {
+            assert_eq!(execution.process((i as PeerIndex, colliding(i))), Async::NotReady);
+        }
+        assert_eq!(execution.live_peers(), vec![0, 1, 2]);
+        assert_eq!(execution.peer(3), None);
+
+        // After the collision, the peers announce fresh keys in the blame phase, and nobody is
+        // found malicious.
+        assert_eq!(execution.dump_state().state, RunState::Blame);
+        for i in 0..3 {
+            let announce = (i as PeerIndex, key_exchange(kepks[6 + i].unwrap()));
+            assert_eq!(execution.process(announce), Async::NotReady);
+            let blame = Payload::Blame(Blame { ke_sk: Secret::new(sks[i]) });
+            let outcome = execution.process((i as PeerIndex, IncomingPayload::Valid(blame)));
+            if i < 2 {
+                assert_eq!(outcome, Async::NotReady);
+            } else {
+                assert_eq!(outcome, Async::Ready(RunOutcome::Failed { excluded: BitSet::new() }));
+            }
+        }
+        assert_eq!(execution.dump_state().run, 1);
+        assert_eq!(execution.excluded_peers(), Vec::<PeerIndex>::new());
+        assert_eq!(execution.live_peers(), vec![0, 1, 2, 3]);
+        assert_eq!(execution.peer(3), Some(&newcomer));
+        assert_eq!(execution.peer(4), None);
+        assert_eq!(execution.add_peer_for_next_run(newcomer, kepks[4].unwrap()), Err(JoinError::DuplicatePeer));
+
+        // The next run uses the announced keys and the key of the newcomer, so the pads of an
+        // honest DC-net with these keys cancel.
+        let next_sks = vec![sks[6], sks[7], sks[8], sks[3]];
+        let next_kepks: PeerVec<_> = vec![kepks[6], kepks[7], kepks[8], kepks[3]].into();
+        for i in 0..4 {
+            let pay = honest_dc_exponential_in_run(1, i, &next_sks, &next_kepks, Fp::from_u127(20 + i as u128));
+            let incoming = (i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay)));
+            assert_eq!(execution.process(incoming), Async::NotReady);
+        }
+        assert_eq!(execution.dump_state().state, RunState::DcProcess(DcPhase::Main));
+        assert_eq!(execution.live_peers(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn execution_setup() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let kepks: Vec<_> = kepks.into_iter().map(Option::unwrap).collect();
+
+        assert!(Execution::new(&peers, options, kepks.clone()).is_ok());
+        match Execution::new(&peers, options, kepks[..2].to_vec()) {
+            Err(err) => assert_eq!(err, SetupError::WrongNumberOfKepks),
+            Ok(_) => panic!("missing key accepted"),
+        }
+        let mut invalid_kepks = kepks.clone();
+        invalid_kepks[1] = PublicKey::new();
+        match Execution::new(&peers, options, invalid_kepks) {
+            Err(err) => assert_eq!(err, SetupError::InvalidKepk(1)),
+            Ok(_) => panic!("invalid key accepted"),
+        }
+    }
+
+    #[test]
+    fn execution_live_peers() {
+        let (sks, kepks) = new_keys(4);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+        assert_eq!(execution.live_peers(), vec![0, 1, 2, 3]);
+        assert!(execution.excluded_peers().is_empty());
+
+        execution.process((2, IncomingPayload::Invalid(RejectReason::BadSignature)));
+        assert_eq!(execution.live_peers(), vec![0, 1, 3]);
+        assert_eq!(execution.excluded_peers(), vec![2]);
+    }
+
+    #[test]
+    fn execution_timeout() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+        for i in 0..3 {
+            assert_eq!(execution.process((i, dc_exponential(i, 3))), Async::NotReady);
+        }
+
+        assert_eq!(execution.process((0, dc_main(3, kepks[0].unwrap()))), Async::NotReady);
+        // A timeout after the message has arrived is ignored.
+        assert_eq!(execution.on_timeout(0), Async::NotReady);
+        assert_eq!(execution.process((1, dc_main(3, kepks[1].unwrap()))), Async::NotReady);
+        assert_eq!(execution.on_timeout(2), Async::NotReady);
+        assert_eq!(execution.excluded_peers(), vec![2]);
+
+        // The remaining peers recover from the missing message and confirm without peer 2.
+        assert_eq!(execution.process((0, reveal(vec![(2, SymmetricKey([0x02; 32]))]))), Async::NotReady);
+        assert_eq!(execution.process((1, reveal(vec![(2, SymmetricKey([0x12; 32]))]))), Async::NotReady);
+        assert_eq!(execution.process((0, confirm(&sks[0]))), Async::NotReady);
+        match execution.process((1, confirm(&sks[1]))) {
+            Async::Ready(RunOutcome::Success(confirmations)) => {
+                assert_eq!(confirmations.live_peers().map(|(i, _)| i).collect::<Vec<_>>(), vec![0, 1]);
+            },
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // Too few peers remain after a timeout.
+        let options = OptionsBuilder::new().min_peers(3).build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+        let remaining: BitSet = vec![0, 1].into_iter().collect();
+        let aborted = Async::Ready(RunOutcome::Aborted { remaining });
+        assert_eq!(execution.on_timeout(2), aborted);
+        assert_eq!(execution.on_timeout(1), aborted);
+    }
+
+    #[test]
+    fn execution_dump_state() {
+        let (sks, kepks) = new_keys(3);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+
+        let snapshot = execution.dump_state();
+        assert_eq!(snapshot.run, 0);
+        assert_eq!(snapshot.state, RunState::DcProcess(DcPhase::Exponential));
+        assert!(snapshot.received.is_empty());
+        assert_eq!(snapshot.peers_before_dc_exponential, Some(vec![0, 1, 2]));
+        assert_eq!(snapshot.peers_before_dc_main, None);
+
+        execution.process((0, dc_exponential(0, 3)));
+        execution.process((2, dc_exponential(2, 3)));
+        let snapshot = execution.dump_state();
+        assert_eq!(snapshot.state, RunState::DcProcess(DcPhase::Exponential));
+        assert_eq!(snapshot.received, vec![0, 2]);
+        assert_eq!(snapshot.live_peers, vec![0, 1, 2]);
+        assert!(!snapshot.aborted);
+
+        // The last message completes the exponential DC-net.
+        execution.process((1, dc_exponential(1, 3)));
+        let snapshot = execution.dump_state();
+        assert_eq!(snapshot.state, RunState::DcProcess(DcPhase::Main));
+        assert!(snapshot.received.is_empty());
+        assert_eq!(snapshot.peers_before_dc_main, Some(vec![0, 1, 2]));
+
+        // The snapshot is serializable.
+        assert!(!::messages::encode(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn round_schedule() {
+        use dc::scalar::Scalar;
+
+        let num_peers = 4;
+        let slot_bytes = 64;
+        let schedule = RoundSchedule::new(slot_bytes);
+        let limit = |state| schedule.frame_limit(state, num_peers);
+
+        // The main DC-net carries the largest payloads, and its limit grows with the slot size.
+        assert!(limit(RunState::DcProcess(DcPhase::Exponential)) < limit(RunState::DcProcess(DcPhase::Main)));
+        assert!(limit(RunState::DcReveal(DcPhase::Main)) < limit(RunState::DcProcess(DcPhase::Main)));
+        assert!(limit(RunState::DcProcess(DcPhase::Main))
+            < RoundSchedule::new(2 * slot_bytes).frame_limit(RunState::DcProcess(DcPhase::Main), num_peers));
+
+        let (sks, kepks) = new_keys(num_peers);
+        let kepk = kepks[0].unwrap();
+        let payloads = vec![
+            (RunState::DcProcess(DcPhase::Exponential),
+             Payload::DcExponential(DcExponential::new(vec![Fp::from_u127(1); num_peers]))),
+            (RunState::DcReveal(DcPhase::Exponential),
+             Payload::Reveal(Reveal { keys: vec![(1, SymmetricKey([0x01; 32])); num_peers] })),
+            (RunState::DcProcess(DcPhase::Main),
+             Payload::DcMain(DcMain {
+                 ok: true,
+                 dc_xor: XorVec::from(vec![XorVec::from(vec![0u8; slot_bytes]); num_peers]),
+                 ke_pk: kepk,
+                 extension: Extension::DcAddSecp256k1Scalar(vec![Scalar::default(); num_peers]),
+                 early_confirm: Some(vec![0; MAX_SLOT_SIZE]),
+             })),
+            (RunState::Blame, Payload::Blame(Blame { ke_sk: Secret::new(sks[0]) })),
+            (RunState::Confirm, Payload::Confirm(Confirm { data: vec![0; MAX_SLOT_SIZE] })),
+        ];
+        for (state, payload) in payloads {
+            let message = Message { header: Header::new(SessionId([0; 32]), 0, 0), payload };
+            let len = ::messages::encode(&message).len()
+                + secp256k1::constants::COMPACT_SIGNATURE_SIZE;
+            assert!(len <= limit(state), "{} > {} in {:?}", len, limit(state), state);
+        }
+    }
+
+    fn key_exchange(kepk: PublicKey) -> IncomingPayload {
+        IncomingPayload::Valid(Payload::KeyExchange(KeyExchange { ke_pk: kepk }))
+    }
+
+    #[test]
+    fn key_exchange_window() {
+        let (_, next_kepks) = new_keys(8);
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(4));
+
+        // Too early
+        rsm.apply_incoming_message((0, key_exchange(next_kepks[4].unwrap())));
+        assert!(rsm.excluded.contains(0));
+
+        rsm.set_state(RunState::DcProcess(DcPhase::Main));
+        rsm.set_state(RunState::Confirm);
+        rsm.apply_incoming_message((1, key_exchange(next_kepks[5].unwrap())));
+        rsm.apply_incoming_message((2, key_exchange(PublicKey::new())));
+        rsm.apply_incoming_message((3, key_exchange(next_kepks[6].unwrap())));
+        rsm.apply_incoming_message((3, key_exchange(next_kepks[7].unwrap())));
+
+        assert_eq!(rsm.next_kepks[1], next_kepks[5]);
+        assert_eq!(rsm.excluded.iter().collect::<Vec<_>>(), vec![0, 2, 3]);
+        // The key exchange is not the message of peer 1 in this round.
+        assert!(!rsm.received.contains(1));
+    }
+
+    #[test]
+    fn key_exchange_restart() {
+        let (sks, kepks) = new_keys(3);
+        let (_, next_kepks) = new_keys(6);
+        let peers = new_peers(&sks);
+        let options = OptionsBuilder::new().build().unwrap();
+        let mut execution = new_execution(&peers, options, &kepks);
+
+        execution.rsm.set_state(RunState::Blame);
+        for i in 0..3 {
+            assert_eq!(execution.process((i, key_exchange(next_kepks[3 + i as usize].unwrap()))), Async::NotReady);
+        }
+        assert_eq!(execution.kepks[0].front(), kepks[0].as_ref());
+
+        execution.restart(&vec![1].into_iter().collect());
+        assert_eq!(*execution.rsm.kepks, vec![next_kepks[3], None, next_kepks[5]]);
+        assert_eq!(execution.kepks[0].front(), next_kepks[3].as_ref());
+    }
+
+    // Pads seeded by the pair of peers, so that tests do not depend on the key exchange
+    struct PairPads(PeerIndex);
+
+    impl blame::PadSource for PairPads {
+        fn pads_for(&self, peer: PeerIndex, phase: DcPhase) -> ::rng::DiceMixRng {
+            let mut seed = [0u8; 32];
+            seed[0] = cmp::min(self.0, peer) as u8;
+            seed[1] = cmp::max(self.0, peer) as u8;
+            let mut rng = ::rng::DiceMixRng::new(&seed);
+            rng.prepare_round(blame::dc_round(0, phase));
+            rng
+        }
+    }
+
+    #[test]
+    fn dc_exponential_pads_cancel() {
+        let msgs = [11, 22, 33];
+        let mut rsm = RunStateMachine::new(0, Variant::PlainEcdsa, new_kepks(3));
+        let peers = live_peers(&rsm.kepks);
+        for i in 0..3 {
+            let msg = Fp::from_u127(msgs[i]);
+            let pay = blame::padded_dc_exponential(i as PeerIndex, &peers, &PairPads(i as PeerIndex), msg, 3);
+            assert!(pay.dc_exp[0] != msg);
+            assert!(blame::dc_exponential_message(i as PeerIndex, &peers, &PairPads(i as PeerIndex), &pay).is_some());
+            rsm.apply_incoming_message((i as PeerIndex, IncomingPayload::Valid(Payload::DcExponential(pay))));
+        }
+
+        assert_eq!(rsm.state, RunState::DcProcess(DcPhase::Main));
+        let mut messages = rsm.solve_dc_exponential().unwrap();
+        messages.sort();
+        assert_eq!(messages, msgs.iter().map(|&msg| Fp::from_u127(msg)).collect::<Vec<_>>());
+
+        // With zero messages, the payloads consist of the pads alone, which cancel out.
+        let pads: Vec<_> = (0..3).map(|i| {
+            let pay = blame::padded_dc_exponential(i, &peers, &PairPads(i), Fp::from_u64(0), 1);
+            pay.dc_exp[0]
+        }).collect();
+        assert!(::dc::dc_pads_cancel(&pads));
+        assert!(!::dc::dc_pads_cancel(&pads[..2]));
+    }
+}
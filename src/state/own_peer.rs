@@ -0,0 +1,229 @@
+//! The payloads of our own peer, which an execution computes and applies itself
+
+use std::collections::VecDeque;
+
+use rand::{Rng, FromEntropy};
+use rand::rngs::StdRng;
+use secp256k1::key::{PublicKey, SecretKey};
+use bit_set::BitSet;
+
+use messages::*;
+use dc::Randomize;
+use dc::fp::Fp;
+use dc::scalar::Scalar;
+use dc::xor::XorVec;
+use {PeerIndex, SymmetricKey, ExtensionKind};
+
+use super::{RunStateMachine, RunState, DcPhase, PeerVec, live_index, peer_index_to_usize};
+use super::blame::{self, SharedKeyPads};
+use super::exponential_message;
+
+/// Chooses a uniformly random nonzero message in the exponential DC-net.
+pub fn random_exponential_message<R: Rng>(rng: &mut R) -> Fp {
+    loop {
+        let msg: Fp = rng.gen();
+        if msg != Fp::from_u127(0) {
+            return msg;
+        }
+    }
+}
+
+/// Our own peer in an execution, see `Execution::with_own_peer()`
+///
+/// It holds the secrets of our peer: the long-term secret key, the ephemeral secret keys of
+/// the current and the next run, and the nonce of the multisignature variants.
+pub struct OwnPeer {
+    peer_index: PeerIndex,
+    ltsk: Secret<SecretKey>,
+    /// The ephemeral secret keys, starting with the key of the current run
+    ke_sks: VecDeque<Secret<SecretKey>>,
+    message: Vec<u8>,
+    /// Returns the sighashes that our peer signs in the ECDSA variants, given the messages of
+    /// the main DC-net
+    sighashes: Box<dyn FnMut(&[XorVec<u8>]) -> Vec<[u8; 32]>>,
+    rng: StdRng,
+    /// Our message in the exponential DC-net of the current run, which determines our slot
+    exponential_message: Option<Fp>,
+    /// The secret key of our nonce commitment in the multisignature variants
+    nonce_sk: Option<Secret<SecretKey>>,
+}
+
+impl OwnPeer {
+    /// Creates our own peer with index `peer_index`, which mixes `message`.
+    ///
+    /// `ke_sk` is the ephemeral secret key of the first run. The keys of later runs are
+    /// generated and announced by the execution. In the ECDSA variants, the confirmation data
+    /// consists of the signatures of the sighashes that `sighashes` returns for the messages of
+    /// the main DC-net, which must not be empty.
+    pub fn new<F>(peer_index: PeerIndex, ltsk: SecretKey, ke_sk: SecretKey, message: Vec<u8>, sighashes: F) -> Self
+        where F: FnMut(&[XorVec<u8>]) -> Vec<[u8; 32]> + 'static
+    {
+        let mut ke_sks = VecDeque::new();
+        ke_sks.push_back(Secret::new(ke_sk));
+        OwnPeer {
+            peer_index,
+            ltsk: Secret::new(ltsk),
+            ke_sks,
+            message,
+            sighashes: Box::new(sighashes),
+            rng: StdRng::from_entropy(),
+            exponential_message: None,
+            nonce_sk: None,
+        }
+    }
+
+    /// Replaces the source of randomness, so that tests are deterministic.
+    #[cfg(test)]
+    pub fn with_rng(mut self, rng: StdRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[inline]
+    pub fn peer_index(&self) -> PeerIndex {
+        self.peer_index
+    }
+
+    #[inline]
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Returns the long-term verification key and the ephemeral public key of the first run.
+    pub fn public_keys(&self) -> (PublicKey, PublicKey) {
+        let pk = |sk: &SecretKey| PublicKey::from_secret_key(&::SECP256K1, sk).expect("the context can sign");
+        (pk(&self.ltsk), pk(&self.ke_sks[0]))
+    }
+
+    /// Moves on to the next run, whose ephemeral key we have announced in the current run.
+    pub fn restart(&mut self) {
+        if self.ke_sks.len() > 1 {
+            self.ke_sks.pop_front();
+        }
+        self.exponential_message = None;
+        self.nonce_sk = None;
+    }
+
+    #[inline]
+    fn ke_sk(&self) -> &SecretKey {
+        &self.ke_sks[0]
+    }
+
+    fn new_secret_key(&mut self) -> SecretKey {
+        loop {
+            let bytes: [u8; 32] = self.rng.gen();
+            if let Ok(sk) = SecretKey::from_slice(&::SECP256K1, &bytes) {
+                return sk;
+            }
+        }
+    }
+
+    /// Returns the announcement of our ephemeral key for the next run, if the run is in the key
+    /// exchange window and we have not announced a key yet.
+    pub(super) fn key_exchange(&mut self, rsm: &RunStateMachine) -> Option<Payload> {
+        let in_window = rsm.state == RunState::Confirm || rsm.state == RunState::Blame;
+        if !in_window || rsm.next_kepks[live_index(self.peer_index)].is_some() {
+            return None;
+        }
+        let ke_sk = self.new_secret_key();
+        let ke_pk = PublicKey::from_secret_key(&::SECP256K1, &ke_sk).expect("the context can sign");
+        self.ke_sks.push_back(Secret::new(ke_sk));
+        Some(Payload::KeyExchange(KeyExchange { ke_pk }))
+    }
+
+    /// Returns our payload in the current state of the run.
+    pub(super) fn payload(&mut self, rsm: &RunStateMachine) -> Option<Payload> {
+        match rsm.state {
+            RunState::DcProcess(DcPhase::Exponential) => Some(self.dc_exponential(rsm)),
+            RunState::DcProcess(DcPhase::Main) => self.dc_main(rsm),
+            RunState::DcReveal(phase) => self.reveal(rsm, phase),
+            RunState::Blame => Some(Payload::Blame(Blame { ke_sk: Secret::new(*self.ke_sk()) })),
+            RunState::Confirm => self.confirm(rsm),
+        }
+    }
+
+    /// Derives the keys shared with the other peers in `peers` from their ephemeral keys at the
+    /// beginning of the run, like the replay in the blame phase.
+    fn shared_keys(&self, rsm: &RunStateMachine, peers: &BitSet) -> PeerVec<SymmetricKey> {
+        let me = peer_index_to_usize(self.peer_index);
+        rsm.kepks_before_dc_exponential.iter().enumerate().map(|(index, kepk)| match *kepk {
+            Some(ref kepk) if Some(index) != me && peers.contains(index) => {
+                Some(blame::shared_key(self.ke_sk(), kepk))
+            },
+            _ => None,
+        }).collect()
+    }
+
+    fn dc_exponential(&mut self, rsm: &RunStateMachine) -> Payload {
+        let peers = rsm.peers_before(DcPhase::Exponential).clone();
+        let msg = exponential_message(&mut self.rng);
+        self.exponential_message = Some(msg);
+        let keys = self.shared_keys(rsm, &peers);
+        let pads = SharedKeyPads::new(&keys, rsm.count);
+        Payload::DcExponential(blame::padded_dc_exponential(self.peer_index, &peers, &pads, msg, peers.len()))
+    }
+
+    fn dc_main(&mut self, rsm: &RunStateMachine) -> Option<Payload> {
+        let peers = rsm.peers_before_dc_main.clone()?;
+        // Our slot is at the rank of our message. If our message is missing, some peer has
+        // disrupted the exponential DC-net, and we send no message.
+        let slot = match (&rsm.exponential_messages, self.exponential_message) {
+            (&Some(ref messages), Some(message)) => messages.binary_search(&message).ok(),
+            _ => None,
+        };
+        let mut slots = vec![XorVec::from(vec![0u8; rsm.slot_bytes]); peers.len()];
+        if let Some(slot) = slot {
+            let mut message = self.message.clone();
+            message.resize(rsm.slot_bytes, 0);
+            slots[slot] = XorVec::from(message);
+        }
+        let mut dc_xor = XorVec::from(slots);
+        for (_, key) in self.shared_keys(rsm, &peers).live_peers() {
+            let mut pad = dc_xor.clone();
+            pad.randomize(&mut blame::pad_rng(key, rsm.count, DcPhase::Main));
+            dc_xor -= pad;
+        }
+
+        // The state machine does not combine the scalars of the extension, so we contribute
+        // zeros.
+        let extension = match rsm.extension {
+            ExtensionKind::None => Extension::None,
+            ExtensionKind::DcAddSecp256k1Scalar => Extension::DcAddSecp256k1Scalar(vec![Scalar::default(); peers.len()]),
+        };
+        let early_confirm = if rsm.variant.has_early_confirm() {
+            let nonce_sk = self.new_secret_key();
+            let nonce = PublicKey::from_secret_key(&::SECP256K1, &nonce_sk).expect("the context can sign");
+            self.nonce_sk = Some(Secret::new(nonce_sk));
+            Some(nonce.serialize_vec(&::SECP256K1, true).to_vec())
+        } else {
+            None
+        };
+        Some(Payload::DcMain(DcMain {
+            ok: slot.is_some(),
+            dc_xor,
+            ke_pk: rsm.kepks[live_index(self.peer_index)]?,
+            extension,
+            early_confirm,
+        }))
+    }
+
+    fn reveal(&self, rsm: &RunStateMachine, phase: DcPhase) -> Option<Payload> {
+        let missing: BitSet = rsm.peers_before(phase).difference(rsm.peers_before_dc_reveal.as_ref()?).collect();
+        let keys = self.shared_keys(rsm, &missing).into_iter().enumerate()
+            .filter_map(|(index, key)| Some((super::to_peer_index(index), key?)))
+            .collect();
+        Some(Payload::Reveal(Reveal { keys }))
+    }
+
+    fn confirm(&mut self, rsm: &RunStateMachine) -> Option<Payload> {
+        let messages = rsm.messages.as_ref()?;
+        let data = match rsm.signing {
+            Some(ref session) => {
+                let nonce_sk = self.nonce_sk.as_ref()?;
+                session.sign(self.peer_index, &self.ltsk, nonce_sk)?.to_bytes().to_vec()
+            },
+            None => Confirm::sign(&(self.sighashes)(messages), &self.ltsk).data,
+        };
+        Some(Payload::Confirm(Confirm { data }))
+    }
+}
@@ -0,0 +1,96 @@
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use std::vec;
+
+use ::PeerIndex;
+use super::to_peer_index;
+
+/// A vector with one optional entry per peer, indexed by `PeerIndex`
+///
+/// A peer is live if its entry is present. The vector dereferences to `Vec<Option<T>>`, so
+/// entries can also be accessed by their `usize` index.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerVec<T>(Vec<Option<T>>);
+
+impl<T> PeerVec<T> {
+    /// Creates a vector in which no peer is live.
+    pub fn empty(num_peers: usize) -> Self {
+        (0..num_peers).map(|_| None).collect()
+    }
+
+    /// Iterates over the present entries in the order of their peer indices.
+    pub fn live_peers(&self) -> impl Iterator<Item = (PeerIndex, &T)> {
+        self.0.iter().enumerate().filter_map(|(i, x)| x.as_ref().map(|x| (to_peer_index(i), x)))
+    }
+
+    /// Iterates mutably over the present entries in the order of their peer indices.
+    pub fn live_peers_mut(&mut self) -> impl Iterator<Item = (PeerIndex, &mut T)> {
+        self.0.iter_mut().enumerate().filter_map(|(i, x)| x.as_mut().map(|x| (to_peer_index(i), x)))
+    }
+}
+
+impl<T> From<Vec<Option<T>>> for PeerVec<T> {
+    fn from(vec: Vec<Option<T>>) -> Self {
+        PeerVec(vec)
+    }
+}
+
+impl<T> FromIterator<Option<T>> for PeerVec<T> {
+    fn from_iter<I: IntoIterator<Item = Option<T>>>(iter: I) -> Self {
+        PeerVec(iter.into_iter().collect())
+    }
+}
+
+impl<T> IntoIterator for PeerVec<T> {
+    type Item = Option<T>;
+    type IntoIter = vec::IntoIter<Option<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PeerVec<T> {
+    type Item = &'a Option<T>;
+    type IntoIter = slice::Iter<'a, Option<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<T> Deref for PeerVec<T> {
+    type Target = Vec<Option<T>>;
+
+    fn deref(&self) -> &Vec<Option<T>> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for PeerVec<T> {
+    fn deref_mut(&mut self) -> &mut Vec<Option<T>> {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_peers() {
+        let mut peervec = PeerVec::from(vec![None, Some('a'), Some('b'), None, None, Some('c')]);
+        let live: Vec<_> = peervec.live_peers().collect();
+        assert_eq!(live, vec![(1, &'a'), (2, &'b'), (5, &'c')]);
+
+        for (_, x) in peervec.live_peers_mut() {
+            *x = x.to_ascii_uppercase();
+        }
+        assert_eq!(peervec, PeerVec::from(vec![None, Some('A'), Some('B'), None, None, Some('C')]));
+
+        let empty: PeerVec<char> = PeerVec::empty(3);
+        assert_eq!(empty.len(), 3);
+        assert_eq!(empty.live_peers().count(), 0);
+    }
+}
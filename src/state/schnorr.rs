@@ -0,0 +1,279 @@
+//! Aggregation of Schnorr multisignatures in the confirmation phase
+//!
+//! In the multisignature variants, every peer commits to its signing nonce in the early
+//! confirmation data of the main DC-net and sends its partial signature as confirmation data.
+//! The signers are the peers that reach the confirmation phase, and they sign the messages of
+//! the main DC-net with their long-term keys, which are aggregated as in MuSig to prevent
+//! rogue-key attacks: peer `i` with key `X_i` and nonce `R_i` sends `s_i = r_i + e*a_i*x_i`,
+//! where `a_i = H(L, X_i)` for the list `L` of all signer keys, `X` is the sum of `a_i*X_i`,
+//! `R` is the sum of `R_i`, and `e = H(R, X, m)`. The partial signatures add up to `s` with
+//! `s*G = R + e*X`.
+
+use std::os::raw::c_int;
+
+use blake2::{Blake2s, Digest};
+use secp256k1::constants::COMPRESSED_PUBLIC_KEY_SIZE;
+use secp256k1::ffi;
+use secp256k1::key::{PublicKey, SecretKey};
+
+use dc::scalar::Scalar;
+use dc::xor::XorVec;
+
+use super::{PeerVec, live_index};
+use PeerIndex;
+
+const KEY_LIST_PREFIX: &[u8; 32] = b"DICEMIX_SCHNORR_MULTI_KEY_LIST__";
+const COEFFICIENT_PREFIX: &[u8; 32] = b"DICEMIX_SCHNORR_MULTI_COEFF_____";
+const CHALLENGE_PREFIX: &[u8; 32] = b"DICEMIX_SCHNORR_MULTI_CHALLENGE_";
+const MESSAGE_PREFIX: &[u8; 32] = b"DICEMIX_SCHNORR_MULTI_MESSAGE___";
+
+/// An aggregated Schnorr multisignature
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiSignature {
+    /// The sum of the nonce commitments of the signers
+    pub nonce: PublicKey,
+    /// The aggregated key of the signers
+    pub key: PublicKey,
+    pub s: Scalar,
+}
+
+impl MultiSignature {
+    /// Checks the signature on the digest of the messages, see `message_digest()`.
+    pub fn verify(&self, message: &[u8; 32]) -> bool {
+        let mut challenged = self.key;
+        if challenged.mul_assign(&::SECP256K1, &challenge(&self.nonce, &self.key, message)).is_err() {
+            return false;
+        }
+        let expected = combine(&[self.nonce, challenged]);
+        expected.is_some() && scalar_times_generator(&self.s) == expected
+    }
+}
+
+/// The public data of a signing session, which the partial signatures are checked against
+#[derive(Clone, Debug)]
+pub struct SigningSession {
+    nonce: PublicKey,
+    key: PublicKey,
+    /// The nonce commitment and the key of each signer, the latter multiplied by `e*a_i`
+    signers: PeerVec<(PublicKey, PublicKey)>,
+    /// The factor `e*a_i` of each signer
+    factors: PeerVec<SecretKey>,
+}
+
+impl SigningSession {
+    /// Sets up the session for the signers with nonce commitments in `nonces`, whose long-term
+    /// keys are taken from `ltvks`.
+    ///
+    /// Returns `None` if the key of a signer is missing or there are no signers. This happens
+    /// also if a sum is the point at infinity, which honest signers hit with negligible
+    /// probability.
+    pub fn new(nonces: &PeerVec<PublicKey>, ltvks: &PeerVec<PublicKey>, message: &[u8; 32]) -> Option<Self> {
+        let signer_keys: Vec<(PeerIndex, PublicKey, PublicKey)> = nonces.live_peers().map(|(index, nonce)| {
+            let ltvk = ltvks.get(live_index(index)).and_then(Option::as_ref)?;
+            Some((index, *nonce, *ltvk))
+        }).collect::<Option<_>>()?;
+
+        let mut hasher = Blake2s::default();
+        hasher.input(KEY_LIST_PREFIX);
+        hasher.input(&(signer_keys.len() as u64).to_le_bytes());
+        for &(_, _, ltvk) in signer_keys.iter() {
+            hasher.input(&ltvk.serialize_vec(&::SECP256K1, true));
+        }
+        let key_list = hasher.result();
+
+        let mut weighted_keys = Vec::with_capacity(signer_keys.len());
+        let mut coefficients = Vec::with_capacity(signer_keys.len());
+        for &(_, _, ltvk) in signer_keys.iter() {
+            let coefficient = hash_to_secret_key(COEFFICIENT_PREFIX, &[&key_list, &ltvk.serialize_vec(&::SECP256K1, true)]);
+            let mut weighted = ltvk;
+            weighted.mul_assign(&::SECP256K1, &coefficient).ok()?;
+            weighted_keys.push(weighted);
+            coefficients.push(coefficient);
+        }
+        let signer_nonces: Vec<_> = signer_keys.iter().map(|&(_, nonce, _)| nonce).collect();
+        let nonce = combine(&signer_nonces)?;
+        let key = combine(&weighted_keys)?;
+        let e = challenge(&nonce, &key, message);
+
+        let mut signers = PeerVec::empty(nonces.len());
+        let mut factors = PeerVec::empty(nonces.len());
+        for (&(index, signer_nonce, ltvk), mut factor) in signer_keys.iter().zip(coefficients) {
+            factor.mul_assign(&::SECP256K1, &e).ok()?;
+            let mut challenged = ltvk;
+            challenged.mul_assign(&::SECP256K1, &factor).ok()?;
+            signers[live_index(index)] = Some((signer_nonce, challenged));
+            factors[live_index(index)] = Some(factor);
+        }
+        Some(SigningSession { nonce, key, signers, factors })
+    }
+
+    /// Computes the partial signature of a signer from its long-term secret key and the secret
+    /// key of its nonce commitment.
+    pub fn sign(&self, peer_index: PeerIndex, ltsk: &SecretKey, nonce_sk: &SecretKey) -> Option<Scalar> {
+        let mut s = *self.factors.get(live_index(peer_index)).and_then(Option::as_ref)?;
+        s.mul_assign(&::SECP256K1, ltsk).ok()?;
+        s.add_assign(&::SECP256K1, nonce_sk).ok()?;
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&s[..]);
+        Scalar::from_bytes(&bytes)
+    }
+
+    /// Checks the partial signature `s_i` of a signer, i.e., `s_i*G = R_i + e*a_i*X_i`.
+    pub fn verify_partial(&self, peer_index: PeerIndex, partial: &Scalar) -> bool {
+        match self.signers.get(live_index(peer_index)) {
+            Some(&Some((nonce, challenged))) => {
+                let expected = combine(&[nonce, challenged]);
+                expected.is_some() && scalar_times_generator(partial) == expected
+            },
+            _ => false,
+        }
+    }
+
+    /// Aggregates the partial signatures of all signers, which must have been checked with
+    /// `verify_partial()`.
+    pub fn aggregate(&self, partials: &[Scalar]) -> MultiSignature {
+        let mut s = Scalar::default();
+        for &partial in partials {
+            s += partial;
+        }
+        MultiSignature { nonce: self.nonce, key: self.key, s }
+    }
+}
+
+/// Parses the nonce commitment sent as early confirmation data, a compressed public key.
+pub fn nonce_commitment(data: &[u8]) -> Option<PublicKey> {
+    if data.len() != COMPRESSED_PUBLIC_KEY_SIZE {
+        return None;
+    }
+    PublicKey::from_slice(&::SECP256K1, data).ok()
+}
+
+/// Parses the partial signature sent as confirmation data, a 32-byte big-endian scalar.
+pub fn partial_signature(data: &[u8]) -> Option<Scalar> {
+    if data.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(data);
+    Scalar::from_bytes(&bytes)
+}
+
+/// Computes the digest of the messages of the main DC-net that the signers sign.
+///
+/// The digest is the BLAKE2s hash of a 32-byte prefix, followed by the number of messages and
+/// each message prefixed with its length, all lengths as 64-bit little-endian integers.
+pub fn message_digest(messages: &[XorVec<u8>]) -> [u8; 32] {
+    let mut hasher = Blake2s::default();
+    hasher.input(MESSAGE_PREFIX);
+    hasher.input(&(messages.len() as u64).to_le_bytes());
+    for message in messages {
+        hasher.input(&(message.len() as u64).to_le_bytes());
+        hasher.input(message);
+    }
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.result());
+    digest
+}
+
+fn challenge(nonce: &PublicKey, key: &PublicKey, message: &[u8; 32]) -> SecretKey {
+    let nonce = nonce.serialize_vec(&::SECP256K1, true);
+    let key = key.serialize_vec(&::SECP256K1, true);
+    hash_to_secret_key(CHALLENGE_PREFIX, &[&nonce, &key, message])
+}
+
+/// Hashes the prefix and the parts with BLAKE2s, along with a counter that is incremented
+/// until the hash is a valid secret key, i.e., a nonzero scalar.
+fn hash_to_secret_key(prefix: &[u8; 32], parts: &[&[u8]]) -> SecretKey {
+    let mut counter = 0u32;
+    loop {
+        let mut hasher = Blake2s::default();
+        hasher.input(prefix);
+        for part in parts {
+            hasher.input(part);
+        }
+        hasher.input(&counter.to_le_bytes());
+        if let Ok(sk) = SecretKey::from_slice(&::SECP256K1, &hasher.result()) {
+            return sk;
+        }
+        counter += 1;
+    }
+}
+
+/// Returns `s*G`, or `None` if `s` is zero.
+fn scalar_times_generator(s: &Scalar) -> Option<PublicKey> {
+    let sk = SecretKey::from_slice(&::SECP256K1, &s.to_bytes()).ok()?;
+    PublicKey::from_secret_key(&::SECP256K1, &sk).ok()
+}
+
+/// Adds points, returning `None` if there are none or the sum is the point at infinity.
+fn combine(points: &[PublicKey]) -> Option<PublicKey> {
+    if points.is_empty() {
+        return None;
+    }
+    let ptrs: Vec<*const ffi::PublicKey> = points.iter().map(PublicKey::as_ptr).collect();
+    let mut sum = ffi::PublicKey::new();
+    // Our bindings do not expose point addition. The context of `::SECP256K1` is private, but
+    // addition does not need any precomputed tables, so a bare context suffices. The arguments
+    // are valid keys and there is at least one, so no argument check calls the abort handler.
+    let res = unsafe {
+        let ctx = ffi::secp256k1_context_create(ffi::SECP256K1_START_NONE);
+        let res = ffi::secp256k1_ec_pubkey_combine(ctx, &mut sum, ptrs.as_ptr(), ptrs.len() as c_int);
+        ffi::secp256k1_context_destroy(ctx);
+        res
+    };
+    if res == 1 { Some(PublicKey::from(sum)) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(seeds: &[u8]) -> (Vec<SecretKey>, PeerVec<PublicKey>) {
+        let sks: Vec<_> = seeds.iter().map(|&b| SecretKey::from_slice(&::SECP256K1, &[b; 32]).unwrap()).collect();
+        let pks = sks.iter().map(|sk| Some(PublicKey::from_secret_key(&::SECP256K1, sk).unwrap())).collect();
+        (sks, pks)
+    }
+
+    #[test]
+    fn partial_signatures_aggregate() {
+        let (ltsks, ltvks) = keys(&[1, 2, 3]);
+        let (nonce_sks, mut nonces) = keys(&[4, 5, 6]);
+        // Peer 1 does not sign.
+        nonces[1] = None;
+        let message = message_digest(&[XorVec::from(vec![1, 2, 3])]);
+        let session = SigningSession::new(&nonces, &ltvks, &message).unwrap();
+
+        let mut partials = vec![];
+        for &i in &[0, 2] {
+            let partial = session.sign(i as PeerIndex, &ltsks[i], &nonce_sks[i]).unwrap();
+            assert!(session.verify_partial(i as PeerIndex, &partial));
+            // The partial signature is bound to the signer.
+            assert!(!session.verify_partial(2 - i as PeerIndex, &partial));
+            partials.push(partial);
+        }
+        assert!(!session.verify_partial(1, &partials[0]));
+
+        let multisig = session.aggregate(&partials);
+        assert!(multisig.verify(&message));
+        assert!(!multisig.verify(&message_digest(&[XorVec::from(vec![1, 2, 4])])));
+        let mut tampered = multisig.clone();
+        tampered.s += Scalar::from_bytes(&[1; 32]).unwrap();
+        assert!(!tampered.verify(&message));
+
+        // A session without the long-term key of a signer cannot be set up.
+        let mut missing = ltvks.clone();
+        missing[2] = None;
+        assert!(SigningSession::new(&nonces, &missing, &message).is_none());
+    }
+
+    #[test]
+    fn nonces_cancel() {
+        let (_, ltvks) = keys(&[1, 2]);
+        // The nonce commitment of peer 1 cancels the one of peer 0.
+        let negated = (-Scalar::from_bytes(&[4; 32]).unwrap()).to_bytes();
+        let (_, mut nonces) = keys(&[4, 4]);
+        let sk = SecretKey::from_slice(&::SECP256K1, &negated).unwrap();
+        nonces[1] = Some(PublicKey::from_secret_key(&::SECP256K1, &sk).unwrap());
+        assert!(SigningSession::new(&nonces, &ltvks, &[0; 32]).is_none());
+    }
+}
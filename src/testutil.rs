@@ -0,0 +1,106 @@
+//! An in-memory broadcast transport for tests
+//!
+//! `broadcast_bus()` connects a number of peers, each with a sink for its outgoing frames and a
+//! stream of incoming frames tagged with the index of the sender. This is what
+//! `WriteSignedPayloads` and `ReadAuthenticatedPayloads` expect from the broadcast mechanism.
+
+use std::io;
+use futures::{Stream, Sink, Poll, Async, StartSend, AsyncSink};
+use futures::sync::mpsc;
+use bytes::Bytes;
+
+use ::PeerIndex;
+
+/// The sending half of a peer on a bus
+pub struct BusSink {
+    peer_index: PeerIndex,
+    peers: Vec<mpsc::UnboundedSender<(PeerIndex, Bytes)>>,
+}
+
+impl Sink for BusSink {
+    type SinkItem = Bytes;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, frame: Bytes) -> StartSend<Bytes, io::Error> {
+        for peer in self.peers.iter() {
+            // A peer that has dropped its stream has left the bus, which does not affect the
+            // other peers.
+            let _ = peer.unbounded_send((self.peer_index, frame.clone()));
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// The receiving half of a peer on a bus
+///
+/// The stream ends once the sinks of all peers have been dropped.
+pub struct BusStream(mpsc::UnboundedReceiver<(PeerIndex, Bytes)>);
+
+impl Stream for BusStream {
+    type Item = (PeerIndex, Bytes);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<(PeerIndex, Bytes)>, io::Error> {
+        // An unbounded receiver never fails.
+        Ok(self.0.poll().expect("receiver failed"))
+    }
+}
+
+/// Creates a bus that connects `num_peers` peers, indexed by peer.
+///
+/// Every frame sent by a peer is delivered to every peer, including the sender itself, as a
+/// broadcast server would echo it. Frames of a single sender arrive in order.
+pub fn broadcast_bus(num_peers: usize) -> Vec<(BusSink, BusStream)> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_peers).map(|_| mpsc::unbounded()).unzip();
+    receivers.into_iter().enumerate().map(|(i, receiver)| {
+        let sink = BusSink {
+            peer_index: i as PeerIndex,
+            peers: senders.clone(),
+        };
+        (sink, BusStream(receiver))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+
+    use super::*;
+
+    #[test]
+    fn delivered_to_all_peers() {
+        let num_peers = 4;
+        let (sinks, streams): (Vec<_>, Vec<_>) = broadcast_bus(num_peers).into_iter().unzip();
+        let mut sinks = sinks.into_iter();
+        let sink = sinks.next().unwrap().send(Bytes::from(&b"hello"[..])).wait().unwrap();
+        let sink = sink.send(Bytes::from(&b"bye"[..])).wait().unwrap();
+        let last = sinks.last().unwrap().send(Bytes::from(&b"last"[..])).wait().unwrap();
+        // Dropping all sinks ends the streams.
+        drop((sink, last));
+
+        for stream in streams {
+            let frames: Vec<_> = stream.collect().wait().unwrap();
+            assert_eq!(frames, vec![
+                (0, Bytes::from(&b"hello"[..])),
+                (0, Bytes::from(&b"bye"[..])),
+                (num_peers as PeerIndex - 1, Bytes::from(&b"last"[..])),
+            ]);
+        }
+    }
+
+    #[test]
+    fn peer_leaves() {
+        let mut bus = broadcast_bus(2);
+        let (_, stream) = bus.pop().unwrap();
+        drop(stream);
+        let (sink, stream) = bus.pop().unwrap();
+
+        // Sending succeeds even though peer 1 has left.
+        drop(sink.send(Bytes::from(&b"frame"[..])).wait().unwrap());
+        assert_eq!(stream.collect().wait().unwrap(), vec![(0, Bytes::from(&b"frame"[..]))]);
+    }
+}
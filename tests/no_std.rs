@@ -0,0 +1,17 @@
+//! Checks that the DC-net arithmetic builds without the standard library.
+
+use std::env;
+use std::process::Command;
+
+#[test]
+fn build_no_default_features() {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    // Use a separate target directory to avoid waiting for the lock of the outer build.
+    let status = Command::new(cargo)
+        .args(&["build", "--lib", "--no-default-features"])
+        .env("CARGO_TARGET_DIR", concat!(env!("CARGO_MANIFEST_DIR"), "/target/no_std"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("cannot run cargo");
+    assert!(status.success(), "build without the std feature failed");
+}
@@ -0,0 +1,70 @@
+//! Golden vectors of the wire encoding of the public types that appear in messages
+//!
+//! The messages themselves are private to the crate, so the vectors of every payload are unit
+//! tests in `src/messages.rs`, which build on the encodings pinned here.
+
+#![cfg(feature = "std")]
+
+extern crate bincode;
+extern crate secp256k1;
+extern crate dicemix;
+
+use secp256k1::key::SecretKey;
+
+use dicemix::{PublicKey, SECP256K1};
+use dicemix::dc::fp::Fp;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn fp_vectors() {
+    // 16 little-endian bytes without a length prefix
+    let vectors = [
+        (0, "00000000000000000000000000000000"),
+        (1, "01000000000000000000000000000000"),
+        (1 << 64, "00000000000000000100000000000000"),
+        (Fp::prime() - 1, "feffffffffffffffffffffffffffff7f"),
+    ];
+    for &(x, expected) in vectors.iter() {
+        let ser = bincode::serialize(&Fp::from_u127(x), bincode::Infinite).unwrap();
+        assert_eq!(hex(&ser), expected);
+        let fp: Fp = bincode::deserialize(&ser).unwrap();
+        assert_eq!(u128::from(fp), x);
+    }
+
+    // Non-canonical encodings, i.e., of integers not smaller than p, are rejected.
+    for &non_canonical in ["ffffffffffffffffffffffffffffff7f", "ffffffffffffffffffffffffffffffff"].iter() {
+        assert!(bincode::deserialize::<Fp>(&unhex(non_canonical)).is_err());
+    }
+    assert!(bincode::deserialize::<Fp>(&unhex("0100000000000000000000000000")).is_err());
+}
+
+#[test]
+fn public_key_vectors() {
+    // A length prefix of 33 followed by the compressed key
+    let vectors = [
+        ([0x01; 32], concat!("2100000000000000",
+                             "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f")),
+        ([0x02; 32], concat!("2100000000000000",
+                             "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766")),
+    ];
+    for &(sk, expected) in vectors.iter() {
+        let sk = SecretKey::from_slice(&SECP256K1, &sk).unwrap();
+        let pk = PublicKey::from_secret_key(&SECP256K1, &sk).unwrap();
+        let ser = bincode::serialize(&pk, bincode::Infinite).unwrap();
+        assert_eq!(hex(&ser), expected);
+        let pk2: PublicKey = bincode::deserialize(&ser).unwrap();
+        assert_eq!(pk2, pk);
+    }
+
+    // A point that is not on the curve is rejected.
+    let invalid = concat!("2100000000000000",
+                          "020000000000000000000000000000000000000000000000000000000000000005");
+    assert!(bincode::deserialize::<PublicKey>(&unhex(invalid)).is_err());
+}